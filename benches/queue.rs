@@ -0,0 +1,193 @@
+// Queue throughput, measured through the HTTP app the way clients hit it.
+// each benchmark prepares its queue once, then times single requests against
+// it; whatever a request consumes is put back untimed before the next one.
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use matchmaker::config::Config;
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const SECRET: &str = "bench-secret";
+// random matching strategies pick the same way on every run
+const SEED: u64 = 82;
+
+struct Bench {
+    rt: Runtime,
+    app: Router,
+    profiles: usize,
+}
+
+impl Bench {
+    fn new() -> Bench {
+        // no rate limits, and only equal ratings match, however long a run
+        // takes. matched inside enqueue, so the timed request does the scan
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            enqueue_rate_limit: u32::MAX,
+            read_rps: 0,
+            write_rps: 0,
+            mmr_range: 0,
+            mmr_range_expand_rate: 0.0,
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        Bench {
+            rt: Runtime::new().unwrap(),
+            app: matchmaker::seeded_router(config, SEED),
+            profiles: 0,
+        }
+    }
+
+    fn send(&self, req: Request<Body>) -> StatusCode {
+        self.rt
+            .block_on(self.app.clone().oneshot(req))
+            .unwrap()
+            .status()
+    }
+
+    fn profile(&mut self, mmr: u32, region: &str) -> Uuid {
+        self.profiles += 1;
+        let name = format!("bench{}", self.profiles);
+        let body = json!({ "name": name, "mmr": mmr, "region": region });
+        let res = self
+            .rt
+            .block_on(
+                self.app
+                    .clone()
+                    .oneshot(post("/v1/profiles", Uuid::nil(), &body)),
+            )
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let bytes = self
+            .rt
+            .block_on(hyper::body::to_bytes(res.into_body()))
+            .unwrap();
+        let profile: Value = serde_json::from_slice(&bytes).unwrap();
+        profile["id"].as_str().unwrap().parse().unwrap()
+    }
+
+    fn enqueue(&self, player: Uuid) -> StatusCode {
+        self.send(enqueue(player))
+    }
+
+    // times the request alone; `prepare` builds it untimed before each call
+    fn timed(
+        &mut self,
+        iters: u64,
+        mut prepare: impl FnMut(&mut Bench) -> Request<Body>,
+        expected: StatusCode,
+    ) -> Duration {
+        let mut total = Duration::ZERO;
+        for _ in 0..iters {
+            let req = prepare(self);
+            let start = Instant::now();
+            let status = self.send(req);
+            total += start.elapsed();
+            assert_eq!(status, expected);
+        }
+        total
+    }
+
+    // a queue of `len` players who match neither each other nor anyone
+    // rated 1000 in the default region
+    fn fill(&mut self, len: usize) {
+        const REGIONS: [&str; 4] = ["NorthAmerica", "Europe", "AsiaPacific", "SouthAmerica"];
+        for i in 0..len {
+            let player = self.profile(1500 + (i / REGIONS.len()) as u32, REGIONS[i % 4]);
+            assert_eq!(self.enqueue(player), StatusCode::ACCEPTED);
+        }
+    }
+}
+
+fn post(path: &str, player: Uuid, body: &Value) -> Request<Body> {
+    let claims = json!({ "sub": player, "exp": chrono::Utc::now().timestamp() + 3600 });
+    let key = jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes());
+    let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(path)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    req.extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+    req
+}
+
+fn enqueue(player: Uuid) -> Request<Body> {
+    post(
+        "/v1/queue/enqueue",
+        player,
+        &json!({ "profile_id": player }),
+    )
+}
+
+// players with an open match cannot queue again, so every pairing uses two
+// fresh profiles
+fn enqueue_and_match(c: &mut Criterion) {
+    let mut bench = Bench::new();
+    c.bench_function("enqueue_matches_the_only_waiting_player", |b| {
+        b.iter_custom(|iters| {
+            let prepare = |bench: &mut Bench| {
+                let waiting = bench.profile(1000, "Other");
+                assert_eq!(bench.enqueue(waiting), StatusCode::ACCEPTED);
+                enqueue(bench.profile(1000, "Other"))
+            };
+            bench.timed(iters, prepare, StatusCode::CREATED)
+        })
+    });
+}
+
+// the only compatible player waits behind 1000 who are not
+fn scan_for_opponent(c: &mut Criterion) {
+    let mut bench = Bench::new();
+    bench.fill(1000);
+    c.bench_function("enqueue_scans_1000_waiting_players", |b| {
+        b.iter_custom(|iters| {
+            let prepare = |bench: &mut Bench| {
+                let waiting = bench.profile(1000, "Other");
+                assert_eq!(bench.enqueue(waiting), StatusCode::ACCEPTED);
+                enqueue(bench.profile(1000, "Other"))
+            };
+            bench.timed(iters, prepare, StatusCode::CREATED)
+        })
+    });
+}
+
+// the leaving player is re-queued at the back each time
+fn leave_long_queue(c: &mut Criterion) {
+    let mut bench = Bench::new();
+    bench.fill(10_000);
+    let player = bench.profile(1000, "Other");
+    c.bench_function("leave_queue_of_10000", |b| {
+        b.iter_custom(|iters| {
+            let prepare = |bench: &mut Bench| {
+                assert_eq!(bench.enqueue(player), StatusCode::ACCEPTED);
+                post("/v1/queue/leave", player, &json!({ "profile_id": player }))
+            };
+            bench.timed(iters, prepare, StatusCode::OK)
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    enqueue_and_match,
+    scan_for_opponent,
+    leave_long_queue
+);
+criterion_main!(benches);
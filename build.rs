@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // sqlx::migrate! embeds the migrations at compile time
+    println!("cargo:rerun-if-changed=migrations");
+    // a bundled protoc, so building needs nothing installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/matchmaker.proto")?;
+    Ok(())
+}
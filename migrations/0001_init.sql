@@ -0,0 +1,18 @@
+-- rows hold the serialized struct so new optional fields don't need a migration
+CREATE TABLE IF NOT EXISTS profiles (
+    id TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS queue_entries (
+    profile_id TEXT PRIMARY KEY NOT NULL,
+    mode TEXT NOT NULL,
+    queued_since TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS matches (
+    id TEXT PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL,
+    data TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- one row per completed reset; the highest number is the current season
+CREATE TABLE IF NOT EXISTS seasons (
+    number INTEGER PRIMARY KEY NOT NULL,
+    started_at TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- members are not listed here: each profile stores its own guild_id
+CREATE TABLE IF NOT EXISTS guilds (
+    id TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- only written when AUDIT_TO_DB is set; never read back
+CREATE TABLE IF NOT EXISTS audit_log (
+    id TEXT PRIMARY KEY NOT NULL,
+    timestamp TEXT NOT NULL,
+    data TEXT NOT NULL
+);
@@ -0,0 +1,102 @@
+// Audit log of every successful POST/PATCH/DELETE under /v1. kept in memory,
+// capped at AUDIT_LOG_MAX entries, and also written to the database when
+// `config.audit_to_db` is set.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{self, Full},
+    extract::{MatchedPath, State},
+    http::{header::CONTENT_TYPE, Method, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{auth, db::DbOp, AppState};
+
+// oldest entries are dropped past this
+pub const AUDIT_LOG_MAX: usize = 10_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    // "admin", the player id from the bearer token, or "anonymous"
+    pub actor: String,
+    // from X-Admin-User on admin requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_user: Option<String>,
+    // method and route, e.g. "POST /v1/profiles/:id/friends"
+    pub action: String,
+    // id of a created resource, else the first id in the path
+    pub resource_id: Option<Uuid>,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub async fn record<B>(State(state): State<Arc<AppState>>, req: Request<B>, next: Next<B>) -> Response {
+    if !matches!(*req.method(), Method::POST | Method::PATCH | Method::DELETE) {
+        return next.run(req).await;
+    }
+    // the admin key wins, as in `auth::require_auth_or_admin`
+    let (actor, admin_user) = match auth::admin_identity(&state, &req) {
+        Some(auth::AdminIdentity(admin)) => ("admin".to_string(), Some(admin)),
+        None => match auth::authenticate(&state, auth::bearer_token(&req)).await {
+            Ok(identity) => (identity.player.0.to_string(), None),
+            Err(_) => ("anonymous".to_string(), None),
+        },
+    };
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| req.uri().path().to_string(), |p| p.as_str().to_string());
+    let action = format!("{} {route}", req.method());
+    let path_id = req.uri().path().split('/').find_map(|s| s.parse::<Uuid>().ok());
+
+    let res = next.run(req).await;
+    if !res.status().is_success() {
+        return res;
+    }
+    let (res, created_id) = created_id(res).await;
+    let entry = AuditEntry {
+        id: Uuid::new_v4(),
+        actor,
+        admin_user,
+        action,
+        resource_id: created_id.or(path_id),
+        timestamp: Utc::now(),
+    };
+
+    let mut log = state.audit_log.lock().await;
+    if log.len() == AUDIT_LOG_MAX {
+        log.pop_front();
+    }
+    log.push_back(entry.clone());
+    drop(log);
+    if state.config.audit_to_db {
+        state.persist(vec![DbOp::InsertAudit(entry)]).await;
+    }
+    res
+}
+
+// the "id" field of a 201 JSON body, if any. the body is buffered to read it
+// and put back unchanged
+async fn created_id(res: Response) -> (Response, Option<Uuid>) {
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if res.status() != axum::http::StatusCode::CREATED || !is_json {
+        return (res, None);
+    }
+    let (parts, body) = res.into_parts();
+    let Ok(bytes) = hyper::body::to_bytes(body).await else {
+        return (Response::from_parts(parts, body::boxed(Full::default())), None);
+    };
+    let id = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|v| v.get("id")?.as_str()?.parse().ok());
+    (Response::from_parts(parts, body::boxed(Full::from(bytes))), id)
+}
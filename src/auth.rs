@@ -0,0 +1,216 @@
+// Bearer token authentication for the write endpoints, and the admin key
+// check for /admin. tokens are HS256 ones signed with JWT_SECRET, or with
+// OAUTH2_JWKS_URL set also those of an external provider, see oauth.rs.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderName, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{error::AppError, AppState};
+
+const ADMIN_KEY_HEADER: HeaderName = HeaderName::from_static("x-admin-key");
+// free-form name of the operator, recorded in admin audit logs
+const ADMIN_USER_HEADER: HeaderName = HeaderName::from_static("x-admin-user");
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: Uuid,
+}
+
+// the player a request was authenticated as, taken from the token's `sub`
+#[derive(Debug, Clone, Copy)]
+pub struct AuthPlayer(pub Uuid);
+
+// the provider's `sub` of a request made with an external token. its
+// `AuthPlayer` is the profile linked to it, or the nil id before there is one
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity(pub String);
+
+// who a valid bearer token speaks for
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub player: AuthPlayer,
+    pub external: Option<ExternalIdentity>,
+}
+
+impl Identity {
+    fn attach<B>(self, req: &mut Request<B>) {
+        req.extensions_mut().insert(self.player);
+        if let Some(external) = self.external {
+            req.extensions_mut().insert(external);
+        }
+    }
+}
+
+// who is calling an admin route, from `X-Admin-User` ("unknown" if absent)
+#[derive(Debug, Clone)]
+pub struct AdminIdentity(pub String);
+
+pub fn decoding_key(secret: &str) -> DecodingKey {
+    DecodingKey::from_secret(secret.as_bytes())
+}
+
+// requires a valid HS256 token on every request that is not a read. the
+// player id is made available to handlers as an `AuthPlayer` extension
+pub async fn require_auth<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next.run(req).await;
+    }
+
+    match authenticate(&state, bearer_token(&req)).await {
+        Ok(identity) => {
+            identity.attach(&mut req);
+            next.run(req).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+// like `require_auth`, but for reads too: for routes that show a player's
+// private data
+pub async fn require_token<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match authenticate(&state, bearer_token(&req)).await {
+        Ok(identity) => {
+            identity.attach(&mut req);
+            next.run(req).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+// for routes open to everyone that act for the caller when signed in, e.g.
+// /graphql: a valid token adds an `AuthPlayer`, an invalid one is rejected
+pub async fn optional_auth<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if req.headers().contains_key(AUTHORIZATION) {
+        match authenticate(&state, bearer_token(&req)).await {
+            Ok(identity) => identity.attach(&mut req),
+            Err(e) => return e.into_response(),
+        }
+    }
+    next.run(req).await
+}
+
+// for routes game servers write to as well as players: a valid admin key
+// adds an `AdminIdentity`, otherwise a token is required as in `require_auth`
+pub async fn require_auth_or_admin<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(admin) = admin_identity(&state, &req) {
+        req.extensions_mut().insert(admin);
+        return next.run(req).await;
+    }
+    match authenticate(&state, bearer_token(&req)).await {
+        Ok(identity) => {
+            identity.attach(&mut req);
+            next.run(req).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+// an owned copy, so the request is not borrowed while the token is checked
+pub fn bearer_token<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(String::from)
+}
+
+pub async fn authenticate(state: &AppState, token: Option<String>) -> Result<Identity, AppError> {
+    verify_token(state, &token.ok_or(AppError::Unauthorized)?).await
+}
+
+// who a bearer token was issued for, if it is valid
+pub async fn verify_token(state: &AppState, token: &str) -> Result<Identity, AppError> {
+    let external = jsonwebtoken::decode_header(token).is_ok_and(|h| h.alg != Algorithm::HS256);
+    if let (true, Some(jwks)) = (external, &state.jwks) {
+        let sub = jwks.verify(token).await?;
+        let player = state.external_ids.get(&sub).map_or(Uuid::nil(), |id| *id);
+        return Ok(Identity {
+            player: AuthPlayer(player),
+            external: Some(ExternalIdentity(sub)),
+        });
+    }
+    match jsonwebtoken::decode::<Claims>(token, &state.jwt_key, &Validation::new(Algorithm::HS256)) {
+        Ok(data) => Ok(Identity {
+            player: AuthPlayer(data.claims.sub),
+            external: None,
+        }),
+        Err(e) => {
+            tracing::debug!("rejected token: {e}");
+            Err(AppError::Unauthorized)
+        }
+    }
+}
+
+// the caller's identity if the request carries the configured admin key
+pub fn admin_identity<B>(state: &AppState, req: &Request<B>) -> Option<AdminIdentity> {
+    let given = req.headers().get(ADMIN_KEY_HEADER).and_then(|v| v.to_str().ok())?;
+    if !keys_match(state.config.admin_key.as_deref()?, given) {
+        return None;
+    }
+    let admin = req
+        .headers()
+        .get(ADMIN_USER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown");
+    Some(AdminIdentity(admin.to_string()))
+}
+
+// compares HMAC-SHA256 tags of both keys under the configured one with
+// `verify_slice`, in constant time like a webhook signature check, so the
+// time taken says nothing about how much of the key was right
+pub(crate) fn keys_match(configured: &str, given: &str) -> bool {
+    let mac = |key: &str| {
+        Hmac::<Sha256>::new_from_slice(configured.as_bytes())
+            .expect("HMAC takes keys of any length")
+            .chain_update(key.as_bytes())
+    };
+    mac(given)
+        .verify_slice(&mac(configured).finalize().into_bytes())
+        .is_ok()
+}
+
+// admin routes need the configured key in `X-Admin-Key`. with no key
+// configured they reject everything
+pub async fn require_admin<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match admin_identity(&state, &req) {
+        Some(admin) => {
+            req.extensions_mut().insert(admin);
+            next.run(req).await
+        }
+        None => {
+            tracing::warn!(path = %req.uri().path(), "rejected admin request");
+            AppError::InvalidAdminKey.into_response()
+        }
+    }
+}
@@ -0,0 +1,348 @@
+// Service configuration: defaults, overridden by an optional TOML file, in
+// turn overridden by environment variables.
+
+use std::{collections::HashMap, path::Path, str::FromStr, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{elo, GameMode};
+
+// how often the background matcher runs by default, and always with a Redis
+// queue when `matching_interval_ms` is 0
+const MATCHING_INTERVAL_MS: u64 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    // the gRPC service listens on the same host
+    pub grpc_port: u16,
+    // max mmr difference allowed between two matched players, widened by
+    // `mmr_range_expand_rate` per second the waiting player has been queued
+    pub mmr_range: u32,
+    pub mmr_range_expand_rate: f64,
+    pub mmr_range_max: u32,
+    pub k_factor: f64,
+    // every rating update is clamped into mmr_floor..=mmr_ceiling
+    pub mmr_floor: u32,
+    pub mmr_ceiling: u32,
+    // queue entries without a heartbeat for `stale_timeout_secs` are dropped,
+    // checked every `stale_check_interval_secs`
+    pub stale_check_interval_secs: u64,
+    pub stale_timeout_secs: u64,
+    pub ready_timeout_secs: u64,
+    // players waiting longer than this are dequeued even with heartbeats
+    pub max_queue_time_secs: u64,
+    pub shutdown_drain_secs: u64,
+    // one state per region instead of a single one, see shards.rs
+    pub shard_by_region: bool,
+    // a queue shared with other instances, see redis_queue.rs; it is always
+    // matched in the background
+    pub redis_url: Option<String>,
+    // enqueue only queues and a background task forms matches every
+    // `matching_interval_ms`; 0 matches inside enqueue instead
+    pub matching_interval_ms: u64,
+    // Discord webhook for match notifications, see discord.rs; unset
+    // disables them
+    pub discord_webhook_url: Option<String>,
+    pub discord_title_template: String,
+    pub discord_description_template: String,
+    // OTLP/HTTP collector traces are exported to, e.g. http://localhost:4318;
+    // unset exports nothing
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    // how long a webhook URL's open circuit waits before a trial delivery
+    pub webhook_half_open_secs: u64,
+    // per-address enqueue budget: `enqueue_rate_limit` requests every
+    // `enqueue_rate_window_secs`
+    pub enqueue_rate_limit: u32,
+    pub enqueue_rate_window_secs: u64,
+    // per-address requests per second over every route, reads (GET) and
+    // writes (POST, PATCH, DELETE) apart; 0 turns a limit off
+    pub read_rps: u32,
+    pub write_rps: u32,
+    // most players a queue may hold; `max_queue_sizes` overrides it per mode
+    pub max_queue_size: usize,
+    pub max_queue_sizes: HashMap<GameMode, usize>,
+    // per mode: "fifo", "random", "closest" or "window:<mmr>", see strategy.rs
+    pub matching_strategies: HashMap<GameMode, String>,
+    // per mode: the champion select steps, e.g. "ban,ban,pick,pick", see
+    // draft.rs. modes without one have no draft
+    pub draft_orders: HashMap<GameMode, String>,
+    // how many of a player's latest opponents they are not matched with again
+    pub recent_opponents_limit: usize,
+    pub max_spectators: usize,
+    // queue ban after each abandoned match, in seconds: the first offence
+    // gets the first entry and so on, later ones the last entry
+    pub queue_ban_secs: Vec<u64>,
+    // Pending or Active matches a player may be part of at the same time
+    pub max_active_matches_per_player: usize,
+    // ranked mmr of players without a completed match for `decay_start_days`
+    // drops by `decay_rate_per_day` per further day, checked every
+    // `decay_interval_secs`
+    pub decay_start_days: u32,
+    pub decay_rate_per_day: f64,
+    pub decay_interval_secs: u64,
+    // players reported by more than this many others within a week are
+    // suspended
+    pub report_suspend_threshold: usize,
+    // lowest ranked mmr a season reset can leave a player with
+    pub season_min_mmr: u32,
+    // also write every audit log entry to the database, if there is one
+    pub audit_to_db: bool,
+    // file written by /admin/snapshot/save and read by /admin/snapshot/restore
+    pub snapshot_path: String,
+    // without a database everything is kept in memory only. `postgres_url`
+    // wins over the SQLite `database_url` when both are set
+    pub database_url: Option<String>,
+    pub postgres_url: Option<String>,
+    // most connections the database pool opens
+    pub db_max_connections: u32,
+    pub jwt_secret: Option<String>,
+    // an external provider's key set: its tokens are accepted too, see
+    // oauth.rs. `aud` and `iss` are only checked when set
+    pub oauth2_jwks_url: Option<String>,
+    pub oauth2_audience: Option<String>,
+    pub oauth2_issuer: Option<String>,
+    // required in X-Admin-Key by /admin routes; unset disables them
+    pub admin_key: Option<String>,
+    // browser origins allowed to call the API, "*" for any; empty allows none
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "0.0.0.0".to_string(),
+            port: 3000,
+            grpc_port: 3001,
+            mmr_range: 150,
+            mmr_range_expand_rate: 5.0,
+            mmr_range_max: 500,
+            k_factor: elo::DEFAULT_K,
+            mmr_floor: elo::DEFAULT_FLOOR,
+            mmr_ceiling: elo::DEFAULT_CEILING,
+            stale_check_interval_secs: 30,
+            shard_by_region: false,
+            redis_url: None,
+            matching_interval_ms: MATCHING_INTERVAL_MS,
+            webhook_half_open_secs: 60,
+            discord_webhook_url: None,
+            otel_exporter_otlp_endpoint: None,
+            discord_title_template: "{event}: {mode}".to_string(),
+            discord_description_template: "{team1} vs {team2}".to_string(),
+            stale_timeout_secs: 60,
+            ready_timeout_secs: 30,
+            max_queue_time_secs: 600,
+            shutdown_drain_secs: 5,
+            enqueue_rate_limit: 10,
+            enqueue_rate_window_secs: 60,
+            read_rps: 100,
+            write_rps: 20,
+            max_queue_size: usize::MAX,
+            max_queue_sizes: HashMap::new(),
+            matching_strategies: HashMap::new(),
+            draft_orders: HashMap::new(),
+            recent_opponents_limit: 5,
+            max_spectators: 10,
+            queue_ban_secs: vec![300, 900, 3600],
+            max_active_matches_per_player: 1,
+            decay_start_days: 14,
+            decay_rate_per_day: 5.0,
+            decay_interval_secs: 86400,
+            report_suspend_threshold: 5,
+            season_min_mmr: 0,
+            audit_to_db: false,
+            snapshot_path: "snapshot.json".to_string(),
+            database_url: None,
+            postgres_url: None,
+            db_max_connections: 10,
+            jwt_secret: None,
+            oauth2_jwks_url: None,
+            oauth2_audience: None,
+            oauth2_issuer: None,
+            admin_key: None,
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // reads `path` if given, then applies the environment on top. panics on
+    // an unreadable file or a malformed value, like the rest of startup
+    pub fn load(path: Option<&Path>) -> Config {
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .unwrap_or_else(|e| panic!("cannot read {}: {e}", path.display()));
+                toml::from_str(&text)
+                    .unwrap_or_else(|e| panic!("invalid config {}: {e}", path.display()))
+            }
+            None => Config::default(),
+        };
+        config.apply_env();
+        config
+    }
+
+    fn apply_env(&mut self) {
+        env("MATCHMAKER_HOST", &mut self.host);
+        // PORT is still honoured for older deployments
+        env("PORT", &mut self.port);
+        env("MATCHMAKER_PORT", &mut self.port);
+        env("GRPC_PORT", &mut self.grpc_port);
+        env("MMR_RANGE", &mut self.mmr_range);
+        env("MMR_RANGE_EXPAND_RATE", &mut self.mmr_range_expand_rate);
+        env("MMR_RANGE_MAX", &mut self.mmr_range_max);
+        env("K_FACTOR", &mut self.k_factor);
+        env("MMR_FLOOR", &mut self.mmr_floor);
+        env("MMR_CEILING", &mut self.mmr_ceiling);
+        env("STALE_CHECK_INTERVAL_SECS", &mut self.stale_check_interval_secs);
+        env("STALE_TIMEOUT_SECS", &mut self.stale_timeout_secs);
+        env("READY_TIMEOUT_SECS", &mut self.ready_timeout_secs);
+        env("MAX_QUEUE_TIME_SECONDS", &mut self.max_queue_time_secs);
+        env("SHUTDOWN_DRAIN_SECS", &mut self.shutdown_drain_secs);
+        env("SHARD_BY_REGION", &mut self.shard_by_region);
+        env("MATCHING_INTERVAL_MS", &mut self.matching_interval_ms);
+        env("WEBHOOK_HALF_OPEN_SECS", &mut self.webhook_half_open_secs);
+        env("DISCORD_TITLE_TEMPLATE", &mut self.discord_title_template);
+        env("DISCORD_DESCRIPTION_TEMPLATE", &mut self.discord_description_template);
+        if let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            self.otel_exporter_otlp_endpoint = Some(endpoint);
+        }
+        if let Ok(url) = std::env::var("DISCORD_WEBHOOK_URL") {
+            self.discord_webhook_url = Some(url);
+        }
+        env("ENQUEUE_RATE_LIMIT", &mut self.enqueue_rate_limit);
+        env("ENQUEUE_RATE_WINDOW_SECS", &mut self.enqueue_rate_window_secs);
+        env("READ_RPS", &mut self.read_rps);
+        env("WRITE_RPS", &mut self.write_rps);
+        env("MAX_QUEUE_SIZE", &mut self.max_queue_size);
+        env("RECENT_OPPONENTS_LIMIT", &mut self.recent_opponents_limit);
+        env("MAX_SPECTATORS", &mut self.max_spectators);
+        env("MAX_ACTIVE_MATCHES_PER_PLAYER", &mut self.max_active_matches_per_player);
+        env("DECAY_START_DAYS", &mut self.decay_start_days);
+        env("DECAY_RATE_PER_DAY", &mut self.decay_rate_per_day);
+        env("DECAY_INTERVAL_SECS", &mut self.decay_interval_secs);
+        env("REPORT_SUSPEND_THRESHOLD", &mut self.report_suspend_threshold);
+        env("SEASON_MIN_MMR", &mut self.season_min_mmr);
+        env("AUDIT_TO_DB", &mut self.audit_to_db);
+        env("SNAPSHOT_PATH", &mut self.snapshot_path);
+        env("DB_MAX_CONNECTIONS", &mut self.db_max_connections);
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            self.database_url = Some(url);
+        }
+        if let Ok(url) = std::env::var("POSTGRES_URL") {
+            self.postgres_url = Some(url);
+        }
+        if let Ok(url) = std::env::var("REDIS_URL") {
+            self.redis_url = Some(url);
+        }
+        if let Ok(secret) = std::env::var("JWT_SECRET") {
+            self.jwt_secret = Some(secret);
+        }
+        if let Ok(url) = std::env::var("OAUTH2_JWKS_URL") {
+            self.oauth2_jwks_url = Some(url);
+        }
+        if let Ok(aud) = std::env::var("OAUTH2_AUDIENCE") {
+            self.oauth2_audience = Some(aud);
+        }
+        if let Ok(iss) = std::env::var("OAUTH2_ISSUER") {
+            self.oauth2_issuer = Some(iss);
+        }
+        if let Ok(key) = std::env::var("ADMIN_KEY") {
+            self.admin_key = Some(key);
+        }
+        if let Ok(origins) = std::env::var("CORS_ORIGINS") {
+            self.cors_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Ok(bans) = std::env::var("QUEUE_BAN_SECS") {
+            self.queue_ban_secs = bans
+                .split(',')
+                .map(str::trim)
+                .filter(|b| !b.is_empty())
+                .map(|b| b.parse().unwrap_or_else(|e| panic!("invalid QUEUE_BAN_SECS={bans}: {e}")))
+                .collect();
+        }
+    }
+
+    pub fn queue_capacity(&self, mode: GameMode) -> usize {
+        self.max_queue_sizes
+            .get(&mode)
+            .copied()
+            .unwrap_or(self.max_queue_size)
+    }
+
+    pub fn stale_check_interval(&self) -> Duration {
+        Duration::from_secs(self.stale_check_interval_secs)
+    }
+
+    pub fn matching_interval(&self) -> Option<Duration> {
+        match self.matching_interval_ms {
+            0 if self.redis_url.is_some() => Some(Duration::from_millis(MATCHING_INTERVAL_MS)),
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn webhook_half_open(&self) -> Duration {
+        Duration::from_secs(self.webhook_half_open_secs)
+    }
+
+    pub fn database(&self) -> Option<&str> {
+        self.postgres_url.as_deref().or(self.database_url.as_deref())
+    }
+
+    pub fn mmr_bounds(&self) -> elo::Bounds {
+        elo::Bounds {
+            floor: self.mmr_floor,
+            ceiling: self.mmr_ceiling,
+        }
+    }
+
+    pub fn stale_timeout(&self) -> Duration {
+        Duration::from_secs(self.stale_timeout_secs)
+    }
+
+    pub fn ready_timeout(&self) -> Duration {
+        Duration::from_secs(self.ready_timeout_secs)
+    }
+
+    pub fn max_queue_time(&self) -> Duration {
+        Duration::from_secs(self.max_queue_time_secs)
+    }
+
+    // ban for a player's `offence`-th abandoned match, counted from 1
+    pub fn queue_ban(&self, offence: u32) -> Duration {
+        let idx = (offence.max(1) as usize - 1).min(self.queue_ban_secs.len().saturating_sub(1));
+        Duration::from_secs(self.queue_ban_secs.get(idx).copied().unwrap_or(0))
+    }
+
+    pub fn shutdown_drain(&self) -> Duration {
+        Duration::from_secs(self.shutdown_drain_secs)
+    }
+
+    pub fn decay_interval(&self) -> Duration {
+        Duration::from_secs(self.decay_interval_secs)
+    }
+
+    pub fn enqueue_rate_window(&self) -> Duration {
+        Duration::from_secs(self.enqueue_rate_window_secs)
+    }
+}
+
+// overrides `target` with the variable `name` when it is set
+fn env<T: FromStr>(name: &str, target: &mut T)
+where
+    T::Err: std::fmt::Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        *target = value
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid {name}={value}: {e}"));
+    }
+}
@@ -0,0 +1,31 @@
+// CORS for browser clients. origins come from `config.cors_origins`; "*"
+// allows any, and none configured means cross-origin requests are refused.
+
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// panics on an origin that is not a valid header value, like the rest of startup
+pub fn layer(origins: &[String]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        let list: Vec<HeaderValue> = origins
+            .iter()
+            .map(|o| {
+                HeaderValue::from_str(o).unwrap_or_else(|e| panic!("invalid CORS origin {o}: {e}"))
+            })
+            .collect();
+        AllowOrigin::list(list)
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers([CONTENT_TYPE, AUTHORIZATION, REQUEST_ID_HEADER])
+        .expose_headers([REQUEST_ID_HEADER])
+}
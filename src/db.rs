@@ -0,0 +1,571 @@
+// Optional SQLite or PostgreSQL persistence, picked by the connection URL.
+// The in-memory maps stay the source of truth while the process runs; every
+// write is mirrored to a `MatchmakerStore` so state can be loaded back on
+// startup. Without a database the store is a `MemoryStore`, which keeps the
+// same writes in maps of its own. Queries go through sqlx's Any driver, so
+// they stick to SQL both databases accept.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions},
+    AnyPool, Row,
+};
+use uuid::Uuid;
+
+use crate::{
+    audit::AuditEntry,
+    lanes::{Lane, ModeQueue},
+    GameMode, Guild, MatchInfo, Profile, QueueEntry, Region, Season,
+};
+
+// a single change to mirror into the database
+pub enum DbOp {
+    UpsertProfile(Profile),
+    UpsertMatch(MatchInfo),
+    UpsertQueueEntry(GameMode, QueueEntry),
+    // keyed by the entry's profile_id (the solo player or party leader)
+    DeleteQueueEntry(Uuid),
+    InsertSeason(Season),
+    UpsertGuild(Guild),
+    DeleteGuild(Uuid),
+    InsertAudit(AuditEntry),
+}
+
+// queue entry as stored, also in snapshots and the event log; the monotonic
+// timestamps are rebuilt on load
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredQueueEntry {
+    profile_id: Uuid,
+    party_id: Option<Uuid>,
+    members: Vec<Uuid>,
+    mmr: u32,
+    region: Region,
+    queued_since: DateTime<Utc>,
+    #[serde(default)]
+    lane: Lane,
+    #[serde(default)]
+    team_size: Option<u8>,
+}
+
+impl From<QueueEntry> for StoredQueueEntry {
+    fn from(e: QueueEntry) -> Self {
+        StoredQueueEntry {
+            profile_id: e.profile_id,
+            party_id: e.party_id,
+            members: e.members,
+            mmr: e.mmr,
+            region: e.region,
+            queued_since: e.queued_since,
+            lane: e.lane,
+            team_size: e.team_size,
+        }
+    }
+}
+
+impl StoredQueueEntry {
+    // entries keep their original join time; heartbeats restart from now
+    pub fn into_entry(self) -> QueueEntry {
+        let waited = (Utc::now() - self.queued_since).to_std().unwrap_or_default();
+        let now = Instant::now();
+        QueueEntry {
+            profile_id: self.profile_id,
+            party_id: self.party_id,
+            members: self.members,
+            mmr: self.mmr,
+            region: self.region,
+            queued_at: now.checked_sub(waited).unwrap_or(now),
+            queued_since: self.queued_since,
+            last_heartbeat: now,
+            lane: self.lane,
+            team_size: self.team_size,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Loaded {
+    pub profiles: HashMap<Uuid, Profile>,
+    pub queues: HashMap<GameMode, ModeQueue>,
+    pub matches: HashMap<Uuid, MatchInfo>,
+    // None until the first reset
+    pub season: Option<Season>,
+    pub guilds: HashMap<Uuid, Guild>,
+}
+
+// where the state is persisted; AppState holds one behind an Arc, shared by
+// the shards
+#[async_trait]
+pub trait MatchmakerStore: Send + Sync {
+    async fn load(&self) -> Result<Loaded, sqlx::Error>;
+    // applies all ops in one transaction
+    async fn apply(&self, ops: Vec<DbOp>) -> Result<(), sqlx::Error>;
+    // fails unless the database can be reached
+    async fn ping(&self) -> Result<(), sqlx::Error>;
+    async fn close(&self);
+
+    // a profile another instance sharing the database may have written
+    async fn find_profile(&self, id: Uuid) -> Result<Option<Profile>, sqlx::Error>;
+
+    // single writes and reads for tools and tests; the app batches its
+    // writes through `apply` and reads everything once with `load`
+    #[allow(dead_code)]
+    async fn insert_profile(&self, p: &Profile) -> Result<(), sqlx::Error> {
+        self.apply(vec![DbOp::UpsertProfile(p.clone())]).await
+    }
+    #[allow(dead_code)]
+    async fn insert_match(&self, m: &MatchInfo) -> Result<(), sqlx::Error> {
+        self.apply(vec![DbOp::UpsertMatch(m.clone())]).await
+    }
+    #[allow(dead_code)]
+    async fn update_match(&self, m: &MatchInfo) -> Result<(), sqlx::Error> {
+        self.apply(vec![DbOp::UpsertMatch(m.clone())]).await
+    }
+    // oldest first
+    #[allow(dead_code)]
+    async fn get_queue_entries(&self, mode: GameMode) -> Result<Vec<QueueEntry>, sqlx::Error>;
+}
+
+// the store of a state without a database
+pub fn memory() -> Arc<dyn MatchmakerStore> {
+    Arc::new(MemoryStore::default())
+}
+
+// `url` is sqlite://... or postgres://...
+pub async fn connect(
+    url: &str,
+    max_connections: u32,
+) -> Result<Arc<dyn MatchmakerStore>, sqlx::Error> {
+    if url.starts_with("postgres:") || url.starts_with("postgresql:") {
+        Ok(Arc::new(
+            PostgresStore::connect(url, max_connections).await?,
+        ))
+    } else {
+        Ok(Arc::new(SqliteStore::connect(url, max_connections).await?))
+    }
+}
+
+pub struct SqliteStore(Db);
+
+impl SqliteStore {
+    // a missing database file is created
+    pub async fn connect(url: &str, max_connections: u32) -> Result<SqliteStore, sqlx::Error> {
+        let url = if url.contains("mode=") {
+            url.to_string()
+        } else {
+            let sep = if url.contains('?') { '&' } else { '?' };
+            format!("{url}{sep}mode=rwc")
+        };
+        Ok(SqliteStore(Db::connect(&url, max_connections).await?))
+    }
+}
+
+pub struct PostgresStore(Db);
+
+impl PostgresStore {
+    pub async fn connect(url: &str, max_connections: u32) -> Result<PostgresStore, sqlx::Error> {
+        Ok(PostgresStore(Db::connect(url, max_connections).await?))
+    }
+}
+
+macro_rules! sql_store {
+    ($store:ty) => {
+        #[async_trait]
+        impl MatchmakerStore for $store {
+            async fn load(&self) -> Result<Loaded, sqlx::Error> {
+                self.0.load().await
+            }
+            async fn apply(&self, ops: Vec<DbOp>) -> Result<(), sqlx::Error> {
+                self.0.apply(ops).await
+            }
+            async fn ping(&self) -> Result<(), sqlx::Error> {
+                self.0.pool.acquire().await.map(|_| ())
+            }
+            async fn close(&self) {
+                self.0.pool.close().await;
+            }
+            async fn find_profile(&self, id: Uuid) -> Result<Option<Profile>, sqlx::Error> {
+                self.0.find_profile(id).await
+            }
+            async fn get_queue_entries(
+                &self,
+                mode: GameMode,
+            ) -> Result<Vec<QueueEntry>, sqlx::Error> {
+                self.0.get_queue_entries(mode).await
+            }
+        }
+    };
+}
+
+sql_store!(SqliteStore);
+sql_store!(PostgresStore);
+
+#[derive(Default)]
+pub struct MemoryStore(Mutex<Memory>);
+
+#[derive(Default)]
+struct Memory {
+    profiles: HashMap<Uuid, Profile>,
+    matches: HashMap<Uuid, MatchInfo>,
+    // in join order
+    queue_entries: Vec<(GameMode, StoredQueueEntry)>,
+    // the latest only, as `load` returns
+    season: Option<Season>,
+    guilds: HashMap<Uuid, Guild>,
+}
+
+#[async_trait]
+impl MatchmakerStore for MemoryStore {
+    async fn load(&self) -> Result<Loaded, sqlx::Error> {
+        let memory = self.0.lock().unwrap();
+        let mut queues: HashMap<GameMode, ModeQueue> = HashMap::new();
+        for (mode, stored) in &memory.queue_entries {
+            queues.entry(*mode).or_default().insert(stored.clone().into_entry());
+        }
+        Ok(Loaded {
+            profiles: memory.profiles.clone(),
+            queues,
+            matches: memory.matches.clone(),
+            season: memory.season.clone(),
+            guilds: memory.guilds.clone(),
+        })
+    }
+
+    async fn apply(&self, ops: Vec<DbOp>) -> Result<(), sqlx::Error> {
+        let mut memory = self.0.lock().unwrap();
+        for op in ops {
+            match op {
+                DbOp::UpsertProfile(p) => {
+                    memory.profiles.insert(p.id, p);
+                }
+                DbOp::UpsertMatch(m) => {
+                    memory.matches.insert(m.id, m);
+                }
+                DbOp::UpsertQueueEntry(mode, e) => {
+                    memory.queue_entries.retain(|(_, q)| q.profile_id != e.profile_id);
+                    memory.queue_entries.push((mode, StoredQueueEntry::from(e)));
+                }
+                DbOp::DeleteQueueEntry(profile_id) => {
+                    memory.queue_entries.retain(|(_, q)| q.profile_id != profile_id);
+                }
+                DbOp::InsertSeason(season) => {
+                    if memory.season.as_ref().is_none_or(|s| s.number <= season.number) {
+                        memory.season = Some(season);
+                    }
+                }
+                DbOp::UpsertGuild(g) => {
+                    memory.guilds.insert(g.id, g);
+                }
+                DbOp::DeleteGuild(id) => {
+                    memory.guilds.remove(&id);
+                }
+                // AppState keeps the audit log, capped, and `load` has no use for it
+                DbOp::InsertAudit(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn close(&self) {}
+
+    async fn find_profile(&self, id: Uuid) -> Result<Option<Profile>, sqlx::Error> {
+        Ok(self.0.lock().unwrap().profiles.get(&id).cloned())
+    }
+
+    async fn get_queue_entries(&self, mode: GameMode) -> Result<Vec<QueueEntry>, sqlx::Error> {
+        let memory = self.0.lock().unwrap();
+        let mut entries: Vec<&StoredQueueEntry> = memory
+            .queue_entries
+            .iter()
+            .filter(|(m, _)| *m == mode)
+            .map(|(_, stored)| stored)
+            .collect();
+        entries.sort_by_key(|stored| stored.queued_since);
+        Ok(entries.into_iter().map(|stored| stored.clone().into_entry()).collect())
+    }
+}
+
+// the queries both stores share
+struct Db {
+    pool: AnyPool,
+}
+
+impl Db {
+    async fn connect(url: &str, max_connections: u32) -> Result<Db, sqlx::Error> {
+        install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(Db { pool })
+    }
+
+    async fn load(&self) -> Result<Loaded, sqlx::Error> {
+        let mut profiles = HashMap::new();
+        for row in sqlx::query("SELECT data FROM profiles").fetch_all(&self.pool).await? {
+            let p: Profile = decode(row.get("data"))?;
+            profiles.insert(p.id, p);
+        }
+
+        let mut matches = HashMap::new();
+        for row in sqlx::query("SELECT data FROM matches").fetch_all(&self.pool).await? {
+            let m: MatchInfo = decode(row.get("data"))?;
+            matches.insert(m.id, m);
+        }
+
+        let mut guilds = HashMap::new();
+        for row in sqlx::query("SELECT data FROM guilds").fetch_all(&self.pool).await? {
+            let g: Guild = decode(row.get("data"))?;
+            guilds.insert(g.id, g);
+        }
+
+        let mut queues: HashMap<GameMode, ModeQueue> = HashMap::new();
+        let rows = sqlx::query("SELECT mode, data FROM queue_entries ORDER BY queued_since")
+            .fetch_all(&self.pool)
+            .await?;
+        for row in rows {
+            let mode: GameMode = decode(row.get("mode"))?;
+            let stored: StoredQueueEntry = decode(row.get("data"))?;
+            queues.entry(mode).or_default().insert(stored.into_entry());
+        }
+
+        // INTEGER is 32 bits in PostgreSQL, which Any will not read as i64
+        let season = sqlx::query(
+            "SELECT CAST(number AS BIGINT) AS number, started_at FROM seasons \
+             ORDER BY number DESC LIMIT 1",
+        )
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| -> Result<Season, sqlx::Error> {
+                let started_at: String = row.get("started_at");
+                Ok(Season {
+                    number: row.get::<i64, _>("number") as u32,
+                    started_at: DateTime::parse_from_rfc3339(&started_at)
+                        .map_err(|e| sqlx::Error::Decode(Box::new(e)))?
+                        .with_timezone(&Utc),
+                })
+            })
+            .transpose()?;
+
+        Ok(Loaded {
+            profiles,
+            queues,
+            matches,
+            season,
+            guilds,
+        })
+    }
+
+    async fn find_profile(&self, id: Uuid) -> Result<Option<Profile>, sqlx::Error> {
+        sqlx::query("SELECT data FROM profiles WHERE id = $1")
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| decode(row.get("data")))
+            .transpose()
+    }
+
+    async fn get_queue_entries(&self, mode: GameMode) -> Result<Vec<QueueEntry>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT data FROM queue_entries WHERE mode = $1 ORDER BY queued_since",
+        )
+        .bind(encode(&mode))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| decode(row.get("data")).map(StoredQueueEntry::into_entry))
+            .collect()
+    }
+
+    async fn apply(&self, ops: Vec<DbOp>) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for op in ops {
+            match op {
+                DbOp::UpsertProfile(p) => {
+                    sqlx::query(
+                        "INSERT INTO profiles (id, data) VALUES ($1, $2) \
+                         ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+                    )
+                        .bind(p.id.to_string())
+                        .bind(encode(&p))
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                DbOp::UpsertMatch(m) => {
+                    sqlx::query(
+                        "INSERT INTO matches (id, created_at, data) VALUES ($1, $2, $3) \
+                         ON CONFLICT (id) DO UPDATE \
+                         SET created_at = excluded.created_at, data = excluded.data",
+                    )
+                    .bind(m.id.to_string())
+                    .bind(m.created_at.to_rfc3339())
+                    .bind(encode(&m))
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                DbOp::UpsertQueueEntry(mode, e) => {
+                    let stored = StoredQueueEntry::from(e);
+                    sqlx::query(
+                        "INSERT INTO queue_entries (profile_id, mode, queued_since, data) \
+                         VALUES ($1, $2, $3, $4) ON CONFLICT (profile_id) DO UPDATE \
+                         SET mode = excluded.mode, queued_since = excluded.queued_since, \
+                         data = excluded.data",
+                    )
+                    .bind(stored.profile_id.to_string())
+                    .bind(encode(&mode))
+                    .bind(stored.queued_since.to_rfc3339())
+                    .bind(encode(&stored))
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                DbOp::DeleteQueueEntry(profile_id) => {
+                    sqlx::query("DELETE FROM queue_entries WHERE profile_id = $1")
+                        .bind(profile_id.to_string())
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                DbOp::InsertSeason(season) => {
+                    sqlx::query(
+                        "INSERT INTO seasons (number, started_at) VALUES ($1, $2) \
+                         ON CONFLICT (number) DO UPDATE SET started_at = excluded.started_at",
+                    )
+                        .bind(season.number as i64)
+                        .bind(season.started_at.to_rfc3339())
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                DbOp::UpsertGuild(g) => {
+                    sqlx::query(
+                        "INSERT INTO guilds (id, data) VALUES ($1, $2) \
+                         ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+                    )
+                        .bind(g.id.to_string())
+                        .bind(encode(&g))
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                DbOp::DeleteGuild(id) => {
+                    sqlx::query("DELETE FROM guilds WHERE id = $1")
+                        .bind(id.to_string())
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                DbOp::InsertAudit(entry) => {
+                    sqlx::query("INSERT INTO audit_log (id, timestamp, data) VALUES ($1, $2, $3)")
+                        .bind(entry.id.to_string())
+                        .bind(entry.timestamp.to_rfc3339())
+                        .bind(encode(&entry))
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+        }
+        tx.commit().await
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(data: String) -> Result<T, sqlx::Error> {
+    serde_json::from_str(&data).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{config::Config, join_queue, new_profile, CreateProfile, QueueRequest};
+
+    // writes through the app, reads back with `load`, with every upsert
+    // applied twice
+    async fn round_trip(db: Arc<dyn MatchmakerStore>) {
+        let config = Config {
+            jwt_secret: Some("db-test".to_string()),
+            ..Config::default()
+        };
+        let state = Arc::new(crate::AppState::new(config, db, Loaded::default()));
+        let name = format!("p{}", Uuid::new_v4().simple());
+        let payload = CreateProfile {
+            name,
+            mmr: 1234,
+            region: Region::Europe,
+        };
+        let id = new_profile(&state, payload).await.unwrap().id;
+        let payload = QueueRequest {
+            profile_id: id,
+            party_id: None,
+            mode: GameMode::RankedSolo,
+            team_size: None,
+        };
+        join_queue(&state, id, payload).await.unwrap();
+        let db = &state.store;
+        let profile = state.profiles.get(&id).unwrap().clone();
+        let season = |number| Season {
+            number,
+            started_at: Utc::now(),
+        };
+        db.apply(vec![
+            DbOp::UpsertProfile(profile),
+            DbOp::InsertSeason(season(7)),
+            DbOp::InsertSeason(season(7)),
+            DbOp::InsertSeason(season(8)),
+        ])
+        .await
+        .unwrap();
+
+        let loaded = db.load().await.unwrap();
+        assert_eq!(loaded.profiles[&id].ranked_mmr, 1234);
+        let queued: Vec<Uuid> = loaded.queues[&GameMode::RankedSolo]
+            .iter()
+            .map(|e| e.profile_id)
+            .collect();
+        assert!(queued.contains(&id));
+        assert_eq!(loaded.season.unwrap().number, 8);
+        assert_eq!(db.find_profile(id).await.unwrap().unwrap().ranked_mmr, 1234);
+        assert!(db.find_profile(Uuid::new_v4()).await.unwrap().is_none());
+        let queued = db.get_queue_entries(GameMode::RankedSolo).await.unwrap();
+        assert!(queued.iter().any(|e| e.profile_id == id));
+        assert!(db.get_queue_entries(GameMode::Arcade).await.unwrap().is_empty());
+
+        db.apply(vec![DbOp::DeleteQueueEntry(id)]).await.unwrap();
+        let loaded = db.load().await.unwrap();
+        assert!(loaded.queues.values().all(|q| q.iter().all(|e| e.profile_id != id)));
+    }
+
+    #[tokio::test]
+    async fn memory_round_trip() {
+        round_trip(memory()).await;
+    }
+
+    #[tokio::test]
+    async fn sqlite_round_trip() {
+        let path = std::env::temp_dir().join(format!("matchmaker-{}.db", Uuid::new_v4()));
+        let url = format!("sqlite://{}", path.display());
+        round_trip(connect(&url, 2).await.unwrap()).await;
+        let _ = std::fs::remove_file(path);
+    }
+
+    // needs a server, so only runs with TEST_POSTGRES_URL set
+    #[tokio::test]
+    async fn postgres_round_trip() {
+        let Ok(url) = std::env::var("TEST_POSTGRES_URL") else {
+            return;
+        };
+        round_trip(connect(&url, 2).await.unwrap()).await;
+    }
+}
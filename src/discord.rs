@@ -0,0 +1,180 @@
+// Built-in Discord notifications: with DISCORD_WEBHOOK_URL set, every match
+// that goes Active and every completed one is posted to that Discord webhook
+// as an embed with both teams' names and ratings, the mode and the match id.
+// sent through the webhook delivery of webhooks.rs, so it has the same
+// retries and circuit breaker. the embed's title and description come from
+// templates, where {event}, {mode}, {match_id}, {team1} and {team2} are
+// replaced.
+
+use serde_json::{json, Value};
+
+use crate::{
+    config::Config,
+    get_mode_mmr,
+    webhooks::{self, Delivery},
+    AppState, MatchInfo, MatchResult,
+};
+
+// Discord's limits for embed titles and descriptions, in characters
+const TITLE_MAX: usize = 256;
+const DESCRIPTION_MAX: usize = 4096;
+
+// embed colours: blurple while playing, green once over
+const STARTED_COLOR: u32 = 0x5865F2;
+const COMPLETED_COLOR: u32 = 0x57F287;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscordEvent {
+    MatchStarted,
+    MatchCompleted,
+}
+
+impl DiscordEvent {
+    fn label(self) -> &'static str {
+        match self {
+            DiscordEvent::MatchStarted => "Match started",
+            DiscordEvent::MatchCompleted => "Match completed",
+        }
+    }
+}
+
+pub struct DiscordWebhookNotifier {
+    url: String,
+    title: String,
+    description: String,
+}
+
+impl DiscordWebhookNotifier {
+    // None without DISCORD_WEBHOOK_URL, which disables the notifier
+    pub fn from_config(config: &Config) -> Option<DiscordWebhookNotifier> {
+        Some(DiscordWebhookNotifier {
+            url: config.discord_webhook_url.clone()?,
+            title: config.discord_title_template.clone(),
+            description: config.discord_description_template.clone(),
+        })
+    }
+
+    fn payload(&self, state: &AppState, event: DiscordEvent, m: &MatchInfo) -> Value {
+        let team1 = team(state, m, &m.team1);
+        let team2 = team(state, m, &m.team2);
+        let render = |template: &str, max: usize| -> String {
+            template
+                .replace("{event}", event.label())
+                .replace("{mode}", &format!("{:?}", m.mode))
+                .replace("{match_id}", &m.id.to_string())
+                .replace("{team1}", &team1)
+                .replace("{team2}", &team2)
+                .chars()
+                .take(max)
+                .collect()
+        };
+
+        let mut fields = vec![
+            json!({ "name": "Team 1", "value": team1, "inline": true }),
+            json!({ "name": "Team 2", "value": team2, "inline": true }),
+            json!({ "name": "Mode", "value": format!("{:?}", m.mode), "inline": true }),
+            json!({ "name": "Match ID", "value": m.id.to_string() }),
+        ];
+        let color = match event {
+            DiscordEvent::MatchStarted => STARTED_COLOR,
+            DiscordEvent::MatchCompleted => {
+                let result = match m.result {
+                    Some(MatchResult::Player1Win) => "Team 1 won",
+                    Some(MatchResult::Player2Win) => "Team 2 won",
+                    Some(MatchResult::Draw) | None => "Draw",
+                };
+                fields.push(json!({ "name": "Result", "value": result }));
+                COMPLETED_COLOR
+            }
+        };
+        json!({
+            "embeds": [{
+                "title": render(&self.title, TITLE_MAX),
+                "description": render(&self.description, DESCRIPTION_MAX),
+                "color": color,
+                "fields": fields,
+                "timestamp": m.ended_at.or(m.started_at).unwrap_or(m.created_at),
+            }]
+        })
+    }
+}
+
+// "alice (1520), bob (1480)": names and ratings on the match's mode
+fn team(state: &AppState, m: &MatchInfo, players: &[uuid::Uuid]) -> String {
+    players
+        .iter()
+        .map(|id| match state.profiles.get(id) {
+            Some(p) => format!("{} ({})", p.name, get_mode_mmr(&p, m.mode)),
+            None => id.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// posts `event` for `m` if the notifier is configured
+pub fn notify(state: &AppState, event: DiscordEvent, m: &MatchInfo) {
+    let Some(discord) = &state.discord else {
+        return;
+    };
+    let body = serde_json::to_vec(&discord.payload(state, event, m)).unwrap();
+    webhooks::send(
+        state,
+        Delivery {
+            target: "discord".to_string(),
+            url: discord.url.clone(),
+            signature: None,
+            body,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        db::{self, Loaded},
+        join_queue, new_profile, CreateProfile, Enqueued, GameMode, QueueRequest, Region,
+    };
+
+    #[tokio::test]
+    async fn embed_lists_players_from_the_templates() {
+        let config = Config {
+            jwt_secret: Some("discord-test".to_string()),
+            discord_webhook_url: Some("http://127.0.0.1:9/discord".to_string()),
+            discord_title_template: "{event} in {mode}".to_string(),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = std::sync::Arc::new(AppState::new(config, db::memory(), Loaded::default()));
+        let mut ids = Vec::new();
+        for (name, mmr) in [("alice", 1520), ("bob", 1480)] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr,
+                region: Region::Europe,
+            };
+            ids.push(new_profile(&state, payload).await.unwrap().id);
+        }
+        let mut matched = None;
+        for &id in &ids {
+            let request = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::RankedSolo,
+                team_size: None,
+            };
+            if let Enqueued::Matched(m) = join_queue(&state, id, request).await.unwrap() {
+                matched = Some(m);
+            }
+        }
+        let m = matched.expect("alice and bob should be matched");
+
+        let discord = state.discord.as_ref().unwrap();
+        let embed = &discord.payload(&state, DiscordEvent::MatchStarted, &m)["embeds"][0];
+        assert_eq!(embed["title"], "Match started in RankedSolo");
+        let teams = embed["description"].as_str().unwrap();
+        assert!(teams.contains("alice (1520)") && teams.contains("bob (1480)"));
+        assert_eq!(embed["fields"][3]["value"], m.id.to_string());
+    }
+}
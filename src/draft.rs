@@ -0,0 +1,399 @@
+// Champion (hero) select before a match is played, for modes with a
+// `[draft_orders]` entry in the config file such as RankedSolo =
+// "ban,ban,pick,pick". the draft opens when the match goes Active after its
+// ready check (or is forced), and the sides take the steps in turn, team1
+// first. a ban can be made by any player of the side whose turn it is; a
+// pick is the acting player's own, once per player. a pick falls to the
+// other side once all of a side's players picked, and is skipped once
+// everyone did, so any order runs to the end whatever the team sizes. a
+// banned or picked champion is gone for both sides. deliberately minimal: no
+// timers, no swaps, any champion name goes.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{auth::AuthPlayer, db::DbOp, error::AppError, AppState, MatchInfo, MatchStatus};
+
+// longest champion name, in characters
+const CHAMPION_NAME_MAX: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DraftPhase {
+    Ban,
+    Pick,
+    // every step taken
+    Done,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct DraftState {
+    // what the current step is
+    pub phase: DraftPhase,
+    // the side whose turn it is, 1 or 2; None once done
+    pub turn: Option<u8>,
+    pub bans: Vec<String>,
+    pub picks: HashMap<Uuid, String>,
+    // the mode's order, and how many of its steps were taken
+    order: Vec<DraftPhase>,
+    step: usize,
+    // players on team1 and team2, and how many of each side picked
+    #[serde(default)]
+    sides: [usize; 2],
+    #[serde(default)]
+    picked: [usize; 2],
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DraftChoice {
+    pub champion: String,
+}
+
+// "ban,pick,..." into its steps
+pub fn parse(spec: &str) -> Result<Vec<DraftPhase>, String> {
+    let order: Vec<DraftPhase> = spec
+        .split(',')
+        .map(|step| match step.trim() {
+            "ban" => Ok(DraftPhase::Ban),
+            "pick" => Ok(DraftPhase::Pick),
+            other => Err(format!("unknown draft step {other:?}")),
+        })
+        .collect::<Result<_, _>>()?;
+    if !order.contains(&DraftPhase::Pick) {
+        return Err("a draft needs at least one pick".to_string());
+    }
+    Ok(order)
+}
+
+impl DraftState {
+    // `sides` is the number of players on team1 and team2
+    pub fn new(order: Vec<DraftPhase>, sides: [usize; 2]) -> DraftState {
+        let mut draft = DraftState {
+            phase: DraftPhase::Done,
+            turn: None,
+            bans: Vec::new(),
+            picks: HashMap::new(),
+            order,
+            step: 0,
+            sides,
+            picked: [0, 0],
+        };
+        draft.advance(0);
+        draft
+    }
+
+    // moves to `step`, or past it to the next pick someone can still make
+    fn advance(&mut self, mut step: usize) {
+        loop {
+            self.step = step;
+            self.phase = self.order.get(step).copied().unwrap_or(DraftPhase::Done);
+            // team1 takes the even steps
+            let side = step % 2;
+            let side = match self.phase {
+                DraftPhase::Done => None,
+                DraftPhase::Ban => Some(side),
+                DraftPhase::Pick => [side, 1 - side]
+                    .into_iter()
+                    .find(|&s| self.picked[s] < self.sides[s]),
+            };
+            self.turn = side.map(|s| s as u8 + 1);
+            if self.phase != DraftPhase::Pick || self.turn.is_some() {
+                return;
+            }
+            step += 1;
+        }
+    }
+
+    // takes the current step for `player` on `side`
+    fn take(
+        &mut self,
+        action: DraftPhase,
+        side: u8,
+        player: Uuid,
+        champion: &str,
+    ) -> Result<(), AppError> {
+        if self.phase == DraftPhase::Done {
+            return Err(AppError::DraftNotOpen);
+        }
+        if action != self.phase {
+            return Err(AppError::WrongDraftPhase);
+        }
+        if Some(side) != self.turn
+            || (action == DraftPhase::Pick && self.picks.contains_key(&player))
+        {
+            return Err(AppError::NotDraftTurn);
+        }
+        let champion = champion.trim();
+        if champion.is_empty() || champion.chars().count() > CHAMPION_NAME_MAX {
+            return Err(AppError::InvalidDraftChoice);
+        }
+        let mut taken = self.bans.iter().chain(self.picks.values());
+        if taken.any(|c| c.eq_ignore_ascii_case(champion)) {
+            return Err(AppError::ChampionUnavailable);
+        }
+        match action {
+            DraftPhase::Ban => self.bans.push(champion.to_string()),
+            _ => {
+                self.picks.insert(player, champion.to_string());
+                self.picked[usize::from(side - 1)] += 1;
+            }
+        }
+        self.advance(self.step + 1);
+        Ok(())
+    }
+}
+
+impl AppState {
+    // starts the mode's draft on a match that just went Active
+    pub(crate) fn open_draft(&self, m: &mut MatchInfo) {
+        if let Some(order) = self.draft_orders.get(&m.mode) {
+            let sides = [m.team1.len(), m.team2.len()];
+            m.draft_state = Some(DraftState::new(order.clone(), sides));
+        }
+    }
+}
+
+async fn take_step(
+    state: &AppState,
+    player: Uuid,
+    id: Uuid,
+    action: DraftPhase,
+    choice: DraftChoice,
+) -> Result<DraftState, AppError> {
+    let mut matches = state.matches.lock().await;
+    let m = matches.get_mut(&id).ok_or(AppError::MatchNotFound)?;
+    let side = if m.team1.contains(&player) {
+        1
+    } else if m.team2.contains(&player) {
+        2
+    } else {
+        return Err(AppError::NotMatchParticipant);
+    };
+    let draft = match &mut m.draft_state {
+        Some(draft) if m.status == MatchStatus::Active => draft,
+        _ => return Err(AppError::DraftNotOpen),
+    };
+    draft.take(action, side, player, &choice.champion)?;
+    let draft = draft.clone();
+    tracing::info!(match_id = %id, %player, ?action, champion = %choice.champion.trim(), "draft step");
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    drop(matches);
+    state.flush().await;
+    Ok(draft)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/draft/ban",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body = DraftChoice,
+    responses(
+        (status = 200, description = "Champion banned, the draft moved on", body = DraftState),
+        (status = 400, description = "Empty or too long champion name", body = ApiError),
+        (status = 403, description = "Not a player of the match, or not their side's turn", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "No open draft, not a ban step, or champion already taken", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+pub async fn ban(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+    Json(choice): Json<DraftChoice>,
+) -> Result<impl IntoResponse, AppError> {
+    let draft = take_step(&state, player, id, DraftPhase::Ban, choice).await?;
+    Ok((StatusCode::OK, Json(draft)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/draft/pick",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body = DraftChoice,
+    responses(
+        (status = 200, description = "Champion picked for the caller, the draft moved on", body = DraftState),
+        (status = 400, description = "Empty or too long champion name", body = ApiError),
+        (status = 403, description = "Not a player of the match, not their side's turn, or already picked", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "No open draft, not a pick step, or champion already taken", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+pub async fn pick(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+    Json(choice): Json<DraftChoice>,
+) -> Result<impl IntoResponse, AppError> {
+    let draft = take_step(&state, player, id, DraftPhase::Pick, choice).await?;
+    Ok((StatusCode::OK, Json(draft)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        auth::AdminIdentity,
+        config::Config,
+        db::{self, Loaded},
+        force_match, join_queue, new_profile, ready_match, CreateProfile, Enqueued, ForceMatch,
+        GameMode, QueueRequest, Region,
+    };
+
+    async fn step(
+        state: &AppState,
+        player: Uuid,
+        id: Uuid,
+        action: DraftPhase,
+        champion: &str,
+    ) -> Result<DraftState, AppError> {
+        let choice = DraftChoice {
+            champion: champion.to_string(),
+        };
+        take_step(state, player, id, action, choice).await
+    }
+
+    #[tokio::test]
+    async fn draft_opens_after_the_ready_check_and_alternates() {
+        let config = Config {
+            jwt_secret: Some("draft".to_string()),
+            draft_orders: HashMap::from([(GameMode::CasualSolo, "ban,ban,pick,pick".to_string())]),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), Loaded::default()));
+        let mut join = Vec::new();
+        for name in ["alice", "bob"] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr: 1000,
+                region: Region::Europe,
+            };
+            let id = new_profile(&state, payload).await.unwrap().id;
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::CasualSolo,
+                team_size: None,
+            };
+            join.push((id, join_queue(&state, id, payload).await.unwrap()));
+        }
+        let [(alice, _), (bob, Enqueued::Matched(m))] = &join[..] else {
+            panic!("alice and bob should be matched");
+        };
+        let (alice, bob) = (*alice, *bob);
+        // team1 is the player who waited
+        assert_eq!(m.team1, [alice]);
+
+        let early = step(&state, alice, m.id, DraftPhase::Ban, "Ahri").await;
+        assert!(matches!(early, Err(AppError::DraftNotOpen)));
+        for profile_id in [alice, bob] {
+            ready_match(
+                State(state.clone()),
+                Extension(AuthPlayer(profile_id)),
+                Path(m.id),
+            )
+            .await
+            .unwrap();
+        }
+
+        let out_of_turn = step(&state, bob, m.id, DraftPhase::Ban, "Ahri").await;
+        assert!(matches!(out_of_turn, Err(AppError::NotDraftTurn)));
+        let too_soon = step(&state, alice, m.id, DraftPhase::Pick, "Ahri").await;
+        assert!(matches!(too_soon, Err(AppError::WrongDraftPhase)));
+        step(&state, alice, m.id, DraftPhase::Ban, "Ahri")
+            .await
+            .unwrap();
+        let banned = step(&state, bob, m.id, DraftPhase::Ban, "ahri").await;
+        assert!(matches!(banned, Err(AppError::ChampionUnavailable)));
+        step(&state, bob, m.id, DraftPhase::Ban, "Zed")
+            .await
+            .unwrap();
+        step(&state, alice, m.id, DraftPhase::Pick, "Lux")
+            .await
+            .unwrap();
+        let draft = step(&state, bob, m.id, DraftPhase::Pick, "Jinx")
+            .await
+            .unwrap();
+
+        assert_eq!(draft.phase, DraftPhase::Done);
+        assert_eq!(draft.turn, None);
+        assert_eq!(draft.bans, ["Ahri", "Zed"]);
+        assert_eq!(draft.picks[&alice], "Lux");
+        assert_eq!(draft.picks[&bob], "Jinx");
+        let done = step(&state, alice, m.id, DraftPhase::Pick, "Ezreal").await;
+        assert!(matches!(done, Err(AppError::DraftNotOpen)));
+
+        assert!(parse("ban,swap").is_err());
+        assert!(parse("ban,ban").is_err());
+    }
+
+    #[tokio::test]
+    async fn forced_1v1_draft_runs_a_long_order_to_the_end() {
+        let config = Config {
+            jwt_secret: Some("draft".to_string()),
+            draft_orders: HashMap::from([(
+                GameMode::CasualSolo,
+                "ban,ban,pick,pick,ban,pick".to_string(),
+            )]),
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), Loaded::default()));
+        let mut ids = Vec::new();
+        for name in ["alice", "bob"] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr: 1000,
+                region: Region::Europe,
+            };
+            ids.push(new_profile(&state, payload).await.unwrap().id);
+        }
+        let (alice, bob) = (ids[0], ids[1]);
+        let payload = ForceMatch {
+            player1: alice,
+            player2: bob,
+            mode: GameMode::CasualSolo,
+        };
+        let admin = Extension(AdminIdentity("admin".to_string()));
+        force_match(State(state.clone()), admin, Json(payload))
+            .await
+            .unwrap();
+        let id = *state.matches.lock().await.keys().next().unwrap();
+
+        step(&state, alice, id, DraftPhase::Ban, "Ahri")
+            .await
+            .unwrap();
+        step(&state, bob, id, DraftPhase::Ban, "Zed").await.unwrap();
+        step(&state, alice, id, DraftPhase::Pick, "Lux")
+            .await
+            .unwrap();
+        step(&state, bob, id, DraftPhase::Pick, "Jinx")
+            .await
+            .unwrap();
+        // both players picked, so the last pick has nobody left to make it
+        let draft = step(&state, alice, id, DraftPhase::Ban, "Ezreal")
+            .await
+            .unwrap();
+
+        assert_eq!(draft.phase, DraftPhase::Done);
+        assert_eq!(draft.turn, None);
+        assert_eq!(draft.bans, ["Ahri", "Zed", "Ezreal"]);
+        assert_eq!(draft.picks.len(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+// Elo rating calculation used after a match result is reported.
+
+// K-factor applied to every rating update.
+pub const DEFAULT_K: f64 = 32.0;
+
+// players with fewer completed games than this are provisional and move
+// with PROVISIONAL_K instead, so a new account finds its level quickly
+pub const PROVISIONAL_GAMES: u32 = 10;
+pub const PROVISIONAL_K: f64 = 64.0;
+
+// a player who has lost COMEBACK_STREAK games in a row loses only
+// COMEBACK_MULTIPLIER of the usual rating on the next loss
+pub const COMEBACK_STREAK: u32 = 3;
+pub const COMEBACK_MULTIPLIER: f64 = 0.5;
+
+pub const DEFAULT_FLOOR: u32 = 100;
+pub const DEFAULT_CEILING: u32 = 5000;
+
+// range every updated rating is clamped into
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub floor: u32,
+    pub ceiling: u32,
+}
+
+impl Bounds {
+    fn clamp(self, mmr: f64) -> f64 {
+        mmr.clamp(self.floor as f64, self.ceiling as f64)
+    }
+}
+
+// expected score of a player rated `a` against a player rated `b`
+fn expected_score(a: f64, b: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((b - a) / 400.0))
+}
+
+// `score_a` is 1.0 for a win of `a`, 0.5 for a draw and 0.0 for a loss
+fn rate(a: f64, b: f64, score_a: f64, k: f64) -> (f64, f64) {
+    let expected_a = expected_score(a, b);
+    let new_a = a + k * (score_a - expected_a);
+    let new_b = b + k * ((1.0 - score_a) - (1.0 - expected_a));
+    (new_a, new_b)
+}
+
+// new `(winner, loser)` ratings after a decisive game, within `bounds`
+pub fn update_elo(winner_mmr: f64, loser_mmr: f64, k: f64, bounds: Bounds) -> (f64, f64) {
+    let (winner, loser) = rate(winner_mmr, loser_mmr, 1.0, k);
+    (bounds.clamp(winner), bounds.clamp(loser))
+}
+
+// new ratings of both players after a draw, within `bounds`
+pub fn update_elo_draw(a_mmr: f64, b_mmr: f64, k: f64, bounds: Bounds) -> (f64, f64) {
+    let (a, b) = rate(a_mmr, b_mmr, 0.5, k);
+    (bounds.clamp(a), bounds.clamp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: Bounds = Bounds {
+        floor: DEFAULT_FLOOR,
+        ceiling: DEFAULT_CEILING,
+    };
+
+    #[test]
+    fn loser_stops_at_floor() {
+        // unclamped the loser would drop to 100 - 32 * 0.5 = 84
+        let (_, loser) = update_elo(100.0, 100.0, DEFAULT_K, BOUNDS);
+        assert_eq!(loser, 100.0);
+    }
+
+    #[test]
+    fn winner_stops_at_ceiling() {
+        let (winner, _) = update_elo(4990.0, 4990.0, PROVISIONAL_K, BOUNDS);
+        assert_eq!(winner, 5000.0);
+    }
+
+    #[test]
+    fn draw_is_clamped_on_both_sides() {
+        // the weak side gains and the strong side loses, neither enough to get back in range
+        let (low, high) = update_elo_draw(50.0, 5200.0, DEFAULT_K, BOUNDS);
+        assert_eq!((low, high), (100.0, 5000.0));
+    }
+
+    #[test]
+    fn rating_out_of_bounds_is_pulled_back() {
+        let (winner, loser) = update_elo(6000.0, 20.0, DEFAULT_K, BOUNDS);
+        assert_eq!((winner, loser), (5000.0, 100.0));
+    }
+
+    #[test]
+    fn ratings_inside_bounds_are_untouched() {
+        let (winner, loser) = update_elo(1000.0, 1000.0, DEFAULT_K, BOUNDS);
+        assert_eq!((winner, loser), (1016.0, 984.0));
+    }
+}
@@ -0,0 +1,375 @@
+// Error responses shared by every handler.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::MatchStatus;
+
+// wire format of an error: `{ "code": "...", "message": "..." }`, plus
+// `banned_until` for QUEUE_BAN
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    banned_until: Option<DateTime<Utc>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            banned_until: None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+// every error a handler can return. "not found" variants are for ids in the
+// path (404); "unknown" ones are for ids referenced in the request body (400)
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("Profile not found")]
+    ProfileNotFound,
+    #[error("Profile does not exist")]
+    UnknownProfile,
+    #[error("Profile is deactivated")]
+    ProfileDeactivated,
+    #[error("Nothing to update")]
+    EmptyUpdate,
+    #[error("Party not found")]
+    PartyNotFound,
+    #[error("Duplicate party members")]
+    DuplicatePartyMembers,
+    #[error("A party needs at least two members")]
+    PartyTooSmall,
+    #[error("Member already in a party")]
+    AlreadyInParty,
+    #[error("Only the party leader can queue or disband the party")]
+    NotPartyLeader,
+    #[error("Only a member can create the party")]
+    NotPartyMember,
+    #[error("Already queued for another mode")]
+    QueuedForAnotherMode,
+    #[error("Not in queue")]
+    NotInQueue,
+    #[error("Not in queue")]
+    NotQueued,
+    #[error("Unknown cursor")]
+    UnknownCursor,
+    #[error("Match not found")]
+    MatchNotFound,
+    #[error("Result already recorded")]
+    ResultAlreadyRecorded,
+    #[error("Cannot {action} match: match is {status:?}")]
+    InvalidTransition {
+        action: &'static str,
+        status: MatchStatus,
+    },
+    #[error("Only the match captains can ready up")]
+    NotMatchCaptain,
+    #[error("Match is waiting for its ready check")]
+    ReadyCheckPending,
+    #[error("Missing or invalid bearer token")]
+    Unauthorized,
+    #[error("Token does not belong to this profile")]
+    ProfileMismatch,
+    #[error("Only the admin key can change mmr")]
+    MmrChangeForbidden,
+    #[error("Too many requests")]
+    RateLimited,
+    #[error("Webhook not found")]
+    WebhookNotFound,
+    #[error("Webhook needs an http(s) url and at least one event")]
+    InvalidWebhook,
+    #[error("Missing or invalid admin key")]
+    InvalidAdminKey,
+    #[error("A player cannot be matched against themselves")]
+    SamePlayer,
+    #[error("Queue is at capacity")]
+    QueueFull,
+    #[error("Players cannot spectate their own match")]
+    SpectatorIsPlayer,
+    #[error("No spectator slots left")]
+    SpectatorsFull,
+    #[error("Not spectating this match")]
+    NotSpectating,
+    #[error("Search query must be at least 2 characters")]
+    QueryTooShort,
+    #[error("Name is already taken")]
+    NameTaken,
+    #[error("This external account already has a profile")]
+    ExternalIdTaken,
+    #[error("Metadata keys must be 1 to 64 characters and values at most 512")]
+    InvalidMetadata,
+    #[error("Metadata key not found")]
+    MetadataKeyNotFound,
+    #[error("Not a player of this match")]
+    NotMatchParticipant,
+    #[error("Tournament not found")]
+    TournamentNotFound,
+    #[error("A tournament needs at least two distinct participants")]
+    InvalidTournament,
+    #[error("The current round still has matches to play")]
+    RoundNotFinished,
+    #[error("Tournament is already finished")]
+    TournamentFinished,
+    #[error("decay_fraction must be between 0 and 1")]
+    InvalidSeasonReset,
+    #[error("Player is already in as many open matches as allowed")]
+    ActiveMatchLimit,
+    #[error("Ratings must be between 1 and 5 and the comment at most 1000 characters")]
+    InvalidFeedback,
+    #[error("Feedback can only be given on a completed match")]
+    MatchNotCompleted,
+    #[error("Feedback already submitted for this match")]
+    FeedbackAlreadySubmitted,
+    #[error("Players cannot report themselves, and a referenced match must include both players")]
+    InvalidReport,
+    #[error("Players cannot befriend themselves")]
+    SelfFriendRequest,
+    #[error("Already friends")]
+    AlreadyFriends,
+    #[error("Neither friends nor a pending friend request")]
+    FriendNotFound,
+    #[error("Players cannot block themselves")]
+    SelfBlock,
+    #[error("Player is not blocked")]
+    NotBlocked,
+    #[error("One of the players has blocked the other")]
+    PlayerBlocked,
+    #[error("Guild not found")]
+    GuildNotFound,
+    #[error("Tenant not found")]
+    TenantNotFound,
+    #[error("Missing or invalid tenant key")]
+    InvalidTenantKey,
+    #[error("Tenant name must be 1 to 64 characters")]
+    InvalidTenantName,
+    #[error("Guild names must be 1 to 32 characters")]
+    InvalidGuildName,
+    #[error("Guild name is already taken")]
+    GuildNameTaken,
+    #[error("Player is already in a guild")]
+    AlreadyInGuild,
+    #[error("Only the guild owner can do this")]
+    NotGuildOwner,
+    #[error("Player is not a member of this guild")]
+    NotGuildMember,
+    #[error("The guild owner cannot leave while other members remain")]
+    GuildOwnerLeaving,
+    #[error("Service is under maintenance")]
+    Maintenance,
+    #[error("Snapshot failed: {0}")]
+    SnapshotFailed(String),
+    #[error("since must be before until")]
+    InvalidTimeRange,
+    #[error("naming must be snake_case or camelCase")]
+    InvalidNaming,
+    #[error("team_size must be 1, 2 or 5, and at least the party's size")]
+    InvalidTeamSize,
+    #[error("Match has no open draft")]
+    DraftNotOpen,
+    #[error("The draft is not at a step of this kind")]
+    WrongDraftPhase,
+    #[error("Not this player's turn in the draft")]
+    NotDraftTurn,
+    #[error("Champion name must be 1 to 64 characters")]
+    InvalidDraftChoice,
+    #[error("Champion already banned or picked")]
+    ChampionUnavailable,
+    #[error("Server address must be ip:port, e.g. 192.168.1.100:7777")]
+    InvalidServerAddress,
+    #[error("Banned from queueing after abandoning a match")]
+    QueueBan { until: DateTime<Utc> },
+}
+
+impl AppError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            AppError::ProfileNotFound
+            | AppError::PartyNotFound
+            | AppError::NotInQueue
+            | AppError::MatchNotFound
+            | AppError::WebhookNotFound
+            | AppError::NotSpectating
+            | AppError::MetadataKeyNotFound
+            | AppError::TournamentNotFound
+            | AppError::FriendNotFound
+            | AppError::NotBlocked
+            | AppError::GuildNotFound
+            | AppError::TenantNotFound
+            | AppError::NotGuildMember => StatusCode::NOT_FOUND,
+            AppError::ProfileDeactivated => StatusCode::GONE,
+            AppError::UnknownProfile
+            | AppError::EmptyUpdate
+            | AppError::DuplicatePartyMembers
+            | AppError::PartyTooSmall
+            | AppError::NotQueued
+            | AppError::UnknownCursor
+            | AppError::InvalidWebhook
+            | AppError::SamePlayer
+            | AppError::SpectatorIsPlayer
+            | AppError::QueryTooShort
+            | AppError::InvalidMetadata
+            | AppError::InvalidTournament
+            | AppError::InvalidSeasonReset
+            | AppError::InvalidFeedback
+            | AppError::InvalidReport
+            | AppError::SelfFriendRequest
+            | AppError::SelfBlock
+            | AppError::InvalidGuildName
+            | AppError::InvalidTimeRange
+            | AppError::InvalidNaming
+            | AppError::InvalidTeamSize
+            | AppError::InvalidDraftChoice
+            | AppError::InvalidServerAddress
+            | AppError::InvalidTenantName => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized | AppError::InvalidAdminKey | AppError::InvalidTenantKey => {
+                StatusCode::UNAUTHORIZED
+            }
+            AppError::NotPartyLeader
+            | AppError::NotPartyMember
+            | AppError::NotMatchCaptain
+            | AppError::ProfileMismatch
+            | AppError::MmrChangeForbidden
+            | AppError::NotMatchParticipant
+            | AppError::NotDraftTurn
+            | AppError::PlayerBlocked
+            | AppError::NotGuildOwner
+            | AppError::QueueBan { .. } => StatusCode::FORBIDDEN,
+            AppError::AlreadyInParty
+            | AppError::QueuedForAnotherMode
+            | AppError::ResultAlreadyRecorded
+            | AppError::InvalidTransition { .. }
+            | AppError::ReadyCheckPending
+            | AppError::SpectatorsFull
+            | AppError::NameTaken
+            | AppError::ExternalIdTaken
+            | AppError::RoundNotFinished
+            | AppError::TournamentFinished
+            | AppError::ActiveMatchLimit
+            | AppError::MatchNotCompleted
+            | AppError::FeedbackAlreadySubmitted
+            | AppError::DraftNotOpen
+            | AppError::WrongDraftPhase
+            | AppError::ChampionUnavailable
+            | AppError::AlreadyFriends
+            | AppError::GuildNameTaken
+            | AppError::AlreadyInGuild
+            | AppError::GuildOwnerLeaving => StatusCode::CONFLICT,
+            AppError::RateLimited | AppError::QueueFull => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::SnapshotFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            AppError::ProfileNotFound | AppError::UnknownProfile => "PROFILE_NOT_FOUND",
+            AppError::ProfileDeactivated => "PROFILE_DEACTIVATED",
+            AppError::EmptyUpdate => "EMPTY_UPDATE",
+            AppError::PartyNotFound => "PARTY_NOT_FOUND",
+            AppError::DuplicatePartyMembers => "DUPLICATE_PARTY_MEMBERS",
+            AppError::PartyTooSmall => "PARTY_TOO_SMALL",
+            AppError::AlreadyInParty => "ALREADY_IN_PARTY",
+            AppError::NotPartyLeader => "NOT_PARTY_LEADER",
+            AppError::NotPartyMember => "NOT_PARTY_MEMBER",
+            AppError::QueuedForAnotherMode => "QUEUED_FOR_ANOTHER_MODE",
+            AppError::NotInQueue | AppError::NotQueued => "NOT_IN_QUEUE",
+            AppError::UnknownCursor => "UNKNOWN_CURSOR",
+            AppError::MatchNotFound => "MATCH_NOT_FOUND",
+            AppError::ResultAlreadyRecorded => "RESULT_ALREADY_RECORDED",
+            AppError::InvalidTransition { .. } => "INVALID_TRANSITION",
+            AppError::NotMatchCaptain => "NOT_MATCH_CAPTAIN",
+            AppError::ReadyCheckPending => "READY_CHECK_PENDING",
+            AppError::Unauthorized => "UNAUTHORIZED",
+            AppError::ProfileMismatch => "PROFILE_MISMATCH",
+            AppError::MmrChangeForbidden => "MMR_CHANGE_FORBIDDEN",
+            AppError::RateLimited => "RATE_LIMITED",
+            AppError::WebhookNotFound => "WEBHOOK_NOT_FOUND",
+            AppError::InvalidWebhook => "INVALID_WEBHOOK",
+            AppError::InvalidAdminKey => "INVALID_ADMIN_KEY",
+            AppError::SamePlayer => "SAME_PLAYER",
+            AppError::QueueFull => "QUEUE_FULL",
+            AppError::SpectatorIsPlayer => "SPECTATOR_IS_PLAYER",
+            AppError::SpectatorsFull => "SPECTATORS_FULL",
+            AppError::NotSpectating => "NOT_SPECTATING",
+            AppError::QueryTooShort => "QUERY_TOO_SHORT",
+            AppError::NameTaken => "NAME_TAKEN",
+            AppError::ExternalIdTaken => "EXTERNAL_ID_TAKEN",
+            AppError::InvalidMetadata => "INVALID_METADATA",
+            AppError::MetadataKeyNotFound => "METADATA_KEY_NOT_FOUND",
+            AppError::NotMatchParticipant => "NOT_MATCH_PARTICIPANT",
+            AppError::TournamentNotFound => "TOURNAMENT_NOT_FOUND",
+            AppError::InvalidTournament => "INVALID_TOURNAMENT",
+            AppError::RoundNotFinished => "ROUND_NOT_FINISHED",
+            AppError::TournamentFinished => "TOURNAMENT_FINISHED",
+            AppError::InvalidSeasonReset => "INVALID_SEASON_RESET",
+            AppError::ActiveMatchLimit => "ACTIVE_MATCH_LIMIT",
+            AppError::QueueBan { .. } => "QUEUE_BAN",
+            AppError::InvalidFeedback => "INVALID_FEEDBACK",
+            AppError::InvalidReport => "INVALID_REPORT",
+            AppError::SelfFriendRequest => "SELF_FRIEND_REQUEST",
+            AppError::AlreadyFriends => "ALREADY_FRIENDS",
+            AppError::FriendNotFound => "FRIEND_NOT_FOUND",
+            AppError::SelfBlock => "SELF_BLOCK",
+            AppError::NotBlocked => "NOT_BLOCKED",
+            AppError::PlayerBlocked => "PLAYER_BLOCKED",
+            AppError::GuildNotFound => "GUILD_NOT_FOUND",
+            AppError::TenantNotFound => "TENANT_NOT_FOUND",
+            AppError::InvalidTenantKey => "INVALID_TENANT_KEY",
+            AppError::InvalidTenantName => "INVALID_TENANT_NAME",
+            AppError::InvalidGuildName => "INVALID_GUILD_NAME",
+            AppError::GuildNameTaken => "GUILD_NAME_TAKEN",
+            AppError::AlreadyInGuild => "ALREADY_IN_GUILD",
+            AppError::NotGuildOwner => "NOT_GUILD_OWNER",
+            AppError::NotGuildMember => "NOT_GUILD_MEMBER",
+            AppError::GuildOwnerLeaving => "GUILD_OWNER_LEAVING",
+            AppError::Maintenance => "MAINTENANCE",
+            AppError::SnapshotFailed(_) => "SNAPSHOT_FAILED",
+            AppError::InvalidTimeRange => "INVALID_TIME_RANGE",
+            AppError::InvalidNaming => "INVALID_NAMING",
+            AppError::InvalidTeamSize => "INVALID_TEAM_SIZE",
+            AppError::DraftNotOpen => "DRAFT_NOT_OPEN",
+            AppError::WrongDraftPhase => "WRONG_DRAFT_PHASE",
+            AppError::NotDraftTurn => "NOT_DRAFT_TURN",
+            AppError::InvalidDraftChoice => "INVALID_DRAFT_CHOICE",
+            AppError::ChampionUnavailable => "CHAMPION_UNAVAILABLE",
+            AppError::InvalidServerAddress => "INVALID_SERVER_ADDRESS",
+            AppError::MatchNotCompleted => "MATCH_NOT_COMPLETED",
+            AppError::FeedbackAlreadySubmitted => "FEEDBACK_ALREADY_SUBMITTED",
+        }
+    }
+}
+
+impl From<AppError> for ApiError {
+    fn from(err: AppError) -> Self {
+        let mut api = ApiError::new(err.status(), err.code(), err.to_string());
+        if let AppError::QueueBan { until } = err {
+            api.banned_until = Some(until);
+        }
+        api
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
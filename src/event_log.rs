@@ -0,0 +1,322 @@
+// Append-only log of every durable state change. each batch handed to
+// `AppState::stage`, under the locks of the state it changes, is recorded
+// here as events in the order of the changes, whether or not a database is
+// configured, so the log covers exactly what a database would store:
+// profiles, queue entries, matches, seasons and guilds. parties, lobbies and
+// the other in-memory bookkeeping are not logged. kept in memory and capped
+// at EVENT_LOG_MAX entries; `replay` rebuilds the stored state from a log
+// that still starts at the first event.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    db::{DbOp, Loaded, StoredQueueEntry},
+    GameMode, Guild, MatchInfo, MatchStatus, Profile, Season,
+};
+
+// oldest entries are dropped past this
+pub const EVENT_LOG_MAX: usize = 100_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ProfileCreated {
+        profile: Profile,
+    },
+    // rating changes, bans, renames and every other edit
+    ProfileUpdated {
+        profile: Profile,
+    },
+    PlayerEnqueued {
+        mode: GameMode,
+        #[schema(value_type = Object)]
+        entry: StoredQueueEntry,
+    },
+    // the entry's profile_id: the solo player or the party leader
+    PlayerDequeued {
+        profile_id: Uuid,
+    },
+    MatchCreated {
+        r#match: MatchInfo,
+    },
+    MatchStarted {
+        r#match: MatchInfo,
+    },
+    MatchResultRecorded {
+        r#match: MatchInfo,
+    },
+    MatchCancelled {
+        r#match: MatchInfo,
+    },
+    // ready checks, spectators, metadata, feedback
+    MatchUpdated {
+        r#match: MatchInfo,
+    },
+    SeasonStarted {
+        season: Season,
+    },
+    GuildSaved {
+        guild: Guild,
+    },
+    GuildDeleted {
+        guild_id: Uuid,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct LoggedEvent {
+    // the page cursor
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub event: Event,
+}
+
+pub struct EventLog {
+    entries: VecDeque<LoggedEvent>,
+    // what has been seen so far, to tell creations and transitions apart
+    profiles: HashSet<Uuid>,
+    matches: HashMap<Uuid, MatchStatus>,
+}
+
+impl EventLog {
+    // state loaded at startup is taken as already created
+    pub fn new(loaded: &Loaded) -> EventLog {
+        EventLog {
+            entries: VecDeque::new(),
+            profiles: loaded.profiles.keys().copied().collect(),
+            matches: loaded.matches.values().map(|m| (m.id, m.status)).collect(),
+        }
+    }
+
+    pub fn entries(&self) -> &VecDeque<LoggedEvent> {
+        &self.entries
+    }
+
+    pub fn record(&mut self, ops: &[DbOp]) {
+        let timestamp = Utc::now();
+        for op in ops {
+            let Some(event) = self.event(op) else {
+                continue;
+            };
+            if self.entries.len() == EVENT_LOG_MAX {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(LoggedEvent {
+                id: Uuid::new_v4(),
+                timestamp,
+                event,
+            });
+        }
+    }
+
+    fn event(&mut self, op: &DbOp) -> Option<Event> {
+        Some(match op {
+            DbOp::UpsertProfile(p) => {
+                let profile = p.clone();
+                if self.profiles.insert(p.id) {
+                    Event::ProfileCreated { profile }
+                } else {
+                    Event::ProfileUpdated { profile }
+                }
+            }
+            DbOp::UpsertMatch(m) => {
+                let r#match = m.clone();
+                match self.matches.insert(m.id, m.status) {
+                    None => Event::MatchCreated { r#match },
+                    Some(before) if before == m.status => Event::MatchUpdated { r#match },
+                    Some(_) => match m.status {
+                        MatchStatus::Active => Event::MatchStarted { r#match },
+                        MatchStatus::Completed => Event::MatchResultRecorded { r#match },
+                        MatchStatus::Cancelled => Event::MatchCancelled { r#match },
+                        MatchStatus::Pending => Event::MatchUpdated { r#match },
+                    },
+                }
+            }
+            DbOp::UpsertQueueEntry(mode, e) => Event::PlayerEnqueued {
+                mode: *mode,
+                entry: StoredQueueEntry::from(e.clone()),
+            },
+            DbOp::DeleteQueueEntry(profile_id) => Event::PlayerDequeued {
+                profile_id: *profile_id,
+            },
+            DbOp::InsertSeason(season) => Event::SeasonStarted {
+                season: season.clone(),
+            },
+            DbOp::UpsertGuild(g) => Event::GuildSaved { guild: g.clone() },
+            DbOp::DeleteGuild(id) => Event::GuildDeleted { guild_id: *id },
+            // the audit log is its own record
+            DbOp::InsertAudit(_) => return None,
+        })
+    }
+}
+
+// the stored state after `events`, in order, as `AppState::new` takes it.
+// used by tests, and to rebuild a service from an exported log
+pub fn replay<'a>(events: impl IntoIterator<Item = &'a Event>) -> Loaded {
+    let mut loaded = Loaded::default();
+    for event in events {
+        match event {
+            Event::ProfileCreated { profile } | Event::ProfileUpdated { profile } => {
+                loaded.profiles.insert(profile.id, profile.clone());
+            }
+            Event::PlayerEnqueued { mode, entry } => {
+                let entry = entry.clone().into_entry();
+                for q in loaded.queues.values_mut() {
+                    q.retain(|e| e.profile_id != entry.profile_id);
+                }
+                loaded.queues.entry(*mode).or_default().insert(entry);
+            }
+            Event::PlayerDequeued { profile_id } => {
+                for q in loaded.queues.values_mut() {
+                    q.retain(|e| e.profile_id != *profile_id);
+                }
+            }
+            Event::MatchCreated { r#match }
+            | Event::MatchStarted { r#match }
+            | Event::MatchResultRecorded { r#match }
+            | Event::MatchCancelled { r#match }
+            | Event::MatchUpdated { r#match } => {
+                loaded.matches.insert(r#match.id, r#match.clone());
+            }
+            Event::SeasonStarted { season } => {
+                if loaded
+                    .season
+                    .as_ref()
+                    .is_none_or(|s| s.number <= season.number)
+                {
+                    loaded.season = Some(season.clone());
+                }
+            }
+            Event::GuildSaved { guild } => {
+                loaded.guilds.insert(guild.id, guild.clone());
+            }
+            Event::GuildDeleted { guild_id } => {
+                loaded.guilds.remove(guild_id);
+            }
+        }
+    }
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        extract::{Path, State},
+        Extension,
+    };
+
+    use super::*;
+    use crate::{
+        auth::AuthPlayer, config::Config, join_queue, new_profile, ready_match, record_result,
+        remove_from_queue, AppState, CreateProfile, Enqueued, QueueRequest, Region, Winner,
+    };
+
+    async fn player(state: &AppState, name: &str, mmr: u32) -> Uuid {
+        let payload = CreateProfile {
+            name: name.to_string(),
+            mmr,
+            region: Region::Europe,
+        };
+        new_profile(state, payload).await.unwrap().id
+    }
+
+    fn queue(profile_id: Uuid) -> QueueRequest {
+        QueueRequest {
+            profile_id,
+            party_id: None,
+            mode: GameMode::RankedSolo,
+            team_size: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_rebuilds_the_stored_state() {
+        let config = Config {
+            jwt_secret: Some("event-log".to_string()),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, crate::db::memory(), Loaded::default()));
+        let alice = player(&state, "alice", 1000).await;
+        let bob = player(&state, "bob", 1000).await;
+        let carol = player(&state, "carol", 1000).await;
+        let dave = player(&state, "dave", 2000).await;
+
+        join_queue(&state, alice, queue(alice)).await.unwrap();
+        let Enqueued::Matched(m) = join_queue(&state, bob, queue(bob)).await.unwrap() else {
+            panic!("alice and bob should be matched");
+        };
+        for id in [alice, bob] {
+            ready_match(State(state.clone()), Extension(AuthPlayer(id)), Path(m.id))
+                .await
+                .unwrap();
+        }
+        for id in [alice, bob] {
+            record_result(&state, Some(id), m.id, Winner::Player1).await.unwrap();
+        }
+        join_queue(&state, carol, queue(carol)).await.unwrap();
+        join_queue(&state, dave, queue(dave)).await.unwrap();
+        remove_from_queue(&state, dave, &queue(dave)).await.unwrap();
+
+        let log = state.event_log.lock().unwrap();
+        let kinds: Vec<String> = log
+            .entries()
+            .iter()
+            .map(|e| serde_json::to_value(&e.event).unwrap()["type"].to_string())
+            .filter(|kind| kind.contains("match"))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "\"match_created\"",
+                // alice readied up first
+                "\"match_updated\"",
+                "\"match_started\"",
+                // and reported the result first
+                "\"match_updated\"",
+                "\"match_result_recorded\""
+            ]
+        );
+        // bob was matched on joining and never waited in the queue
+        let dequeued: Vec<(usize, Uuid)> = log
+            .entries()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| match e.event {
+                Event::PlayerDequeued { profile_id } => Some((i, profile_id)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            dequeued.iter().map(|(_, id)| *id).collect::<Vec<_>>(),
+            [alice, dave]
+        );
+        let created = log
+            .entries()
+            .iter()
+            .position(|e| matches!(e.event, Event::MatchCreated { .. }))
+            .unwrap();
+        assert!(dequeued[0].0 < created);
+
+        let replayed = replay(log.entries().iter().map(|e| &e.event));
+        for id in [alice, bob, carol, dave] {
+            let profile = state.profiles.get(&id).unwrap();
+            assert_eq!(replayed.profiles[&id].ranked_mmr, profile.ranked_mmr);
+        }
+        assert!(replayed.profiles[&alice].ranked_mmr > 1000);
+        assert!(replayed.profiles[&bob].ranked_mmr < 1000);
+        assert_eq!(replayed.matches[&m.id].status, MatchStatus::Completed);
+        let queued: Vec<Uuid> = replayed.queues[&GameMode::RankedSolo]
+            .iter()
+            .map(|e| e.profile_id)
+            .collect();
+        assert_eq!(queued, [carol]);
+    }
+}
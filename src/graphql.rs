@@ -0,0 +1,578 @@
+// GraphQL API at /graphql, next to the REST routes. queries and mutations run
+// the same code as their HTTP endpoints; mutations need the bearer token the
+// REST writes need, checked by `auth::optional_auth`. `matchFound` pushes the
+// `matched` events of /ws/matches over /graphql/ws. like gRPC, nothing here
+// is rate limited or written to the audit log.
+
+use std::sync::Arc;
+
+use async_graphql::{
+    http::{playground_source, GraphQLPlaygroundConfig},
+    Context, Enum, Error, ErrorExtensions, Object, Result, Schema,
+    Subscription, ID,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
+use axum::{
+    extract::Extension,
+    middleware,
+    response::{Html, IntoResponse},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, AuthPlayer},
+    error::AppError,
+    join_queue, leaderboard_page, new_profile, queue_view, rank, record_result,
+    remove_from_queue, AppState, CreateProfile, Enqueued, LeaderboardQuery, LeaderboardSort,
+    MatchInfo, Notification, PlayerEvent, Profile, QueueRequest, QueueView, DEFAULT_MMR,
+};
+
+pub type MatchmakerSchema = Schema<Query, Mutation, Subscription>;
+
+pub fn schema(state: Arc<AppState>) -> MatchmakerSchema {
+    Schema::build(Query, Mutation, Subscription).data(state).finish()
+}
+
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let schema = schema(state.clone());
+    Router::new()
+        .route("/graphql", get(playground).post(execute))
+        .route_layer(middleware::from_fn_with_state(state, auth::optional_auth))
+        .route_service("/graphql/ws", GraphQLSubscription::new(schema.clone()))
+        .layer(Extension(schema))
+}
+
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    responses((status = 200, description = "GraphQL Playground", content_type = "text/html"))
+)]
+pub async fn playground() -> impl IntoResponse {
+    Html(playground_source(
+        GraphQLPlaygroundConfig::new("/graphql").subscription_endpoint("/graphql/ws"),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    responses(
+        (status = 200, description = "GraphQL response; errors carry the REST error code in extensions.code"),
+        (status = 401, description = "Invalid token", body = crate::ApiError),
+    )
+)]
+pub async fn execute(
+    Extension(schema): Extension<MatchmakerSchema>,
+    player: Option<Extension<AuthPlayer>>,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut req = req.into_inner();
+    if let Some(Extension(player)) = player {
+        req = req.data(player);
+    }
+    schema.execute(req).await.into()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn profile(&self, ctx: &Context<'_>, id: ID) -> Result<GqlProfile> {
+        let id = parse_id(&id)?;
+        match state(ctx).profiles.get(&id) {
+            None => Err(error(AppError::ProfileNotFound)),
+            Some(p) if p.deactivated => Err(error(AppError::ProfileDeactivated)),
+            Some(p) => Ok(GqlProfile(p.clone())),
+        }
+    }
+
+    #[graphql(name = "match")]
+    async fn match_(&self, ctx: &Context<'_>, id: ID) -> Result<GqlMatch> {
+        let m = state(ctx).find_match(parse_id(&id)?).await.map_err(error)?;
+        Ok(GqlMatch(m))
+    }
+
+    // every mode when none is given
+    async fn queue(&self, ctx: &Context<'_>, mode: Option<GameMode>) -> Vec<GqlQueueEntry> {
+        let queues = state(ctx).queue.read().await;
+        queue_view(&queues, mode.map(Into::into))
+            .into_iter()
+            .map(GqlQueueEntry)
+            .collect()
+    }
+
+    async fn leaderboard(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 25)] limit: usize,
+        #[graphql(default)] offset: usize,
+        #[graphql(default)] mode: GameMode,
+    ) -> GqlLeaderboard {
+        let query = LeaderboardQuery {
+            limit,
+            offset,
+            mode: mode.into(),
+            exclude_provisional: false,
+            sort: LeaderboardSort::Mmr,
+        };
+        let page = leaderboard_page(state(ctx), &query).await;
+        GqlLeaderboard {
+            total: page.total,
+            entries: page
+                .entries
+                .into_iter()
+                .map(|e| GqlLeaderboardEntry {
+                    rank: e.rank,
+                    mmr: e.mmr,
+                    profile: GqlProfile(e.profile.profile),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn create_profile(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        #[graphql(default_with = "DEFAULT_MMR")] mmr: u32,
+        #[graphql(default)] region: Region,
+    ) -> Result<GqlProfile> {
+        caller(ctx)?;
+        let payload = CreateProfile {
+            name,
+            mmr,
+            region: region.into(),
+        };
+        let profile = new_profile(state(ctx), payload).await.map_err(error)?;
+        Ok(GqlProfile(profile))
+    }
+
+    // queues the profile, or the party it leads
+    async fn enqueue(
+        &self,
+        ctx: &Context<'_>,
+        profile_id: ID,
+        party_id: Option<ID>,
+        #[graphql(default)] mode: GameMode,
+        team_size: Option<u8>,
+    ) -> Result<GqlEnqueued> {
+        let player = caller(ctx)?;
+        let payload = QueueRequest {
+            profile_id: parse_id(&profile_id)?,
+            party_id: party_id.as_ref().map(parse_id).transpose()?,
+            mode: mode.into(),
+            team_size,
+        };
+        Ok(match join_queue(state(ctx), player, payload).await.map_err(error)? {
+            Enqueued::Matched(m) => GqlEnqueued {
+                status: "matched".to_string(),
+                r#match: Some(GqlMatch(*m)),
+                ..GqlEnqueued::default()
+            },
+            Enqueued::Waiting(w) => GqlEnqueued {
+                status: w.status.to_string(),
+                lane: Some(w.lane.into()),
+                queue_position: Some(w.queue_position),
+                estimated_wait_seconds: w.estimated_wait_seconds,
+                ..GqlEnqueued::default()
+            },
+            Enqueued::AlreadyQueued(at) => GqlEnqueued {
+                status: "already_queued".to_string(),
+                queue_position: Some(at.position),
+                ..GqlEnqueued::default()
+            },
+        })
+    }
+
+    async fn leave_queue(
+        &self,
+        ctx: &Context<'_>,
+        profile_id: ID,
+        #[graphql(default)] mode: GameMode,
+    ) -> Result<bool> {
+        let player = caller(ctx)?;
+        let payload = QueueRequest {
+            profile_id: parse_id(&profile_id)?,
+            party_id: None,
+            mode: mode.into(),
+            team_size: None,
+        };
+        remove_from_queue(state(ctx), player, &payload).await.map_err(error)?;
+        Ok(true)
+    }
+
+    async fn report_result(&self, ctx: &Context<'_>, match_id: ID, winner: Winner) -> Result<GqlMatch> {
+        let player = caller(ctx)?;
+        let id = parse_id(&match_id)?;
+        let m = record_result(state(ctx), Some(player), id, winner.into()).await.map_err(error)?;
+        Ok(GqlMatch(m))
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    // the match of every `matched` event /ws/matches would send the profile
+    async fn match_found(&self, ctx: &Context<'_>, profile_id: ID) -> Result<impl Stream<Item = GqlMatch>> {
+        let profile_id = parse_id(&profile_id)?;
+        let events = BroadcastStream::new(state(ctx).events.subscribe());
+        Ok(events.filter_map(move |event| match event {
+            Ok(Notification {
+                profile_ids,
+                event: PlayerEvent::Matched { r#match },
+            }) if profile_ids.contains(&profile_id) => Some(GqlMatch(*r#match)),
+            // missed events are dropped, as on the websocket
+            _ => None,
+        }))
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Default)]
+#[graphql(remote = "crate::GameMode", rename_items = "PascalCase")]
+pub enum GameMode {
+    #[default]
+    RankedSolo,
+    RankedDuo,
+    CasualSolo,
+    Arcade,
+    GuildPractice,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Default)]
+#[graphql(remote = "crate::Region", rename_items = "PascalCase")]
+pub enum Region {
+    NorthAmerica,
+    Europe,
+    AsiaPacific,
+    SouthAmerica,
+    #[default]
+    Other,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::MatchStatus", rename_items = "PascalCase")]
+pub enum MatchStatus {
+    Pending,
+    Active,
+    Completed,
+    Cancelled,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::MatchResult", rename_items = "PascalCase")]
+pub enum MatchResult {
+    Player1Win,
+    Player2Win,
+    Draw,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::Winner", rename_items = "PascalCase")]
+pub enum Winner {
+    Player1,
+    Player2,
+    Draw,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::lanes::Lane", rename_items = "PascalCase")]
+pub enum Lane {
+    Vip,
+    Normal,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::rank::RankTier", rename_items = "PascalCase")]
+pub enum RankTier {
+    Bronze,
+    Silver,
+    Gold,
+    Platinum,
+    Diamond,
+    Master,
+}
+
+pub struct GqlProfile(Profile);
+
+#[Object(name = "Profile")]
+impl GqlProfile {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn mmr(&self, #[graphql(default)] mode: GameMode) -> u32 {
+        crate::get_mode_mmr(&self.0, mode.into())
+    }
+
+    async fn wins(&self) -> u32 {
+        self.0.wins
+    }
+
+    async fn losses(&self) -> u32 {
+        self.0.losses
+    }
+
+    async fn draws(&self) -> u32 {
+        self.0.draws
+    }
+
+    async fn games_played(&self) -> u32 {
+        self.0.games_played
+    }
+
+    async fn provisional(&self) -> bool {
+        self.0.is_provisional()
+    }
+
+    // from ranked mmr, as in the REST profile
+    async fn tier(&self) -> RankTier {
+        rank::mmr_to_tier(self.0.ranked_mmr).into()
+    }
+
+    async fn peak_mmr(&self) -> u32 {
+        self.0.peak_mmr
+    }
+
+    async fn region(&self) -> Region {
+        self.0.region.into()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+}
+
+pub struct GqlMatch(MatchInfo);
+
+#[Object(name = "Match")]
+impl GqlMatch {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn player1(&self) -> ID {
+        ID(self.0.player1.to_string())
+    }
+
+    async fn player2(&self) -> ID {
+        ID(self.0.player2.to_string())
+    }
+
+    async fn team1(&self) -> Vec<ID> {
+        ids(&self.0.team1)
+    }
+
+    async fn team2(&self) -> Vec<ID> {
+        ids(&self.0.team2)
+    }
+
+    async fn mode(&self) -> GameMode {
+        self.0.mode.into()
+    }
+
+    async fn status(&self) -> MatchStatus {
+        self.0.status.into()
+    }
+
+    async fn result(&self) -> Option<MatchResult> {
+        self.0.result.map(Into::into)
+    }
+
+    async fn cancel_reason(&self) -> Option<&str> {
+        self.0.cancel_reason.map(|r| r.as_str())
+    }
+
+    async fn quality(&self) -> Option<f64> {
+        self.0.quality
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.0.started_at
+    }
+
+    async fn ended_at(&self) -> Option<DateTime<Utc>> {
+        self.0.ended_at
+    }
+}
+
+pub struct GqlQueueEntry(QueueView);
+
+#[Object(name = "QueueEntry")]
+impl GqlQueueEntry {
+    async fn profile_id(&self) -> ID {
+        ID(self.0.profile_id.to_string())
+    }
+
+    async fn party_id(&self) -> Option<ID> {
+        self.0.party_id.map(|id| ID(id.to_string()))
+    }
+
+    async fn region(&self) -> Region {
+        self.0.region.into()
+    }
+
+    async fn mode(&self) -> GameMode {
+        self.0.mode.into()
+    }
+
+    async fn lane(&self) -> Lane {
+        self.0.lane.into()
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+#[graphql(name = "Leaderboard")]
+pub struct GqlLeaderboard {
+    // rated players in total, not just on this page
+    total: usize,
+    entries: Vec<GqlLeaderboardEntry>,
+}
+
+#[derive(async_graphql::SimpleObject)]
+#[graphql(name = "LeaderboardEntry")]
+pub struct GqlLeaderboardEntry {
+    rank: usize,
+    // on the requested mode
+    mmr: u32,
+    profile: GqlProfile,
+}
+
+// what `enqueue` led to: `status` is "matched" with the match, "enqueued" or
+// "already_queued" with the position
+#[derive(async_graphql::SimpleObject, Default)]
+#[graphql(name = "Enqueued")]
+pub struct GqlEnqueued {
+    status: String,
+    r#match: Option<GqlMatch>,
+    lane: Option<Lane>,
+    queue_position: Option<usize>,
+    estimated_wait_seconds: Option<f64>,
+}
+
+fn state<'a>(ctx: &Context<'a>) -> &'a Arc<AppState> {
+    ctx.data_unchecked::<Arc<AppState>>()
+}
+
+fn caller(ctx: &Context<'_>) -> Result<Uuid> {
+    ctx.data_opt::<AuthPlayer>()
+        .map(|player| player.0)
+        .ok_or_else(|| error(AppError::Unauthorized))
+}
+
+// keeps the REST error code in `extensions.code`, e.g. PROFILE_NOT_FOUND
+fn error(err: AppError) -> Error {
+    let code = err.code();
+    Error::new(err.to_string()).extend_with(|_, e| e.set("code", code))
+}
+
+fn parse_id(id: &ID) -> Result<Uuid> {
+    id.parse().map_err(|_| {
+        Error::new(format!("{} is not a UUID", id.as_str())).extend_with(|_, e| e.set("code", "INVALID_ID"))
+    })
+}
+
+fn ids(players: &[Uuid]) -> Vec<ID> {
+    players.iter().map(|id| ID(id.to_string())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Request, Value};
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::{config::Config, db::{self, Loaded}};
+
+    fn setup() -> MatchmakerSchema {
+        let config = Config {
+            jwt_secret: Some("graphql-test-secret".to_string()),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        schema(Arc::new(AppState::new(config, db::memory(), Loaded::default())))
+    }
+
+    async fn run(schema: &MatchmakerSchema, query: String, player: Option<Uuid>) -> Value {
+        let mut req = Request::new(query);
+        if let Some(id) = player {
+            req = req.data(AuthPlayer(id));
+        }
+        let res = schema.execute(req).await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        res.data
+    }
+
+    async fn create(schema: &MatchmakerSchema, name: &str) -> Uuid {
+        let query = format!(r#"mutation {{ createProfile(name: "{name}") {{ id }} }}"#);
+        let data = run(schema, query, Some(Uuid::nil())).await.into_json().unwrap();
+        data["createProfile"]["id"].as_str().unwrap().parse().unwrap()
+    }
+
+    fn enqueue(id: Uuid) -> String {
+        format!(r#"mutation {{ enqueue(profileId: "{id}", mode: CasualSolo) {{ status match {{ id }} }} }}"#)
+    }
+
+    #[tokio::test]
+    async fn match_found_follows_the_queue() {
+        let schema = setup();
+        let alice = create(&schema, "alice").await;
+        let bob = create(&schema, "bob").await;
+        let mut found = schema.execute_stream(format!(
+            r#"subscription {{ matchFound(profileId: "{alice}") {{ id status player2 }} }}"#
+        ));
+
+        let first = run(&schema, enqueue(alice), Some(alice)).await.into_json().unwrap();
+        assert_eq!(first["enqueue"]["status"], "enqueued");
+        // the resolver only subscribes once the stream is polled
+        let matched = tokio::spawn({
+            let schema = schema.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                run(&schema, enqueue(bob), Some(bob)).await.into_json().unwrap()
+            }
+        });
+
+        let pushed = found.next().await.unwrap().data.into_json().unwrap();
+        let second = matched.await.unwrap();
+        assert_eq!(second["enqueue"]["status"], "matched");
+        assert_eq!(pushed["matchFound"]["id"], second["enqueue"]["match"]["id"]);
+        assert_eq!(pushed["matchFound"]["status"], "Pending");
+        assert_eq!(pushed["matchFound"]["player2"], bob.to_string());
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_rest_code() {
+        let schema = setup();
+        let alice = create(&schema, "alice").await;
+
+        let anonymous = schema.execute(enqueue(alice)).await;
+        let code = anonymous.errors[0].extensions.as_ref().and_then(|e| e.get("code")).cloned();
+        assert_eq!(code, Some(Value::from("UNAUTHORIZED")));
+
+        let missing = schema
+            .execute(format!(r#"{{ profile(id: "{}") {{ id }} }}"#, Uuid::new_v4()))
+            .await;
+        let code = missing.errors[0].extensions.as_ref().and_then(|e| e.get("code")).cloned();
+        assert_eq!(code, Some(Value::from("PROFILE_NOT_FOUND")));
+    }
+}
@@ -0,0 +1,375 @@
+// gRPC service from proto/matchmaker.proto, served next to the HTTP API on
+// `config.grpc_port`. every call runs the same code as its HTTP endpoint and
+// writes need the same bearer token; maintenance mode answers UNAVAILABLE.
+// calls here are neither rate limited nor written to the audit log.
+
+// `Status` is what tonic's handlers return, boxing it would only add unboxing
+#![allow(clippy::result_large_err)]
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::http::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::{Code, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    auth, error::AppError, join_queue, new_profile, record_result,
+    remove_from_queue, AppState, CreateProfile, Enqueued, MatchInfo, Profile, QueueRequest, Winner,
+    DEFAULT_MMR,
+};
+
+pub mod pb {
+    tonic::include_proto!("matchmaker.v1");
+}
+
+use pb::{
+    enqueue_reply::Outcome,
+    matchmaker_service_server::{MatchmakerService, MatchmakerServiceServer},
+};
+
+pub async fn serve(state: Arc<AppState>, addr: SocketAddr) {
+    tracing::info!("gRPC listening on {}", addr);
+    let result = tonic::transport::Server::builder()
+        .add_service(MatchmakerServiceServer::new(Grpc::new(state)))
+        .serve(addr)
+        .await;
+    if let Err(e) = result {
+        tracing::error!("gRPC server stopped: {e}");
+    }
+}
+
+pub struct Grpc {
+    state: Arc<AppState>,
+}
+
+impl Grpc {
+    pub fn new(state: Arc<AppState>) -> Grpc {
+        Grpc { state }
+    }
+
+    fn check_available(&self) -> Result<(), Status> {
+        if self.state.maintenance.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(status(AppError::Maintenance));
+        }
+        Ok(())
+    }
+
+    // the player the `authorization` metadata holds a token for
+    async fn caller<T>(&self, req: &Request<T>) -> Result<Uuid, Status> {
+        let token = req
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(String::from)
+            .ok_or_else(|| status(AppError::Unauthorized))?;
+        let identity = auth::verify_token(&self.state, &token).await.map_err(status)?;
+        Ok(identity.player.0)
+    }
+}
+
+#[tonic::async_trait]
+impl MatchmakerService for Grpc {
+    async fn create_profile(
+        &self,
+        req: Request<pb::CreateProfileRequest>,
+    ) -> Result<Response<pb::Profile>, Status> {
+        self.check_available()?;
+        self.caller(&req).await?;
+        let req = req.into_inner();
+        let payload = CreateProfile {
+            name: req.name,
+            mmr: req.mmr.unwrap_or(DEFAULT_MMR),
+            region: parse_name("region", &req.region)?,
+        };
+        let profile = new_profile(&self.state, payload).await.map_err(status)?;
+        Ok(Response::new(pb::Profile::from(&profile)))
+    }
+
+    async fn enqueue(
+        &self,
+        req: Request<pb::EnqueueRequest>,
+    ) -> Result<Response<pb::EnqueueReply>, Status> {
+        self.check_available()?;
+        let player = self.caller(&req).await?;
+        let req = req.into_inner();
+        let payload = QueueRequest {
+            profile_id: parse_id("profile_id", &req.profile_id)?,
+            party_id: req.party_id.as_deref().map(|id| parse_id("party_id", id)).transpose()?,
+            mode: parse_name("mode", &req.mode)?,
+            // sizes past u8 are rejected by join_queue like any other
+            team_size: req.team_size.map(|n| u8::try_from(n).unwrap_or(u8::MAX)),
+        };
+        let outcome = match join_queue(&self.state, player, payload).await.map_err(status)? {
+            Enqueued::Matched(m) => Outcome::Matched(pb::Match::from(&*m)),
+            Enqueued::Waiting(w) => Outcome::Waiting(pb::Waiting {
+                lane: name(&w.lane),
+                queue_position: w.queue_position as u32,
+                estimated_wait_seconds: w.estimated_wait_seconds,
+            }),
+            Enqueued::AlreadyQueued(_) => Outcome::AlreadyQueued(pb::AlreadyQueued {}),
+        };
+        Ok(Response::new(pb::EnqueueReply { outcome: Some(outcome) }))
+    }
+
+    async fn leave_queue(
+        &self,
+        req: Request<pb::LeaveQueueRequest>,
+    ) -> Result<Response<pb::LeaveQueueReply>, Status> {
+        self.check_available()?;
+        let player = self.caller(&req).await?;
+        let req = req.into_inner();
+        let payload = QueueRequest {
+            profile_id: parse_id("profile_id", &req.profile_id)?,
+            party_id: None,
+            mode: parse_name("mode", &req.mode)?,
+            team_size: None,
+        };
+        remove_from_queue(&self.state, player, &payload).await.map_err(status)?;
+        Ok(Response::new(pb::LeaveQueueReply {}))
+    }
+
+    async fn get_match(
+        &self,
+        req: Request<pb::GetMatchRequest>,
+    ) -> Result<Response<pb::Match>, Status> {
+        self.check_available()?;
+        let id = parse_id("id", &req.into_inner().id)?;
+        let m = self.state.find_match(id).await.map_err(status)?;
+        Ok(Response::new(pb::Match::from(&m)))
+    }
+
+    async fn report_result(
+        &self,
+        req: Request<pb::ReportResultRequest>,
+    ) -> Result<Response<pb::Match>, Status> {
+        self.check_available()?;
+        let player = self.caller(&req).await?;
+        let req = req.into_inner();
+        let winner = match pb::Winner::try_from(req.winner) {
+            Ok(pb::Winner::Player1) => Winner::Player1,
+            Ok(pb::Winner::Player2) => Winner::Player2,
+            Ok(pb::Winner::Draw) => Winner::Draw,
+            Ok(pb::Winner::Unspecified) | Err(_) => {
+                return Err(Status::invalid_argument("winner must be set"))
+            }
+        };
+        let id = parse_id("match_id", &req.match_id)?;
+        let m = record_result(&self.state, Some(player), id, winner).await.map_err(status)?;
+        Ok(Response::new(pb::Match::from(&m)))
+    }
+}
+
+// a function rather than `From`, which would make `AppError` public API
+fn status(err: AppError) -> Status {
+    let code = match err.status() {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND | StatusCode::GONE => Code::NotFound,
+        StatusCode::CONFLICT => Code::FailedPrecondition,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    // led by the code the HTTP API would answer with, e.g. MATCH_NOT_FOUND
+    Status::new(code, format!("{}: {err}", err.code()))
+}
+
+impl From<&Profile> for pb::Profile {
+    fn from(p: &Profile) -> Self {
+        pb::Profile {
+            id: p.id.to_string(),
+            name: p.name.clone(),
+            mmr_by_mode: p.mmr_by_mode.iter().map(|(mode, mmr)| (name(mode), *mmr)).collect(),
+            wins: p.wins,
+            losses: p.losses,
+            draws: p.draws,
+            region: name(&p.region),
+        }
+    }
+}
+
+impl From<&MatchInfo> for pb::Match {
+    fn from(m: &MatchInfo) -> Self {
+        let ids = |team: &[Uuid]| team.iter().map(Uuid::to_string).collect();
+        pb::Match {
+            id: m.id.to_string(),
+            player1: m.player1.to_string(),
+            player2: m.player2.to_string(),
+            team1: ids(&m.team1),
+            team2: ids(&m.team2),
+            mode: name(&m.mode),
+            status: name(&m.status),
+            result: m.result.as_ref().map(name),
+            created_at: m.created_at.to_rfc3339(),
+        }
+    }
+}
+
+fn parse_id(field: &str, value: &str) -> Result<Uuid, Status> {
+    value
+        .parse()
+        .map_err(|_| Status::invalid_argument(format!("{field} is not a UUID")))
+}
+
+// an enum by its JSON name, or its default when empty
+fn parse_name<T: DeserializeOwned + Default>(field: &str, value: &str) -> Result<T, Status> {
+    if value.is_empty() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| Status::invalid_argument(format!("unknown {field} {value}")))
+}
+
+// the JSON name of an enum
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Channel;
+
+    use super::*;
+    use crate::{config::Config, db::{self, Loaded}};
+    use pb::matchmaker_service_client::MatchmakerServiceClient;
+
+    const SECRET: &str = "grpc-test-secret";
+
+    async fn client() -> MatchmakerServiceClient<Channel> {
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), Loaded::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(MatchmakerServiceServer::new(Grpc::new(state)))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        MatchmakerServiceClient::connect(format!("http://{addr}")).await.unwrap()
+    }
+
+    fn authed<T>(message: T, player: &str) -> Request<T> {
+        let claims = serde_json::json!({
+            "sub": player,
+            "exp": chrono::Utc::now().timestamp() + 3600,
+        });
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap();
+        let mut req = Request::new(message);
+        req.metadata_mut()
+            .insert("authorization", format!("Bearer {token}").parse().unwrap());
+        req
+    }
+
+    async fn create(client: &mut MatchmakerServiceClient<Channel>, name: &str) -> String {
+        let req = pb::CreateProfileRequest {
+            name: name.to_string(),
+            mmr: None,
+            region: String::new(),
+        };
+        let admin = Uuid::nil().to_string();
+        client.create_profile(authed(req, &admin)).await.unwrap().into_inner().id
+    }
+
+    fn enqueue_req(profile_id: &str) -> pb::EnqueueRequest {
+        pb::EnqueueRequest {
+            profile_id: profile_id.to_string(),
+            party_id: None,
+            mode: "CasualSolo".to_string(),
+            team_size: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_players_are_matched_and_rated() {
+        let mut client = client().await;
+        let alice = create(&mut client, "alice").await;
+        let bob = create(&mut client, "bob").await;
+
+        let first = client.enqueue(authed(enqueue_req(&alice), &alice)).await.unwrap();
+        assert!(matches!(first.into_inner().outcome, Some(Outcome::Waiting(_))));
+        let second = client.enqueue(authed(enqueue_req(&bob), &bob)).await.unwrap();
+        let Some(Outcome::Matched(m)) = second.into_inner().outcome else {
+            panic!("second player was not matched");
+        };
+        assert_eq!((m.player1.as_str(), m.player2.as_str()), (alice.as_str(), bob.as_str()));
+        assert_eq!(m.status, "Pending");
+
+        let reported = client
+            .report_result(authed(
+                pb::ReportResultRequest {
+                    match_id: m.id.clone(),
+                    winner: pb::Winner::Player1 as i32,
+                },
+                &alice,
+            ))
+            .await;
+        // a pending match has to be started first, as over HTTP
+        assert_eq!(reported.unwrap_err().code(), Code::FailedPrecondition);
+
+        let fetched = client
+            .get_match(pb::GetMatchRequest { id: m.id })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(fetched.status, "Pending");
+        assert_eq!(fetched.mode, "CasualSolo");
+    }
+
+    #[tokio::test]
+    async fn leaving_empties_the_queue() {
+        let mut client = client().await;
+        let alice = create(&mut client, "alice").await;
+        client.enqueue(authed(enqueue_req(&alice), &alice)).await.unwrap();
+        let leave = pb::LeaveQueueRequest {
+            profile_id: alice.clone(),
+            mode: "CasualSolo".to_string(),
+        };
+        client.leave_queue(authed(leave.clone(), &alice)).await.unwrap();
+        let again = client.leave_queue(authed(leave, &alice)).await.unwrap_err();
+        assert_eq!(again.code(), Code::InvalidArgument);
+        assert!(again.message().starts_with("NOT_IN_QUEUE"));
+    }
+
+    #[tokio::test]
+    async fn writes_need_the_players_own_token() {
+        let mut client = client().await;
+        let alice = create(&mut client, "alice").await;
+        let bob = create(&mut client, "bob").await;
+
+        let anonymous = client.enqueue(Request::new(enqueue_req(&alice))).await.unwrap_err();
+        assert_eq!(anonymous.code(), Code::Unauthenticated);
+        let someone_else = client.enqueue(authed(enqueue_req(&alice), &bob)).await.unwrap_err();
+        assert_eq!(someone_else.code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn unknown_match_is_not_found() {
+        let mut client = client().await;
+        let missing = pb::GetMatchRequest {
+            id: Uuid::new_v4().to_string(),
+        };
+        let err = client.get_match(missing).await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+        let malformed = pb::GetMatchRequest { id: "nope".to_string() };
+        let err = client.get_match(malformed).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,70 @@
+// The queue of one mode, kept in priority order: every VIP entry ahead of
+// every normal one, and within a lane the longest waiting first. iteration,
+// indices and positions follow that order, so opponent search and queue
+// positions favour VIPs and then the oldest entries without knowing about
+// lanes. a sorted deque rather than a heap, since matching walks the entries
+// in order and removes from the middle.
+
+use std::{collections::VecDeque, time::Instant};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::QueueEntry;
+
+// declared in priority order, which the derived `Ord` follows
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Lane {
+    Vip,
+    #[default]
+    Normal,
+}
+
+impl QueueEntry {
+    // lower sorts first
+    fn priority(&self) -> (Lane, Instant) {
+        (self.lane, self.queued_at)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ModeQueue {
+    entries: VecDeque<QueueEntry>,
+}
+
+impl ModeQueue {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &QueueEntry> {
+        self.entries.iter()
+    }
+
+    // callers must not change `lane` or `queued_at`, which fix the position
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut QueueEntry> {
+        self.entries.iter_mut()
+    }
+
+    pub fn retain(&mut self, keep: impl FnMut(&QueueEntry) -> bool) {
+        self.entries.retain(keep);
+    }
+
+    // `idx` as counted by `iter`
+    pub fn remove(&mut self, idx: usize) -> Option<QueueEntry> {
+        self.entries.remove(idx)
+    }
+
+    // puts the entry behind everything of the same or higher priority and
+    // returns its index as counted by `iter`. a new entry lands at the back
+    // of its lane, a requeued one back where its join time puts it
+    pub fn insert(&mut self, entry: QueueEntry) -> usize {
+        let key = entry.priority();
+        let idx = self.entries.partition_point(|e| e.priority() <= key);
+        self.entries.insert(idx, entry);
+        idx
+    }
+}
@@ -0,0 +1,5808 @@
+use axum::{
+    body::StreamBody,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, Path, Query, State,
+    },
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use dashmap::DashMap;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, mpsc, Mutex, RwLock},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use unicode_normalization::UnicodeNormalization;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+use uuid::Uuid;
+
+use crate::{
+    audit::AuditEntry,
+    auth::{AdminIdentity, AuthPlayer, ExternalIdentity},
+    config::Config,
+    discord::DiscordEvent,
+    db::DbOp,
+    error::{ApiError, AppError},
+    event_log::{EventLog, LoggedEvent},
+    lanes::{Lane, ModeQueue},
+    rank::RankTier,
+    snapshot::{SnapshotRestored, SnapshotSaved},
+    tournament::{CreateTournament, Next, Tournament},
+    webhooks::{CircuitStatus, CreateWebhook, Webhook, WebhookEvent},
+};
+
+mod audit;
+mod auth;
+pub mod config;
+mod cors;
+mod db;
+mod discord;
+mod draft;
+mod elo;
+mod error;
+mod event_log;
+mod graphql;
+mod grpc;
+mod lanes;
+mod maintenance;
+mod matchmaking;
+mod metrics;
+mod msgpack;
+mod naming;
+mod oauth;
+mod openapi;
+mod rank;
+mod rate_limit;
+mod redis_queue;
+mod request_id;
+mod routes;
+mod shards;
+mod snapshot;
+mod strategy;
+mod telemetry;
+mod tenants;
+mod tournament;
+mod webhooks;
+
+// v1: returned as is by the v1 API and also the stored form
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+struct Profile {
+    id: Uuid,
+    name: String,
+    // headline ratings kept for v1 clients: the RankedSolo and CasualSolo
+    // entries of `mmr_by_mode`, updated along with them
+    ranked_mmr: u32,
+    casual_mmr: u32,
+    // independent rating per mode, so no mode's games touch another's mmr.
+    // only changed through `set_mode_mmr`
+    #[serde(default)]
+    mmr_by_mode: HashMap<GameMode, u32>,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+    region: Region,
+    // profiles stored before this field existed read back as the epoch
+    #[serde(default)]
+    created_at: DateTime<Utc>,
+    // best ranked mmr since the last season reset
+    #[serde(default)]
+    season_peak_mmr: u32,
+    // best ranked mmr ever reached, kept across season resets
+    #[serde(default)]
+    peak_mmr: u32,
+    // best mmr ever reached on each mode; see `mode_peak_mmr`
+    #[serde(default)]
+    peak_mmr_by_mode: HashMap<GameMode, u32>,
+    // completed games on any track, counted from reported results
+    #[serde(default)]
+    games_played: u32,
+    #[serde(default)]
+    last_game_at: Option<DateTime<Utc>>,
+    // losses in a row, reset by a win
+    #[serde(default)]
+    current_loss_streak: u32,
+    // set by DELETE /profiles/:id; the profile stays for match history
+    #[serde(default)]
+    deactivated: bool,
+    // queued in the VIP lane
+    #[serde(default)]
+    vip: bool,
+    // abandoned matches so far; each makes the next queue ban longer
+    #[serde(default)]
+    penalty_count: u32,
+    #[serde(default)]
+    ban_until: Option<DateTime<Utc>>,
+    // inactivity up to this point has already been decayed
+    #[serde(default)]
+    decayed_until: Option<DateTime<Utc>>,
+    // accepted friendships, always listed on both profiles
+    #[serde(default)]
+    friends: HashSet<Uuid>,
+    // players waiting for this one to accept their friend request
+    #[serde(default)]
+    friend_requests: HashSet<Uuid>,
+    // never matched against this player, whichever side blocked
+    #[serde(default)]
+    blocked: HashSet<Uuid>,
+    #[serde(default)]
+    guild_id: Option<Uuid>,
+    // `sub` of the external provider token the profile was created with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    external_id: Option<String>,
+    // additional fields can be added: avatar, etc.
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+enum Region {
+    NorthAmerica,
+    Europe,
+    AsiaPacific,
+    SouthAmerica,
+    #[default]
+    Other,
+}
+
+impl Profile {
+    fn set_mode_mmr(&mut self, mode: GameMode, mmr: u32) {
+        self.mmr_by_mode.insert(mode, mmr);
+        match mode {
+            GameMode::RankedSolo => self.ranked_mmr = mmr,
+            GameMode::CasualSolo => self.casual_mmr = mmr,
+            _ => {}
+        }
+    }
+
+    // profiles stored before ratings were kept per mode start every mode from
+    // the ranked or casual track it used to share
+    fn fill_mode_mmr(&mut self) {
+        for mode in GameMode::ALL {
+            let track = if mode.is_ranked() { self.ranked_mmr } else { self.casual_mmr };
+            self.mmr_by_mode.entry(mode).or_insert(track);
+        }
+    }
+
+    // keeps the peaks up with a new rating on `mode`; the overall ones only
+    // follow ranked modes
+    fn raise_peaks(&mut self, mode: GameMode, mmr: u32) {
+        let peak = self.peak_mmr_by_mode.entry(mode).or_insert(mmr);
+        *peak = (*peak).max(mmr);
+        if mode.is_ranked() {
+            self.season_peak_mmr = self.season_peak_mmr.max(mmr);
+            self.peak_mmr = self.peak_mmr.max(mmr);
+        }
+    }
+
+    // profiles stored before peaks were kept per mode have at least their
+    // current rating as the peak
+    fn mode_peak_mmr(&self, mode: GameMode) -> u32 {
+        let peak = self.peak_mmr_by_mode.get(&mode).copied().unwrap_or_default();
+        peak.max(get_mode_mmr(self, mode))
+    }
+
+    // highest rating over the ranked modes, which is what peaks track
+    fn best_ranked_mmr(&self) -> u32 {
+        GameMode::ALL
+            .into_iter()
+            .filter(|mode| mode.is_ranked())
+            .map(|mode| get_mode_mmr(self, mode))
+            .max()
+            .unwrap_or(DEFAULT_MMR)
+    }
+
+    fn is_provisional(&self) -> bool {
+        self.games_played < elo::PROVISIONAL_GAMES
+    }
+
+    // K-factor of this player's next rating update
+    fn k_factor(&self, established: f64) -> f64 {
+        if self.is_provisional() {
+            elo::PROVISIONAL_K
+        } else {
+            established
+        }
+    }
+
+    // counts a finished game: Some(true) for a win, None for a draw.
+    // draws leave the loss streak as it is
+    fn record_game(&mut self, won: Option<bool>) {
+        match won {
+            Some(true) => {
+                self.wins += 1;
+                self.current_loss_streak = 0;
+            }
+            Some(false) => {
+                self.losses += 1;
+                self.current_loss_streak += 1;
+            }
+            None => self.draws += 1,
+        }
+        self.games_played += 1;
+    }
+
+    // wins as a fraction of all completed games, 0 when none were played
+    fn win_rate(&self) -> f64 {
+        let total = self.wins + self.losses + self.draws;
+        if total == 0 {
+            0.0
+        } else {
+            self.wins as f64 / total as f64
+        }
+    }
+}
+
+// the stored rating on `mode`, or DEFAULT_MMR for a mode without an entry
+fn get_mode_mmr(profile: &Profile, mode: GameMode) -> u32 {
+    profile.mmr_by_mode.get(&mode).copied().unwrap_or(DEFAULT_MMR)
+}
+
+// form names are compared in: NFC, lowercased
+fn normalize_name(name: &str) -> String {
+    name.nfc().collect::<String>().to_lowercase()
+}
+
+// profile as returned by the API, with computed stats alongside the stored fields
+#[derive(Debug, Serialize, ToSchema)]
+struct ProfileView {
+    #[serde(flatten)]
+    profile: Profile,
+    win_rate: f64,
+    // from ranked mmr
+    tier: RankTier,
+    // fewer than elo::PROVISIONAL_GAMES games played
+    provisional: bool,
+}
+
+impl From<Profile> for ProfileView {
+    fn from(profile: Profile) -> Self {
+        ProfileView {
+            win_rate: profile.win_rate(),
+            tier: rank::mmr_to_tier(profile.ranked_mmr),
+            provisional: profile.is_provisional(),
+            profile,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, ToSchema)]
+pub enum GameMode {
+    #[default]
+    RankedSolo,
+    RankedDuo,
+    CasualSolo,
+    Arcade,
+    // casual, with guild mates preferred as opponents
+    GuildPractice,
+}
+
+impl GameMode {
+    const ALL: [GameMode; 5] = [
+        GameMode::RankedSolo,
+        GameMode::RankedDuo,
+        GameMode::CasualSolo,
+        GameMode::Arcade,
+        GameMode::GuildPractice,
+    ];
+
+    fn is_ranked(self) -> bool {
+        matches!(self, GameMode::RankedSolo | GameMode::RankedDuo)
+    }
+}
+
+// v1: returned as is by the v1 API and also the stored form
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+struct MatchInfo {
+    id: Uuid,
+    // player1/player2 are the captains of each side: the solo player or the
+    // party leader. team1/team2 list every player on that side
+    player1: Uuid,
+    player2: Uuid,
+    team1: Vec<Uuid>,
+    team2: Vec<Uuid>,
+    mode: GameMode,
+    created_at: DateTime<Utc>,
+    // when the match went Active, and when it was completed or cancelled
+    #[serde(default)]
+    started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    ended_at: Option<DateTime<Utc>>,
+    result: Option<MatchResult>,
+    // the result each side reported, by any of its players. a player report
+    // only completes the match once the other side reported the same
+    #[serde(default)]
+    report_team1: Option<MatchResult>,
+    #[serde(default)]
+    report_team2: Option<MatchResult>,
+    // the two sides reported different results; the admin key settles it
+    #[serde(default)]
+    disputed: bool,
+    status: MatchStatus,
+    cancel_reason: Option<CancelReason>,
+    // free text given with a cancel request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cancel_note: Option<String>,
+    // ready check of the two captains while the match is Pending
+    ready_player1: bool,
+    ready_player2: bool,
+    // profiles watching the match, at most `config.max_spectators`
+    #[serde(default)]
+    spectators: Vec<Uuid>,
+    // tier of every player on the match's rating track when it was created
+    #[serde(default)]
+    tiers: HashMap<Uuid, RankTier>,
+    // 1.0 for sides of equal mmr down to 0.0 for a gap of `mmr_range_max` or
+    // more, see `AppState::quality`. None for matches stored before it existed
+    #[serde(default)]
+    quality: Option<f64>,
+    // free-form entries set by game servers and players, e.g. server address
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    // at most one review per participant, once the match is completed
+    #[serde(default)]
+    feedback: Vec<MatchFeedback>,
+    // champion select, in modes with a draft order once the match is Active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    draft_state: Option<draft::DraftState>,
+    // the game server, once an admin assigned one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "192.168.1.100:7777")]
+    server_address: Option<SocketAddr>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+struct MatchFeedback {
+    player_id: Uuid,
+    // 1 to 5 stars
+    match_quality: u8,
+    opponent_sportsmanship: Option<u8>,
+    comment: Option<String>,
+}
+
+impl MatchInfo {
+    fn participants(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.team1.iter().chain(&self.team2).copied()
+    }
+
+    fn involves(&self, profile_id: Uuid) -> bool {
+        self.team1.contains(&profile_id) || self.team2.contains(&profile_id)
+    }
+
+    // moves to `next`, stamping the start or end time. callers check
+    // `can_transition_to` first
+    fn transition(&mut self, next: MatchStatus) {
+        self.status = next;
+        match next {
+            MatchStatus::Active => self.started_at = Some(Utc::now()),
+            MatchStatus::Completed | MatchStatus::Cancelled => self.ended_at = Some(Utc::now()),
+            MatchStatus::Pending => {}
+        }
+    }
+
+    // how long the match was played, once it is completed
+    fn duration(&self) -> Option<Duration> {
+        if self.status != MatchStatus::Completed {
+            return None;
+        }
+        (self.ended_at? - self.started_at?).to_std().ok()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+enum MatchStatus {
+    Pending,
+    Active,
+    Completed,
+    Cancelled,
+}
+
+impl MatchStatus {
+    fn can_transition_to(self, next: MatchStatus) -> bool {
+        matches!(
+            (self, next),
+            (MatchStatus::Pending, MatchStatus::Active)
+                | (MatchStatus::Active, MatchStatus::Completed)
+                | (MatchStatus::Pending | MatchStatus::Active, MatchStatus::Cancelled)
+        )
+    }
+}
+
+// the 409 error for a transition the lifecycle does not allow
+fn invalid_transition(action: &'static str, m: &MatchInfo) -> AppError {
+    AppError::InvalidTransition {
+        action,
+        status: m.status,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+enum MatchResult {
+    Player1Win,
+    Player2Win,
+    Draw,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+struct Season {
+    number: u32,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+enum ReportReason {
+    Cheating,
+    Harassment,
+    #[serde(rename = "AFK")]
+    Afk,
+    Smurfing,
+    Other,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+struct Report {
+    id: Uuid,
+    reporter_id: Uuid,
+    reported_id: Uuid,
+    match_id: Option<Uuid>,
+    reason: ReportReason,
+    created_at: DateTime<Utc>,
+}
+
+// v1: returned as is by the v1 API and also the stored form. the members are
+// the profiles whose guild_id points here
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+struct Guild {
+    id: Uuid,
+    name: String,
+    // the creator; the only one who can add or remove other members
+    owner: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+struct Party {
+    id: Uuid,
+    leader: Uuid,
+    members: Vec<Uuid>,
+}
+
+// locks are always taken in field order: parties, queue, matches, names,
+// lobbies, webhooks, tournaments, season, reports, recent_matches, guilds,
+// audit_log.
+// profiles are sharded; a profile guard is never held across an await
+struct AppState {
+    profiles: DashMap<Uuid, Profile>,
+    parties: Mutex<HashMap<Uuid, Party>>,
+    // one isolated queue per game mode
+    queue: RwLock<HashMap<GameMode, ModeQueue>>,
+    matches: Mutex<HashMap<Uuid, MatchInfo>>,
+    // normalized name -> profile id. held while a profile is added, renamed
+    // or removed so the two maps never disagree. shared by the shards of an
+    // instance, so names are unique across regions
+    name_index: Arc<Mutex<HashMap<String, Uuid>>>,
+    // external id -> profile id, only written under `name_index` too
+    external_ids: DashMap<String, Uuid>,
+    // queue entries of pending matches, put back in the queue with their
+    // original timestamps if the ready check fails
+    lobbies: Mutex<HashMap<Uuid, Vec<QueueEntry>>>,
+    config: Config,
+    // how long the most recently matched players waited, oldest first
+    wait_times: Mutex<VecDeque<Duration>>,
+    // player notifications, fanned out to every websocket connection
+    events: broadcast::Sender<Notification>,
+    // latest opponents of each player, oldest first, capped at
+    // `config.recent_opponents_limit`
+    recent_opponents: DashMap<Uuid, VecDeque<Uuid>>,
+    // the mode whose queue just changed, for position streams
+    queue_changes: broadcast::Sender<GameMode>,
+    // the match whose stored state just changed, for server address streams
+    match_changes: broadcast::Sender<Uuid>,
+    // mirrors every write: to the database when DATABASE_URL or POSTGRES_URL
+    // is set, else to a `db::MemoryStore`
+    store: Arc<dyn db::MatchmakerStore>,
+    // shares the queue with other instances when REDIS_URL is set
+    redis: Option<redis_queue::RedisQueue>,
+    // verifies the bearer tokens of write requests
+    jwt_key: jsonwebtoken::DecodingKey,
+    // provider keys, with OAUTH2_JWKS_URL set
+    jwks: Option<oauth::Jwks>,
+    enqueue_limits: Mutex<HashMap<IpAddr, rate_limit::RateWindow>>,
+    // per-address request windows for `rate_limit::limit_requests`
+    ip_limits: DashMap<IpAddr, rate_limit::WindowState>,
+    // shared by the shards of an instance
+    metrics: Arc<metrics::Metrics>,
+    // set on SIGTERM so /ready starts failing while requests drain
+    shutting_down: AtomicBool,
+    // toggled by /admin/maintenance, see maintenance.rs
+    maintenance: AtomicBool,
+    webhooks: Mutex<Vec<Webhook>>,
+    // by URL, shared with the delivery tasks
+    webhook_circuits: Arc<webhooks::Circuits>,
+    // posts started and completed matches, with DISCORD_WEBHOOK_URL set
+    discord: Option<discord::DiscordWebhookNotifier>,
+    // shared client for webhook deliveries
+    http: reqwest::Client,
+    // in memory only; their matches are stored like any other
+    tournaments: Mutex<HashMap<Uuid, Tournament>>,
+    // numbered from 1; a reset starts the next one
+    season: Mutex<Season>,
+    // in memory only, oldest first
+    reports: Mutex<Vec<Report>>,
+    // creation time and mode of the matches made in the last
+    // RECENT_MATCHES_WINDOW, oldest first
+    recent_matches: Mutex<VecDeque<(Instant, GameMode)>>,
+    guilds: Mutex<HashMap<Uuid, Guild>>,
+    // oldest first, at most audit::AUDIT_LOG_MAX entries
+    audit_log: Mutex<VecDeque<AuditEntry>>,
+    // a std lock: batches are recorded as they are staged, under the state
+    // locks
+    event_log: std::sync::Mutex<EventLog>,
+    // writes staged under the state locks, oldest first, so they reach the
+    // store in the order the changes were made. a std lock: it is taken
+    // under them
+    staged: std::sync::Mutex<VecDeque<Vec<DbOp>>>,
+    // held while staged writes are applied, one batch at a time
+    writer: Mutex<()>,
+    // from `config.matching_strategies`
+    strategies: HashMap<GameMode, Arc<dyn strategy::MatchingStrategy>>,
+    // from `config.draft_orders`
+    draft_orders: HashMap<GameMode, Vec<draft::DraftPhase>>,
+    // every random choice the matcher makes, so a seeded state replays
+    // them. a std lock: it is taken while matching, under the queue lock
+    rng: std::sync::Mutex<StdRng>,
+    // always empty in a tenant's own state
+    tenants: DashMap<Uuid, Arc<tenants::TenantState>>,
+    // with `config.shard_by_region`, the states requests are handed to; None
+    // in the shards themselves
+    shards: Option<shards::ShardedAppState>,
+    // in a shard, where it records what it holds for request routing
+    home: Option<shards::Home>,
+}
+
+impl AppState {
+    // the state for `config`, starting from what was loaded from the database.
+    // panics without a JWT secret, like the rest of startup
+    fn new(config: Config, store: Arc<dyn db::MatchmakerStore>, mut loaded: db::Loaded) -> AppState {
+        let jwt_secret = config.jwt_secret.clone().expect("JWT_SECRET must be set");
+
+        // profiles stored before peaks were tracked start from their current mmr,
+        // and those stored before games were counted still have their record
+        for p in loaded.profiles.values_mut() {
+            p.fill_mode_mmr();
+            p.season_peak_mmr = p.season_peak_mmr.max(p.best_ranked_mmr());
+            p.peak_mmr = p.peak_mmr.max(p.best_ranked_mmr());
+            p.games_played = p.games_played.max(p.wins + p.losses + p.draws);
+        }
+        let event_log = EventLog::new(&loaded);
+        let strategies = config
+            .matching_strategies
+            .iter()
+            .map(|(mode, spec)| {
+                let strategy = strategy::parse(spec)
+                    .unwrap_or_else(|e| panic!("invalid matching_strategies.{mode:?}: {e}"));
+                (*mode, strategy)
+            })
+            .collect();
+        let draft_orders = config
+            .draft_orders
+            .iter()
+            .map(|(mode, spec)| {
+                let order = draft::parse(spec)
+                    .unwrap_or_else(|e| panic!("invalid draft_orders.{mode:?}: {e}"));
+                (*mode, order)
+            })
+            .collect();
+        let season = loaded.season.take().unwrap_or_else(|| Season {
+            number: 1,
+            started_at: Utc::now(),
+        });
+        let mut name_index = HashMap::new();
+        for p in loaded.profiles.values() {
+            if let Some(other) = name_index.insert(normalize_name(&p.name), p.id) {
+                tracing::warn!(profile_id = %p.id, %other, name = %p.name, "stored profiles share a name");
+            }
+        }
+        let external_ids = external_ids(loaded.profiles.values());
+
+        AppState {
+            profiles: loaded.profiles.into_iter().collect(),
+            name_index: Arc::new(Mutex::new(name_index)),
+            external_ids,
+            parties: Mutex::new(HashMap::new()),
+            queue: RwLock::new(loaded.queues),
+            matches: Mutex::new(loaded.matches),
+            lobbies: Mutex::new(HashMap::new()),
+            wait_times: Mutex::new(VecDeque::with_capacity(WAIT_TIME_SAMPLES)),
+            events: broadcast::channel(256).0,
+            queue_changes: broadcast::channel(256).0,
+            match_changes: broadcast::channel(256).0,
+            recent_opponents: DashMap::new(),
+            store,
+            redis: None,
+            jwt_key: auth::decoding_key(&jwt_secret),
+            jwks: config.oauth2_jwks_url.clone().map(|url| {
+                oauth::Jwks::new(
+                    url,
+                    config.oauth2_audience.clone(),
+                    config.oauth2_issuer.clone(),
+                    oauth::JWKS_REFRESH_MIN,
+                )
+            }),
+            enqueue_limits: Mutex::new(HashMap::new()),
+            ip_limits: DashMap::new(),
+            metrics: Arc::new(metrics::Metrics::new()),
+            shutting_down: AtomicBool::new(false),
+            maintenance: AtomicBool::new(false),
+            webhooks: Mutex::new(Vec::new()),
+            webhook_circuits: Arc::new(webhooks::Circuits::new(config.webhook_half_open())),
+            discord: discord::DiscordWebhookNotifier::from_config(&config),
+            http: reqwest::Client::new(),
+            tournaments: Mutex::new(HashMap::new()),
+            season: Mutex::new(season),
+            reports: Mutex::new(Vec::new()),
+            recent_matches: Mutex::new(VecDeque::new()),
+            guilds: Mutex::new(loaded.guilds),
+            audit_log: Mutex::new(VecDeque::new()),
+            event_log: std::sync::Mutex::new(event_log),
+            staged: std::sync::Mutex::new(VecDeque::new()),
+            writer: Mutex::new(()),
+            strategies,
+            draft_orders,
+            rng: std::sync::Mutex::new(StdRng::from_entropy()),
+            tenants: DashMap::new(),
+            shards: None,
+            home: None,
+            config,
+        }
+    }
+
+    // the same state with its random choices seeded, for reproducible tests
+    // and benchmarks
+    fn with_seed(self, seed: u64) -> AppState {
+        AppState {
+            rng: std::sync::Mutex::new(StdRng::seed_from_u64(seed)),
+            ..self
+        }
+    }
+
+    // effective mmr window for a player that has been waiting for `waited`
+    fn mmr_window(&self, waited: Duration) -> u32 {
+        let c = &self.config;
+        let expanded = c.mmr_range as f64 + c.mmr_range_expand_rate * waited.as_secs_f64();
+        (expanded as u32).min(c.mmr_range_max)
+    }
+
+    // once a player's window is fully expanded they may be matched cross-region
+    fn region_relaxed(&self, waited: Duration) -> bool {
+        self.mmr_window(waited) >= self.config.mmr_range_max
+    }
+
+    // whether `id` may join parties, queues and matches
+    fn check_active(&self, id: &Uuid) -> Result<(), AppError> {
+        match self.profiles.get(id) {
+            None => Err(AppError::UnknownProfile),
+            Some(p) if p.deactivated => Err(AppError::ProfileDeactivated),
+            Some(_) => Ok(()),
+        }
+    }
+
+    // marks the profile deactivated and detaches it from parties, queues and
+    // open matches, which are cancelled with `reason` and `note`
+    async fn deactivate(&self, id: Uuid, reason: CancelReason, note: &str) -> Result<(), AppError> {
+        // the profile is only deactivated once these locks are held, so anything
+        // that checks it under one of them sees it either active or fully detached.
+        // it keeps its name so a reactivation cannot collide
+        let mut parties = self.parties.lock().await;
+        let mut queue = self.queue.write().await;
+        let mut matches = self.matches.lock().await;
+
+        let profile = {
+            let Some(mut p) = self.profiles.get_mut(&id) else {
+                return Err(AppError::ProfileNotFound);
+            };
+            if p.deactivated {
+                return Err(AppError::ProfileDeactivated);
+            }
+            p.deactivated = true;
+            p.clone()
+        };
+        self.recent_opponents.remove(&id);
+        // leave any party, disbanding it when fewer than two members remain
+        parties.retain(|_, party| {
+            party.members.retain(|m| *m != id);
+            if party.leader == id {
+                if let Some(next) = party.members.first() {
+                    party.leader = *next;
+                }
+            }
+            party.members.len() >= 2
+        });
+        let mut ops = vec![DbOp::UpsertProfile(profile)];
+        let mut changed = Vec::new();
+        for (mode, q) in queue.iter_mut() {
+            q.retain(|e| {
+                let keep = !e.members.contains(&id);
+                if !keep {
+                    ops.push(DbOp::DeleteQueueEntry(e.profile_id));
+                    changed.push(*mode);
+                }
+                keep
+            });
+        }
+        let mut cancelled = Vec::new();
+        let mut lobbies = self.lobbies.lock().await;
+        for m in matches.values_mut() {
+            if m.involves(id) && m.status.can_transition_to(MatchStatus::Cancelled) {
+                let lobby = lobbies.remove(&m.id);
+                cancel_locked(self, &mut queue, m, reason, Some(note.to_string()), lobby, &mut ops);
+                cancelled.push(m.clone());
+            }
+        }
+        drop(lobbies);
+        self.stage(ops);
+        drop((parties, queue, matches));
+        self.flush().await;
+        for mode in changed {
+            let _ = self.queue_changes.send(mode);
+        }
+        for m in &cancelled {
+            announce_cancel(self, m).await;
+        }
+        Ok(())
+    }
+
+    // refuses if any of `players` already takes part in as many Pending or
+    // Active matches as allowed. a linear scan over every match; if it ever
+    // shows up in profiles, keep a player -> open matches index instead
+    fn check_match_quota(
+        &self,
+        matches: &HashMap<Uuid, MatchInfo>,
+        players: &[Uuid],
+    ) -> Result<(), AppError> {
+        let open = |id: &Uuid| {
+            matches
+                .values()
+                .filter(|m| matches!(m.status, MatchStatus::Pending | MatchStatus::Active))
+                .filter(|m| m.involves(*id))
+                .count()
+        };
+        if players.iter().any(|id| open(id) >= self.config.max_active_matches_per_player) {
+            return Err(AppError::ActiveMatchLimit);
+        }
+        Ok(())
+    }
+
+    // how close the two sides' average mmr on `mode` is, relative to the widest
+    // gap matchmaking ever accepts. forced matches can go below that, so the
+    // score is clamped at 0
+    fn quality(&self, team1: &[Uuid], team2: &[Uuid], mode: GameMode) -> Option<f64> {
+        let delta = (team_mmr(&self.profiles, team1, mode)? - team_mmr(&self.profiles, team2, mode)?).abs();
+        let max_delta = self.config.mmr_range_max as f64;
+        if max_delta == 0.0 {
+            return Some(if delta == 0.0 { 1.0 } else { 0.0 });
+        }
+        Some((1.0 - delta / max_delta).clamp(0.0, 1.0))
+    }
+
+    async fn find_match(&self, id: Uuid) -> Result<MatchInfo, AppError> {
+        self.matches.lock().await.get(&id).cloned().ok_or(AppError::MatchNotFound)
+    }
+
+    // current tier of each of `players` on `mode`'s rating track
+    fn tiers<'a>(
+        &self,
+        players: impl IntoIterator<Item = &'a Uuid>,
+        mode: GameMode,
+    ) -> HashMap<Uuid, RankTier> {
+        players
+            .into_iter()
+            .filter_map(|id| {
+                let p = self.profiles.get(id)?;
+                Some((*id, rank::mmr_to_tier(get_mode_mmr(&p, mode))))
+            })
+            .collect()
+    }
+
+    // whether anyone in `players` recently faced anyone in `others`
+    fn played_recently(&self, players: &[Uuid], others: &[Uuid]) -> bool {
+        players.iter().any(|id| {
+            self.recent_opponents
+                .get(id)
+                .is_some_and(|recent| recent.iter().any(|o| others.contains(o)))
+        })
+    }
+
+    fn guild_of(&self, id: &Uuid) -> Option<Uuid> {
+        self.profiles.get(id).and_then(|p| p.guild_id)
+    }
+
+    // ids of the profiles in `guild`, sorted
+    fn guild_members(&self, guild: Uuid) -> Vec<Uuid> {
+        let mut members: Vec<Uuid> = self
+            .profiles
+            .iter()
+            .filter(|p| p.guild_id == Some(guild))
+            .map(|p| *p.key())
+            .collect();
+        members.sort();
+        members
+    }
+
+    // whether anyone in `players` blocked anyone in `others`, or the other
+    // way round
+    fn blocked_between(&self, players: &[Uuid], others: &[Uuid]) -> bool {
+        let blocks = |from: &[Uuid], to: &[Uuid]| {
+            from.iter().any(|id| {
+                self.profiles
+                    .get(id)
+                    .is_some_and(|p| to.iter().any(|o| p.blocked.contains(o)))
+            })
+        };
+        blocks(players, others) || blocks(others, players)
+    }
+
+    // one time-to-match sample for the enqueue wait estimate
+    async fn record_wait(&self, waited: Duration) {
+        let mut wait_times = self.wait_times.lock().await;
+        if wait_times.len() == WAIT_TIME_SAMPLES {
+            wait_times.pop_front();
+        }
+        wait_times.push_back(waited);
+    }
+
+    // counts a new match in the metrics and the queue stats
+    async fn count_created(&self, m: &MatchInfo) {
+        self.metrics.matches_created.inc();
+        let mut recent = self.recent_matches.lock().await;
+        let now = Instant::now();
+        while recent.front().is_some_and(|(at, _)| now - *at > RECENT_MATCHES_WINDOW) {
+            recent.pop_front();
+        }
+        recent.push_back((now, m.mode));
+    }
+
+    // records both sides of `m` as each other's latest opponents
+    fn remember_opponents(&self, m: &MatchInfo) {
+        let limit = self.config.recent_opponents_limit;
+        for (team, others) in [(&m.team1, &m.team2), (&m.team2, &m.team1)] {
+            for id in team {
+                let mut recent = self.recent_opponents.entry(*id).or_default();
+                for other in others {
+                    recent.retain(|o| o != other);
+                    recent.push_back(*other);
+                }
+                while recent.len() > limit {
+                    recent.pop_front();
+                }
+            }
+        }
+    }
+
+    // sends `n` to the listed players' connections, on every instance
+    fn notify(&self, n: Notification) {
+        if let Some(redis) = &self.redis {
+            redis.publish(&n);
+        }
+        let _ = self.events.send(n);
+    }
+
+    // appends `ops` to the event log and queues them for the next `flush`.
+    // called under the locks of the state they change, so events and writes
+    // to the same rows keep the order of the changes without the locks being
+    // held across the I/O
+    fn stage(&self, ops: Vec<DbOp>) {
+        if !ops.is_empty() {
+            self.event_log.lock().unwrap().record(&ops);
+            self.staged.lock().unwrap().push_back(ops);
+        }
+    }
+
+    // stages `ops` and writes them, for changes made without holding a lock
+    async fn persist(&self, ops: Vec<DbOp>) {
+        self.stage(ops);
+        self.flush().await;
+    }
+
+    // writes everything staged so far. call it once the locks are released; a
+    // caller whose batch another one is already writing waits for that write
+    // to finish
+    async fn flush(&self) {
+        let _writer = self.writer.lock().await;
+        loop {
+            let Some(ops) = self.staged.lock().unwrap().pop_front() else {
+                break;
+            };
+            self.write(ops).await;
+        }
+    }
+
+    // writes `ops` to the store. failures are logged and the in-memory state
+    // is kept as is
+    async fn write(&self, ops: Vec<DbOp>) {
+        if let Some(redis) = &self.redis {
+            redis.mirror(&ops).await;
+        }
+        for op in &ops {
+            match op {
+                DbOp::UpsertMatch(m) => {
+                    self.record_home(m.id);
+                    let _ = self.match_changes.send(m.id);
+                }
+                DbOp::UpsertProfile(p) => self.record_home(p.id),
+                DbOp::UpsertGuild(g) => self.record_home(g.id),
+                _ => {}
+            }
+        }
+        if let Err(e) = self.store.apply(ops).await {
+            tracing::error!("failed to persist state: {e}");
+        }
+    }
+}
+
+// number of recent wait times kept for the enqueue estimate
+const WAIT_TIME_SAMPLES: usize = 100;
+
+// the team sizes a queue request may ask for
+const TEAM_SIZES: [u8; 3] = [1, 2, 5];
+
+// span of the matches_created_last_minute queue stat
+const RECENT_MATCHES_WINDOW: Duration = Duration::from_secs(60);
+
+// a solo player or a whole party waiting in a queue
+#[derive(Debug, Clone)]
+struct QueueEntry {
+    // the solo player or the party leader
+    profile_id: Uuid,
+    party_id: Option<Uuid>,
+    members: Vec<Uuid>,
+    // the player's mmr, or the party average, at enqueue time
+    mmr: u32,
+    region: Region,
+    // monotonic, used for wait computations
+    queued_at: Instant,
+    // wall clock, reported to clients
+    queued_since: DateTime<Utc>,
+    last_heartbeat: Instant,
+    // from the solo player or party leader at enqueue time
+    lane: Lane,
+    // the queue request's team size; entries only meet others of the same
+    team_size: Option<u8>,
+}
+
+// an event addressed to the listed players
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Notification {
+    profile_ids: Vec<Uuid>,
+    event: PlayerEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum PlayerEvent {
+    Matched { r#match: Box<MatchInfo> },
+    Dequeued { reason: DequeueReason },
+    ServerAssigned { match_id: Uuid, address: SocketAddr },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum DequeueReason {
+    Timeout,
+    // no heartbeat for `stale_timeout`
+    Stale,
+    AdminFlush,
+}
+
+// v1 request and response bodies, from here down to `WsParams`
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateProfile {
+    name: String,
+    // starting rating on every mode
+    #[serde(default = "default_mmr")]
+    mmr: u32,
+    #[serde(default)]
+    region: Region,
+}
+
+const DEFAULT_MMR: u32 = 1000;
+
+fn default_mmr() -> u32 {
+    DEFAULT_MMR
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct UpdateProfile {
+    name: Option<String>,
+    // sets every ranked, or every casual, mode at once. the mmr fields
+    // need the admin key
+    ranked_mmr: Option<u32>,
+    casual_mmr: Option<u32>,
+    // applied after the two above
+    mmr_by_mode: Option<HashMap<GameMode, u32>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct QueueRequest {
+    profile_id: Uuid,
+    // set by a party leader to queue the whole party
+    #[serde(default)]
+    party_id: Option<Uuid>,
+    #[serde(default)]
+    mode: GameMode,
+    // players per side, 1, 2 or 5: solo players and parties are pooled into
+    // two sides of this size. without it a side is the entry itself
+    #[serde(default)]
+    team_size: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateParty {
+    members: Vec<Uuid>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct QueueFilter {
+    mode: Option<GameMode>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct QueueStats {
+    // players waiting, party members included
+    depth: usize,
+    // over the entries waiting now; None when the queue is empty
+    avg_wait_seconds: Option<f64>,
+    oldest_entry_seconds: Option<f64>,
+    matches_created_last_minute: u32,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Winner {
+    Player1,
+    Player2,
+    Draw,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ReportResult {
+    winner: Winner,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+struct CancelMatch {
+    #[serde(default)]
+    reason: CancelReason,
+    note: Option<String>,
+}
+
+// timeout and error are not the players' doing, so they go back in the queue
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum CancelReason {
+    #[default]
+    PlayerRequest,
+    Admin,
+    Timeout,
+    Error,
+}
+
+impl CancelReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CancelReason::PlayerRequest => "player_request",
+            CancelReason::Admin => "admin",
+            CancelReason::Timeout => "timeout",
+            CancelReason::Error => "error",
+        }
+    }
+
+    fn requeues(self) -> bool {
+        matches!(self, CancelReason::Timeout | CancelReason::Error)
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct MetadataEntry {
+    key: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SubmitFeedback {
+    player_id: Uuid,
+    match_quality: u8,
+    opponent_sportsmanship: Option<u8>,
+    comment: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateGuild {
+    name: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct AddGuildMember {
+    profile_id: Uuid,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct GuildView {
+    #[serde(flatten)]
+    guild: Guild,
+    members: Vec<Uuid>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct FriendRequest {
+    friend_id: Uuid,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum Presence {
+    InQueue,
+    InMatch,
+    Idle,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct FriendStatus {
+    profile: Profile,
+    status: Presence,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateReport {
+    reporter_id: Uuid,
+    reported_id: Uuid,
+    match_id: Option<Uuid>,
+    reason: ReportReason,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FeedbackQuery {
+    // only matches of this mode; all modes when absent
+    mode: Option<GameMode>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct FeedbackStats {
+    reviews: usize,
+    // None when there is nothing to average
+    avg_match_quality: Option<f64>,
+    avg_opponent_sportsmanship: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct EnqueueResponse {
+    status: &'static str,
+    lane: Lane,
+    queue_position: usize,
+    estimated_wait_seconds: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct QueueView {
+    profile_id: Uuid,
+    party_id: Option<Uuid>,
+    region: Region,
+    mode: GameMode,
+    lane: Lane,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct QueuePosition {
+    mode: GameMode,
+    position: usize,
+    queued_since: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Pagination {
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CursorPagination {
+    #[serde(default = "default_limit")]
+    limit: usize,
+    after: Option<Uuid>,
+    // order the cursor walks in
+    #[serde(default)]
+    sort: SortOrder,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct FlushQueue {
+    // every mode when absent
+    mode: Option<GameMode>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Flushed {
+    // players, party members included
+    removed: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct MaintenanceStatus {
+    maintenance: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditQuery {
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+    after: Option<Uuid>,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct EventQuery {
+    #[serde(default = "default_event_limit")]
+    limit: usize,
+    after: Option<Uuid>,
+}
+
+fn default_event_limit() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    #[default]
+    CreatedAtAsc,
+    CreatedAtDesc,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum LeaderboardSort {
+    // current mmr of the requested mode
+    #[default]
+    Mmr,
+    // all-time best mmr of the requested mode
+    PeakMmr,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NameSearch {
+    // case-insensitive substring of the name, at least 2 characters
+    name: Option<String>,
+    // instead of a name: the profile linked to this external id, if any
+    external_id: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LeaderboardQuery {
+    #[serde(default = "default_leaderboard_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+    // picks the rating track; defaults to RankedSolo
+    #[serde(default)]
+    mode: GameMode,
+    #[serde(default)]
+    exclude_provisional: bool,
+    #[serde(default)]
+    sort: LeaderboardSort,
+}
+
+fn default_leaderboard_limit() -> usize {
+    25
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct LeaderboardEntry {
+    rank: usize,
+    profile: ProfileView,
+    mmr: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Leaderboard {
+    // rated players overall, not just on this page
+    total: usize,
+    entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[aliases(
+    MatchPage = Page<MatchInfo>,
+    AuditPage = Page<AuditEntry>,
+    EventPage = Page<LoggedEvent>
+)]
+struct Page<T> {
+    items: Vec<T>,
+    next_cursor: Option<Uuid>,
+}
+
+// keyset page over `sorted`: up to `limit` items following the one whose id
+// is `after`. None when the cursor does not match any item
+fn paginate<T: Clone>(
+    sorted: &[T],
+    id_of: impl Fn(&T) -> Uuid,
+    after: Option<Uuid>,
+    limit: usize,
+) -> Option<Page<T>> {
+    let start = match after {
+        Some(cursor) => sorted.iter().position(|item| id_of(item) == cursor)? + 1,
+        None => 0,
+    };
+    let items: Vec<T> = sorted.iter().skip(start).take(limit).cloned().collect();
+    let next_cursor = if start + items.len() < sorted.len() {
+        items.last().map(&id_of)
+    } else {
+        None
+    };
+    Some(Page { items, next_cursor })
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Probe {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+// how long the readiness probe waits for a lock before reporting unavailable
+const READY_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ForceMatch {
+    player1: Uuid,
+    player2: Uuid,
+    #[serde(default)]
+    mode: GameMode,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct AssignServer {
+    // ip:port, e.g. 192.168.1.100:7777
+    address: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetVip {
+    vip: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SeasonReset {
+    // share of the distance to `baseline` removed from every ranked mmr
+    decay_fraction: f64,
+    baseline: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Dequeued {
+    removed_from: Vec<GameMode>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct QualityStats {
+    avg: f64,
+    p10: f64,
+    p90: f64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct QualityQuery {
+    // newest matches to look at, 100 by default
+    #[serde(default = "default_quality_window")]
+    last: usize,
+}
+
+fn default_quality_window() -> usize {
+    100
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct DurationStats {
+    avg_seconds: f64,
+    p50: f64,
+    p95: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct PositionUpdate {
+    position: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct WsParams {
+    profile_id: Uuid,
+}
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// TOML file with any of the config fields; environment variables win
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Restore the snapshot at `snapshot_path` before serving
+    #[arg(long)]
+    snapshot_on_startup: bool,
+    /// Start from the state rebuilt from a JSON array of /v1/admin/events
+    /// items, oldest first, instead of an empty one. needs no database
+    #[arg(long)]
+    replay_events: Option<PathBuf>,
+}
+
+// limits on match metadata, in characters
+const METADATA_KEY_MAX: usize = 64;
+const METADATA_VALUE_MAX: usize = 512;
+
+// longest guild name, in characters
+const GUILD_NAME_MAX: usize = 32;
+
+// longest feedback comment, in characters
+const FEEDBACK_COMMENT_MAX: usize = 1000;
+
+// reports older than this no longer count towards a suspension
+const REPORT_WINDOW_DAYS: i64 = 7;
+
+// how often websocket clients are pinged to detect dead connections
+const WS_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+// the whole service as started by the binary: configuration from the command
+// line, file and environment, then HTTP and gRPC until shutdown
+pub async fn run() {
+    let args = Args::parse();
+    let config = Config::load(args.config.as_deref());
+    telemetry::init(&config);
+
+    // without DATABASE_URL everything is kept in memory only
+    let db = match config.database() {
+        Some(url) => Some(db::connect(url, config.db_max_connections).await.unwrap()),
+        None => None,
+    };
+    let loaded = match (&db, &args.replay_events) {
+        (Some(_), Some(_)) => panic!("--replay-events cannot be combined with a database"),
+        (Some(db), None) => db.load().await.unwrap(),
+        (None, Some(path)) => {
+            let data = std::fs::read(path)
+                .unwrap_or_else(|e| panic!("cannot read {}: {e}", path.display()));
+            let events: Vec<LoggedEvent> = serde_json::from_slice(&data)
+                .unwrap_or_else(|e| panic!("invalid event log {}: {e}", path.display()));
+            event_log::replay(events.iter().map(|e| &e.event))
+        }
+        (None, None) => db::Loaded::default(),
+    };
+    let store = db.unwrap_or_else(db::memory);
+
+    // with REDIS_URL the queue is shared with the other instances
+    let redis = match &config.redis_url {
+        Some(_) if config.shard_by_region => panic!("REDIS_URL cannot be combined with SHARD_BY_REGION"),
+        Some(url) => Some(redis_queue::RedisQueue::connect(url).await.unwrap()),
+        None => None,
+    };
+
+    let state = if config.shard_by_region {
+        Arc::new(shards::sharded(config, store, loaded))
+    } else {
+        let mut state = AppState::new(config, store, loaded);
+        state.redis = redis;
+        Arc::new(state)
+    };
+
+    // each shard from its own file
+    let restoring = if args.snapshot_on_startup { state.shard_states() } else { Vec::new() };
+    for shard in restoring {
+        let restored = snapshot::restore(shard)
+            .await
+            .unwrap_or_else(|e| panic!("cannot restore snapshot: {e}"));
+        tracing::info!(
+            path = %restored.path,
+            profiles = restored.profiles,
+            queued = restored.queued,
+            matches = restored.matches,
+            "snapshot restored"
+        );
+    }
+
+    spawn_background(&state);
+    for shard in state.shards.iter().flat_map(|s| &s.shards) {
+        spawn_background(shard);
+    }
+
+    let app = app(state.clone());
+
+    let host: IpAddr = state.config.host.parse().unwrap();
+    tokio::spawn(grpc::serve(state.clone(), SocketAddr::from((host, state.config.grpc_port))));
+    let addr = SocketAddr::from((host, state.config.port));
+    tracing::info!("Listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal(state.clone()))
+        .await
+        .unwrap();
+
+    let (mut queued, mut open) = (0, 0);
+    for shard in state.shard_states() {
+        queued += shard.queue.read().await.values().map(|q| q.len()).sum::<usize>();
+        open += shard
+            .matches
+            .lock()
+            .await
+            .values()
+            .filter(|m| matches!(m.status, MatchStatus::Pending | MatchStatus::Active))
+            .count();
+    }
+    tracing::info!(queued, open_matches = open, "shut down");
+    // shards share one pool
+    state.shard_states()[0].store.close().await;
+    telemetry::shutdown();
+}
+
+// the periodic tasks that keep `state` going: stale and timed out queue
+// entries, rating decay, idle rate limit windows and, if configured, the
+// background matcher and the other instances' changes from Redis
+fn spawn_background(state: &Arc<AppState>) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut tasks = vec![
+        tokio::spawn(sweep_queue(state.clone())),
+        tokio::spawn(decay_inactive(state.clone())),
+        tokio::spawn(rate_limit::forget_idle(state.clone())),
+    ];
+    if let Some(interval) = state.config.matching_interval() {
+        tasks.push(tokio::spawn(matchmaking::match_queues(state.clone(), interval)));
+    }
+    if state.redis.is_some() {
+        tasks.push(tokio::spawn(redis_queue::follow(state.clone())));
+    }
+    tasks
+}
+
+// the HTTP app over fresh in-memory state, as `run` serves it but without
+// the database, gRPC or the background sweeps. for tests and embedding. the
+// background matcher still runs unless `matching_interval_ms` is 0, so this
+// has to be called inside a Tokio runtime
+pub fn router(config: Config) -> Router {
+    embedded(AppState::new(config, db::memory(), db::Loaded::default()))
+}
+
+// like `router`, with random choices seeded by `seed`
+pub fn seeded_router(config: Config, seed: u64) -> Router {
+    embedded(AppState::new(config, db::memory(), db::Loaded::default()).with_seed(seed))
+}
+
+fn embedded(state: AppState) -> Router {
+    let state = Arc::new(state);
+    if let Some(interval) = state.config.matching_interval() {
+        tokio::spawn(matchmaking::match_queues(state.clone(), interval));
+    }
+    app(state)
+}
+
+// every HTTP route with the middleware shared by all of them
+fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/", get(routes::api_root))
+        .nest(
+            "/v1",
+            routes::v1::v1_router(state.clone())
+                .layer(middleware::from_fn_with_state(state.clone(), audit::record))
+                // with shards, hands the request over before anything else runs
+                .layer(middleware::from_fn_with_state(state.clone(), shards::route)),
+        )
+        .merge(routes::unversioned_redirects(state.clone()))
+        .merge(graphql::router(state.clone()))
+        .merge(tenants::router(state.clone()))
+        .merge(shards::router(state.clone()))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", openapi::ApiDoc::openapi()))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::reject_during_maintenance,
+        ))
+        // every route, tenants' included
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit_requests,
+        ))
+        // renames keys before msgpack re-encodes, so both formats match
+        .layer(middleware::from_fn(naming::negotiate))
+        // outside maintenance and auth so their errors are re-encoded too
+        .layer(middleware::from_fn(msgpack::negotiate))
+        // outermost, so even rejected requests get an id
+        .layer(middleware::from_fn(request_id::propagate))
+        // answers preflights before any redirect or auth check
+        .layer(cors::layer(&state.config.cors_origins))
+        .with_state(state.clone())
+}
+
+// resolves once the drain window after SIGTERM (or ctrl-c) has passed. until
+// then the server keeps serving, but /ready reports unavailable
+async fn shutdown_signal(state: Arc<AppState>) {
+    let drain = state.config.shutdown_drain();
+    let mut term = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = term.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    tracing::info!("shutdown requested, draining for {:?}", drain);
+    state.shutting_down.store(true, Ordering::SeqCst);
+    tokio::time::sleep(drain).await;
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/profiles",
+    tag = "profiles",
+    request_body = CreateProfile,
+    responses(
+        (status = 201, description = "Profile created, linked to the token's external id if it is a provider token", body = Profile),
+        (status = 401, description = "Missing or invalid token", body = ApiError),
+        (status = 409, description = "Name already taken, or the external id already has a profile", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_profile(
+    State(state): State<Arc<AppState>>,
+    external: Option<Extension<ExternalIdentity>>,
+    Json(payload): Json<CreateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let external_id = external.map(|Extension(ExternalIdentity(sub))| sub);
+    let profile = new_linked_profile(&state, payload, external_id).await?;
+    Ok((StatusCode::CREATED, Json(profile)))
+}
+
+// shared by HTTP, gRPC and GraphQL
+async fn new_profile(state: &AppState, payload: CreateProfile) -> Result<Profile, AppError> {
+    new_linked_profile(state, payload, None).await
+}
+
+// a provider's `sub` gets at most one profile
+async fn new_linked_profile(
+    state: &AppState,
+    payload: CreateProfile,
+    external_id: Option<String>,
+) -> Result<Profile, AppError> {
+    let key = normalize_name(&payload.name);
+    let mut names = state.name_index.lock().await;
+    if names.contains_key(&key) {
+        return Err(AppError::NameTaken);
+    }
+    if external_id.as_ref().is_some_and(|e| state.external_ids.contains_key(e)) {
+        return Err(AppError::ExternalIdTaken);
+    }
+
+    let id = Uuid::new_v4();
+    let profile = Profile {
+        id,
+        name: payload.name,
+        ranked_mmr: payload.mmr,
+        casual_mmr: payload.mmr,
+        mmr_by_mode: GameMode::ALL.into_iter().map(|mode| (mode, payload.mmr)).collect(),
+        wins: 0,
+        losses: 0,
+        draws: 0,
+        region: payload.region,
+        created_at: Utc::now(),
+        season_peak_mmr: payload.mmr,
+        peak_mmr: payload.mmr,
+        peak_mmr_by_mode: GameMode::ALL.into_iter().map(|mode| (mode, payload.mmr)).collect(),
+        games_played: 0,
+        last_game_at: None,
+        decayed_until: None,
+        friends: HashSet::new(),
+        friend_requests: HashSet::new(),
+        blocked: HashSet::new(),
+        guild_id: None,
+        external_id,
+        current_loss_streak: 0,
+        deactivated: false,
+        vip: false,
+        penalty_count: 0,
+        ban_until: None,
+    };
+    state.profiles.insert(id, profile.clone());
+    names.insert(key, id);
+    if let Some(external_id) = &profile.external_id {
+        state.external_ids.insert(external_id.clone(), id);
+    }
+    state.stage(vec![DbOp::UpsertProfile(profile.clone())]);
+    drop(names);
+    state.flush().await;
+    Ok(profile)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/profiles/{id}",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "The profile with its stats", body = ProfileView),
+        (status = 404, description = "Profile not found", body = ApiError),
+        (status = 410, description = "Profile deactivated", body = ApiError),
+    )
+)]
+async fn get_profile(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.profiles.get(&id) {
+        None => Err(AppError::ProfileNotFound),
+        Some(p) if p.deactivated => Err(AppError::ProfileDeactivated),
+        Some(p) => Ok((StatusCode::OK, Json(ProfileView::from(p.clone())))),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/v1/profiles/{id}",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "Updated profile", body = ProfileView),
+        (status = 400, description = "Nothing to update", body = ApiError),
+        (status = 403, description = "Token does not belong to this profile, or a player changing mmr", body = ApiError),
+        (status = 404, description = "Profile not found", body = ApiError),
+        (status = 409, description = "Name already taken", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+async fn update_profile(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin = player.is_none();
+    check_owner(id, player)?;
+    let sets_mmr =
+        payload.ranked_mmr.is_some() || payload.casual_mmr.is_some() || payload.mmr_by_mode.is_some();
+    if payload.name.is_none() && !sets_mmr {
+        return Err(AppError::EmptyUpdate);
+    }
+    // players earn their mmr; only operators correct it
+    if sets_mmr && !admin {
+        return Err(AppError::MmrChangeForbidden);
+    }
+
+    let mut names = state.name_index.lock().await;
+    let Some(mut p) = state.profiles.get_mut(&id) else {
+        return Err(AppError::ProfileNotFound);
+    };
+    if p.deactivated {
+        return Err(AppError::ProfileDeactivated);
+    }
+    if let Some(name) = payload.name {
+        let key = normalize_name(&name);
+        if names.get(&key).is_some_and(|&owner| owner != id) {
+            return Err(AppError::NameTaken);
+        }
+        names.remove(&normalize_name(&p.name));
+        names.insert(key, id);
+        p.name = name;
+    }
+    // mmr is normally owned by the elo subsystem
+    let mut changes: Vec<(GameMode, u32)> = Vec::new();
+    for mode in GameMode::ALL {
+        let track = if mode.is_ranked() { payload.ranked_mmr } else { payload.casual_mmr };
+        changes.extend(track.map(|mmr| (mode, mmr)));
+    }
+    changes.extend(payload.mmr_by_mode.into_iter().flatten());
+    for (mode, mmr) in changes {
+        let old = get_mode_mmr(&p, mode);
+        tracing::warn!(profile_id = %id, ?mode, old, new = mmr, "mmr changed manually");
+        p.set_mode_mmr(mode, mmr);
+        p.raise_peaks(mode, mmr);
+    }
+    let profile = p.clone();
+    state.stage(vec![DbOp::UpsertProfile(profile.clone())]);
+    drop(p);
+    drop(names);
+    state.flush().await;
+    Ok((StatusCode::OK, Json(ProfileView::from(profile))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/profiles/{id}",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 204, description = "Profile deactivated"),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 404, description = "Profile not found", body = ApiError),
+        (status = 410, description = "Profile already deactivated", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+async fn delete_profile(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let reason = match player {
+        Some(_) => CancelReason::PlayerRequest,
+        None => CancelReason::Admin,
+    };
+    check_owner(id, player)?;
+    state.deactivate(id, reason, "profile deactivated").await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/profiles/{id}/reactivate",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "The profile, active again", body = ProfileView),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Profile not found", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn reactivate_profile(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = {
+        let mut p = state.profiles.get_mut(&id).ok_or(AppError::ProfileNotFound)?;
+        p.deactivated = false;
+        p.clone()
+    };
+    state.persist(vec![DbOp::UpsertProfile(profile.clone())]).await;
+    tracing::warn!(%admin, profile_id = %id, "profile reactivated");
+    Ok((StatusCode::OK, Json(ProfileView::from(profile))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/profiles/{id}/friends",
+    tag = "friends",
+    params(("id" = Uuid, Path, description = "Profile sending the request")),
+    request_body = FriendRequest,
+    responses(
+        (status = 204, description = "Request sent, or already pending"),
+        (status = 400, description = "Unknown player, or a request to oneself", body = ApiError),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 409, description = "Already friends", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn send_friend_request(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<FriendRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    if payload.friend_id == id {
+        return Err(AppError::SelfFriendRequest);
+    }
+    state.check_active(&id)?;
+    state.check_active(&payload.friend_id)?;
+    if state.blocked_between(&[id], &[payload.friend_id]) {
+        return Err(AppError::PlayerBlocked);
+    }
+
+    // the request is stored on the receiving profile
+    let friend = {
+        let mut f = state
+            .profiles
+            .get_mut(&payload.friend_id)
+            .ok_or(AppError::UnknownProfile)?;
+        if f.friends.contains(&id) {
+            return Err(AppError::AlreadyFriends);
+        }
+        f.friend_requests.insert(id);
+        f.clone()
+    };
+    state.persist(vec![DbOp::UpsertProfile(friend)]).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/profiles/{id}/friends/{friend_id}/accept",
+    tag = "friends",
+    params(
+        ("id" = Uuid, Path, description = "Profile that received the request"),
+        ("friend_id" = Uuid, Path, description = "Profile that sent it"),
+    ),
+    responses(
+        (status = 204, description = "Now friends"),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 404, description = "No pending request from this player", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn accept_friend_request(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path((id, friend_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AppError> {
+    if id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    state.check_active(&friend_id)?;
+
+    // one profile guard at a time: both could live in the same shard
+    let profile = {
+        let mut p = state.profiles.get_mut(&id).ok_or(AppError::ProfileNotFound)?;
+        if !p.friend_requests.remove(&friend_id) {
+            return Err(AppError::FriendNotFound);
+        }
+        p.friends.insert(friend_id);
+        p.clone()
+    };
+    let friend = {
+        let mut f = state.profiles.get_mut(&friend_id).ok_or(AppError::UnknownProfile)?;
+        f.friend_requests.remove(&id);
+        f.friends.insert(id);
+        f.clone()
+    };
+    state
+        .persist(vec![DbOp::UpsertProfile(profile), DbOp::UpsertProfile(friend)])
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/profiles/{id}/friends/{friend_id}",
+    tag = "friends",
+    params(
+        ("id" = Uuid, Path, description = "Profile id"),
+        ("friend_id" = Uuid, Path, description = "Friend, or the other side of a pending request"),
+    ),
+    responses(
+        (status = 204, description = "Friendship ended, or request declined or withdrawn"),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 404, description = "Neither friends nor a pending request", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn remove_friend(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path((id, friend_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AppError> {
+    if id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    let (profile, removed) = {
+        let mut p = state.profiles.get_mut(&id).ok_or(AppError::ProfileNotFound)?;
+        let removed = p.friends.remove(&friend_id) | p.friend_requests.remove(&friend_id);
+        (p.clone(), removed)
+    };
+    let friend = state.profiles.get_mut(&friend_id).map(|mut f| {
+        let removed = f.friends.remove(&id) | f.friend_requests.remove(&id);
+        (f.clone(), removed)
+    });
+    let removed_from_friend = friend.as_ref().is_some_and(|(_, removed)| *removed);
+    if !removed && !removed_from_friend {
+        return Err(AppError::FriendNotFound);
+    }
+    let mut ops = vec![DbOp::UpsertProfile(profile)];
+    ops.extend(friend.map(|(f, _)| DbOp::UpsertProfile(f)));
+    state.persist(ops).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/guilds",
+    tag = "guilds",
+    request_body = CreateGuild,
+    responses(
+        (status = 201, description = "Guild created with the caller as owner and first member", body = GuildView),
+        (status = 400, description = "Name empty or longer than 32 characters", body = ApiError),
+        (status = 409, description = "Name taken, or the caller is already in a guild", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_guild(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Json(payload): Json<CreateGuild>,
+) -> Result<impl IntoResponse, AppError> {
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > GUILD_NAME_MAX {
+        return Err(AppError::InvalidGuildName);
+    }
+    state.check_active(&player)?;
+
+    let mut guilds = state.guilds.lock().await;
+    let key = normalize_name(&name);
+    if guilds.values().any(|g| normalize_name(&g.name) == key) {
+        return Err(AppError::GuildNameTaken);
+    }
+    let guild = Guild {
+        id: Uuid::new_v4(),
+        name,
+        owner: player,
+        created_at: Utc::now(),
+    };
+    let profile = {
+        let mut p = state.profiles.get_mut(&player).ok_or(AppError::UnknownProfile)?;
+        if p.guild_id.is_some() {
+            return Err(AppError::AlreadyInGuild);
+        }
+        p.guild_id = Some(guild.id);
+        p.clone()
+    };
+    guilds.insert(guild.id, guild.clone());
+    state.stage(vec![DbOp::UpsertGuild(guild.clone()), DbOp::UpsertProfile(profile)]);
+    drop(guilds);
+    state.flush().await;
+    let body = GuildView {
+        guild,
+        members: vec![player],
+    };
+    Ok((StatusCode::CREATED, Json(body)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/guilds/{id}",
+    tag = "guilds",
+    params(("id" = Uuid, Path, description = "Guild id")),
+    responses(
+        (status = 200, description = "The guild and its members", body = GuildView),
+        (status = 404, description = "Guild not found", body = ApiError),
+    )
+)]
+async fn get_guild(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let guild = state
+        .guilds
+        .lock()
+        .await
+        .get(&id)
+        .cloned()
+        .ok_or(AppError::GuildNotFound)?;
+    let body = GuildView {
+        members: state.guild_members(id),
+        guild,
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/guilds/{id}/members",
+    tag = "guilds",
+    params(("id" = Uuid, Path, description = "Guild id")),
+    request_body = AddGuildMember,
+    responses(
+        (status = 200, description = "The guild with its new member", body = GuildView),
+        (status = 400, description = "Unknown player", body = ApiError),
+        (status = 403, description = "Only the guild owner can add members", body = ApiError),
+        (status = 404, description = "Guild not found", body = ApiError),
+        (status = 409, description = "Player already in a guild", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn add_guild_member(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<AddGuildMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let guilds = state.guilds.lock().await;
+    let guild = guilds.get(&id).cloned().ok_or(AppError::GuildNotFound)?;
+    if guild.owner != player {
+        return Err(AppError::NotGuildOwner);
+    }
+    state.check_active(&payload.profile_id)?;
+    let profile = {
+        let mut p = state
+            .profiles
+            .get_mut(&payload.profile_id)
+            .ok_or(AppError::UnknownProfile)?;
+        if p.guild_id.is_some() {
+            return Err(AppError::AlreadyInGuild);
+        }
+        p.guild_id = Some(id);
+        p.clone()
+    };
+    state.stage(vec![DbOp::UpsertProfile(profile)]);
+    drop(guilds);
+    state.flush().await;
+    let body = GuildView {
+        members: state.guild_members(id),
+        guild,
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/guilds/{id}/members/{profile_id}",
+    tag = "guilds",
+    params(
+        ("id" = Uuid, Path, description = "Guild id"),
+        ("profile_id" = Uuid, Path, description = "Member to remove"),
+    ),
+    responses(
+        (status = 204, description = "Removed; the guild is disbanded when its owner leaves last"),
+        (status = 403, description = "Neither the guild owner nor the member themselves", body = ApiError),
+        (status = 404, description = "Guild not found, or the player is not a member", body = ApiError),
+        (status = 409, description = "The owner cannot leave while others remain", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn remove_guild_member(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path((id, profile_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut guilds = state.guilds.lock().await;
+    let guild = guilds.get(&id).ok_or(AppError::GuildNotFound)?;
+    if player != guild.owner && player != profile_id {
+        return Err(AppError::NotGuildOwner);
+    }
+    if state.guild_of(&profile_id) != Some(id) {
+        return Err(AppError::NotGuildMember);
+    }
+    let mut ops = Vec::new();
+    if profile_id == guild.owner {
+        if state.guild_members(id).len() > 1 {
+            return Err(AppError::GuildOwnerLeaving);
+        }
+        guilds.remove(&id);
+        ops.push(DbOp::DeleteGuild(id));
+    }
+    if let Some(mut p) = state.profiles.get_mut(&profile_id) {
+        p.guild_id = None;
+        ops.push(DbOp::UpsertProfile(p.clone()));
+    }
+    state.stage(ops);
+    drop(guilds);
+    state.flush().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/guilds/{id}/leaderboard",
+    tag = "guilds",
+    params(("id" = Uuid, Path, description = "Guild id")),
+    responses(
+        (status = 200, description = "Active members by ranked mmr, then wins, then oldest profile", body = Leaderboard),
+        (status = 404, description = "Guild not found", body = ApiError),
+    )
+)]
+async fn get_guild_leaderboard(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.guilds.lock().await.contains_key(&id) {
+        return Err(AppError::GuildNotFound);
+    }
+    let mut members: Vec<Profile> = state
+        .profiles
+        .iter()
+        .filter(|p| p.guild_id == Some(id) && !p.deactivated)
+        .map(|p| p.value().clone())
+        .collect();
+    members.sort_by(|a, b| {
+        b.ranked_mmr
+            .cmp(&a.ranked_mmr)
+            .then(b.wins.cmp(&a.wins))
+            .then(a.created_at.cmp(&b.created_at))
+    });
+    let total = members.len();
+    let entries = members
+        .into_iter()
+        .enumerate()
+        .map(|(i, profile)| LeaderboardEntry {
+            rank: i + 1,
+            mmr: profile.ranked_mmr,
+            profile: profile.into(),
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(Leaderboard { total, entries })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/profiles/{id}/block/{target_id}",
+    tag = "friends",
+    params(
+        ("id" = Uuid, Path, description = "Profile id"),
+        ("target_id" = Uuid, Path, description = "Player to block"),
+    ),
+    responses(
+        (status = 204, description = "Blocked, or already blocked"),
+        (status = 400, description = "A player cannot block themselves", body = ApiError),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 404, description = "Profile not found", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn block_player(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path((id, target_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AppError> {
+    if id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    if target_id == id {
+        return Err(AppError::SelfBlock);
+    }
+    if !state.profiles.contains_key(&target_id) {
+        return Err(AppError::ProfileNotFound);
+    }
+
+    // blocking also ends any friendship or pending request between the two
+    let profile = {
+        let mut p = state.profiles.get_mut(&id).ok_or(AppError::ProfileNotFound)?;
+        p.blocked.insert(target_id);
+        p.friends.remove(&target_id);
+        p.friend_requests.remove(&target_id);
+        p.clone()
+    };
+    let mut ops = vec![DbOp::UpsertProfile(profile)];
+    if let Some(mut t) = state.profiles.get_mut(&target_id) {
+        if t.friends.remove(&id) | t.friend_requests.remove(&id) {
+            ops.push(DbOp::UpsertProfile(t.clone()));
+        }
+    }
+    state.persist(ops).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/profiles/{id}/block/{target_id}",
+    tag = "friends",
+    params(
+        ("id" = Uuid, Path, description = "Profile id"),
+        ("target_id" = Uuid, Path, description = "Player to unblock"),
+    ),
+    responses(
+        (status = 204, description = "Unblocked"),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 404, description = "Profile not found, or the player is not blocked", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn unblock_player(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path((id, target_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AppError> {
+    if id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    let profile = {
+        let mut p = state.profiles.get_mut(&id).ok_or(AppError::ProfileNotFound)?;
+        if !p.blocked.remove(&target_id) {
+            return Err(AppError::NotBlocked);
+        }
+        p.clone()
+    };
+    state.persist(vec![DbOp::UpsertProfile(profile)]).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/profiles/{id}/friends/status",
+    tag = "friends",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "Every active friend and what they are doing", body = [FriendStatus]),
+        (status = 401, description = "Missing or invalid bearer token", body = ApiError),
+        (status = 403, description = "Token does not belong to this profile", body = ApiError),
+        (status = 404, description = "Profile not found", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn friends_status(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    if id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    let friend_ids = state
+        .profiles
+        .get(&id)
+        .ok_or(AppError::ProfileNotFound)?
+        .friends
+        .clone();
+    let friends: Vec<Profile> = friend_ids
+        .iter()
+        .filter_map(|f| state.profiles.get(f).map(|p| p.clone()))
+        .filter(|p| !p.deactivated)
+        .collect();
+
+    let queues = state.queue.read().await;
+    let matches = state.matches.lock().await;
+    let list: Vec<FriendStatus> = friends
+        .into_iter()
+        .map(|profile| {
+            let in_match = matches.values().any(|m| {
+                matches!(m.status, MatchStatus::Pending | MatchStatus::Active) && m.involves(profile.id)
+            });
+            let in_queue = queues
+                .values()
+                .flat_map(ModeQueue::iter)
+                .any(|e| e.members.contains(&profile.id));
+            let status = if in_match {
+                Presence::InMatch
+            } else if in_queue {
+                Presence::InQueue
+            } else {
+                Presence::Idle
+            };
+            FriendStatus { profile, status }
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(list)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/profiles/{id}/matches",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id"), Pagination),
+    responses(
+        (status = 200, description = "Matches of the player", body = [MatchInfo]),
+        (status = 404, description = "Profile not found", body = ApiError),
+    )
+)]
+async fn get_profile_matches(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Pagination>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.profiles.contains_key(&id) {
+        return Err(AppError::ProfileNotFound);
+    }
+
+    let matches = state.matches.lock().await;
+    let list: Vec<MatchInfo> = player_matches(&matches, id)
+        .into_iter()
+        .skip(page.offset)
+        .take(page.limit)
+        .cloned()
+        .collect();
+    Ok((StatusCode::OK, Json(list)))
+}
+
+// one row of GET /profiles/:id/matches.csv, seen from the exported player
+#[derive(Serialize)]
+struct MatchCsvRow {
+    match_id: Uuid,
+    mode: GameMode,
+    // the other side's captain
+    opponent_id: Uuid,
+    // the other side's average mmr on the match's mode as of now; no
+    // history of past ratings is kept
+    opponent_mmr: Option<u32>,
+    // win, loss or draw; empty until a result is recorded
+    result: Option<&'static str>,
+    match_quality: Option<f64>,
+    created_at: DateTime<Utc>,
+    ended_at: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/profiles/{id}/matches.csv",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "Every match of the player, oldest first", content_type = "text/csv"),
+        (status = 204, description = "The player has no matches"),
+        (status = 404, description = "Profile not found", body = ApiError),
+    )
+)]
+async fn export_profile_matches(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.profiles.contains_key(&id) {
+        return Err(AppError::ProfileNotFound);
+    }
+
+    let rows: Vec<MatchCsvRow> = player_matches(&*state.matches.lock().await, id)
+        .into_iter()
+        .map(|m| {
+            let on_team1 = m.team1.contains(&id);
+            let (opponent_id, opponents) = if on_team1 {
+                (m.player2, &m.team2)
+            } else {
+                (m.player1, &m.team1)
+            };
+            let result = m.result.map(|r| match (r, on_team1) {
+                (MatchResult::Draw, _) => "draw",
+                (MatchResult::Player1Win, true) | (MatchResult::Player2Win, false) => "win",
+                _ => "loss",
+            });
+            MatchCsvRow {
+                match_id: m.id,
+                mode: m.mode,
+                opponent_id,
+                opponent_mmr: team_mmr(&state.profiles, opponents, m.mode)
+                    .map(|mmr| mmr.round() as u32),
+                result,
+                match_quality: m.quality,
+                created_at: m.created_at,
+                ended_at: m.ended_at,
+            }
+        })
+        .collect();
+
+    let headers = [
+        (CONTENT_TYPE, "text/csv".to_string()),
+        (CONTENT_DISPOSITION, format!("attachment; filename=\"matches-{id}.csv\"")),
+    ];
+    // a 204 carries no body, so the header row is only sent with matches
+    if rows.is_empty() {
+        return Ok((StatusCode::NO_CONTENT, headers, Vec::new()));
+    }
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        csv.serialize(row).expect("writing csv to memory cannot fail");
+    }
+    let body = csv.into_inner().expect("writing csv to memory cannot fail");
+    Ok((StatusCode::OK, headers, body))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/profiles/{id}/recent_opponents",
+    tag = "profiles",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "Latest opponents, oldest first", body = [Uuid]),
+        (status = 404, description = "Profile not found", body = ApiError),
+    )
+)]
+async fn get_recent_opponents(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.profiles.contains_key(&id) {
+        return Err(AppError::ProfileNotFound);
+    }
+    let recent: Vec<Uuid> = state
+        .recent_opponents
+        .get(&id)
+        .map(|r| r.iter().copied().collect())
+        .unwrap_or_default();
+    Ok((StatusCode::OK, Json(recent)))
+}
+
+// all matches `profile_id` took part in, in a stable order.
+// linear scan for now; swap in a player -> matches index here if needed
+fn player_matches(matches: &HashMap<Uuid, MatchInfo>, profile_id: Uuid) -> Vec<&MatchInfo> {
+    let mut list: Vec<&MatchInfo> = matches
+        .values()
+        .filter(|m| m.involves(profile_id))
+        .collect();
+    list.sort_by_key(|m| (m.created_at, m.id));
+    list
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportRange {
+    // matches created at or after this time
+    since: Option<DateTime<Utc>>,
+    // matches created before this time
+    until: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/export/matches",
+    tag = "matches",
+    params(ExportRange),
+    responses(
+        (
+            status = 200,
+            description = "Every match created in the range, oldest first, one MatchInfo JSON object per line",
+            content_type = "application/x-ndjson"
+        ),
+        (status = 400, description = "since is not before until", body = ApiError),
+    )
+)]
+async fn export_matches(
+    State(state): State<Arc<AppState>>,
+    Query(range): Query<ExportRange>,
+) -> Result<impl IntoResponse, AppError> {
+    if let (Some(since), Some(until)) = (range.since, range.until) {
+        if since >= until {
+            return Err(AppError::InvalidTimeRange);
+        }
+    }
+    let in_range = |m: &MatchInfo| {
+        range.since.is_none_or(|since| m.created_at >= since)
+            && range.until.is_none_or(|until| m.created_at < until)
+    };
+    // only the ids are collected up front, with the shard holding each;
+    // each match is cloned and written on its own, so neither the match set
+    // is copied nor a lock held while a slow client reads
+    let mut ids: Vec<(DateTime<Utc>, Uuid, usize)> = Vec::new();
+    for (shard, s) in state.shard_states().into_iter().enumerate() {
+        let matches = s.matches.lock().await;
+        ids.extend(matches.values().filter(|m| in_range(m)).map(|m| (m.created_at, m.id, shard)));
+    }
+    ids.sort();
+
+    let (tx, rx) = mpsc::channel::<Result<String, Infallible>>(64);
+    tokio::spawn(async move {
+        for (_, id, shard) in ids {
+            // a match removed since the ids were taken is skipped
+            let Some(m) = state.shard_states()[shard].matches.lock().await.get(&id).cloned() else {
+                continue;
+            };
+            let mut line = serde_json::to_string(&m).unwrap();
+            line.push('\n');
+            if tx.send(Ok(line)).await.is_err() {
+                return;
+            }
+        }
+    });
+    Ok((
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(ReceiverStream::new(rx)),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/parties",
+    tag = "parties",
+    request_body = CreateParty,
+    responses(
+        (status = 201, description = "Party created", body = Party),
+        (status = 400, description = "Invalid member list", body = ApiError),
+        (status = 403, description = "Token does not belong to a member", body = ApiError),
+        (status = 409, description = "A member is already in a party", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_party(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Json(payload): Json<CreateParty>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = payload.members;
+    if !members.contains(&player) {
+        return Err(AppError::NotPartyMember);
+    }
+    let mut unique = members.clone();
+    unique.sort();
+    unique.dedup();
+    if unique.len() != members.len() {
+        return Err(AppError::DuplicatePartyMembers);
+    }
+    if members.len() < 2 {
+        return Err(AppError::PartyTooSmall);
+    }
+
+    // checked under the parties lock so a concurrent deactivation cannot slip in
+    let mut parties = state.parties.lock().await;
+    members.iter().try_for_each(|id| state.check_active(id))?;
+    if parties.values().any(|p| p.members.iter().any(|id| members.contains(id))) {
+        return Err(AppError::AlreadyInParty);
+    }
+
+    // whoever created the party leads it
+    let party = Party {
+        id: Uuid::new_v4(),
+        leader: player,
+        members,
+    };
+    parties.insert(party.id, party.clone());
+    state.record_home(party.id);
+    Ok((StatusCode::CREATED, Json(party)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/parties/{id}",
+    tag = "parties",
+    params(("id" = Uuid, Path, description = "Party id")),
+    responses(
+        (status = 200, description = "The party", body = Party),
+        (status = 404, description = "Party not found", body = ApiError),
+    )
+)]
+async fn get_party(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let parties = state.parties.lock().await;
+    if let Some(p) = parties.get(&id) {
+        Ok((StatusCode::OK, Json(p.clone())))
+    } else {
+        Err(AppError::PartyNotFound)
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/parties/{id}",
+    tag = "parties",
+    params(("id" = Uuid, Path, description = "Party id")),
+    responses(
+        (status = 204, description = "Party disbanded"),
+        (status = 403, description = "Token does not belong to the party leader", body = ApiError),
+        (status = 404, description = "Party not found", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+async fn delete_party(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut parties = state.parties.lock().await;
+    let mut queue = state.queue.write().await;
+    let Some(party) = parties.get(&id) else {
+        return Err(AppError::PartyNotFound);
+    };
+    // without a player the request was let through on the admin key
+    if player.is_some_and(|Extension(AuthPlayer(p))| p != party.leader) {
+        return Err(AppError::NotPartyLeader);
+    }
+    parties.remove(&id);
+    let mut ops = Vec::new();
+    let mut changed = Vec::new();
+    for (mode, q) in queue.iter_mut() {
+        q.retain(|e| {
+            let keep = e.party_id != Some(id);
+            if !keep {
+                ops.push(DbOp::DeleteQueueEntry(e.profile_id));
+                changed.push(*mode);
+            }
+            keep
+        });
+    }
+    state.stage(ops);
+    drop((parties, queue));
+    state.flush().await;
+    for mode in changed {
+        let _ = state.queue_changes.send(mode);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/queue/enqueue",
+    tag = "queue",
+    request_body = QueueRequest,
+    responses(
+        (status = 201, description = "An opponent was found inside the request, with matching_interval_ms 0", body = MatchInfo),
+        (status = 202, description = "Waiting in the queue", body = EnqueueResponse),
+        (status = 200, description = "Already in the queue, where the entry waits", body = QueuePosition),
+        (status = 403, description = "Token does not belong to the profile, or queue ban", body = ApiError),
+        (status = 409, description = "Queued for another mode or too many open matches", body = ApiError),
+        (status = 429, description = "Rate limited or queue full", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(skip_all, fields(profile_id = %payload.profile_id, mode = ?payload.mode))]
+async fn enqueue(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Json(payload): Json<QueueRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(match join_queue(&state, player, payload).await? {
+        Enqueued::Matched(m) => (StatusCode::CREATED, Json(m)).into_response(),
+        Enqueued::Waiting(body) => (StatusCode::ACCEPTED, Json(body)).into_response(),
+        Enqueued::AlreadyQueued(at) => (StatusCode::OK, Json(at)).into_response(),
+    })
+}
+
+// what joining the queue led to
+enum Enqueued {
+    Matched(Box<MatchInfo>),
+    Waiting(EnqueueResponse),
+    // where the existing entry waits
+    AlreadyQueued(QueuePosition),
+}
+
+// queues `payload.profile_id` (or their party) on behalf of `player`, matching
+// them straight away if an opponent is waiting. shared by HTTP, gRPC and
+// GraphQL
+#[tracing::instrument(
+    skip_all,
+    fields(
+        profile_id = %payload.profile_id,
+        game_mode = ?payload.mode,
+        queue_depth = tracing::field::Empty,
+        match_id = tracing::field::Empty,
+    )
+)]
+async fn join_queue(
+    state: &Arc<AppState>,
+    player: Uuid,
+    payload: QueueRequest,
+) -> Result<Enqueued, AppError> {
+    let _timer = state.metrics.enqueue_duration.start_timer();
+    // players may only queue themselves (or the party they lead)
+    if payload.profile_id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+
+    // Ensure profile exists
+    state.check_active(&payload.profile_id)?;
+    let Some((region, vip)) = state.profiles.get(&payload.profile_id).map(|p| (p.region, p.vip))
+    else {
+        return Err(AppError::UnknownProfile);
+    };
+    let lane = if vip { Lane::Vip } else { Lane::Normal };
+
+    // a party is queued by its leader and matched as a single entry
+    let members = match payload.party_id {
+        None => vec![payload.profile_id],
+        Some(party_id) => {
+            let parties = state.parties.lock().await;
+            match parties.get(&party_id) {
+                None => return Err(AppError::PartyNotFound),
+                Some(party) if party.leader != payload.profile_id => {
+                    return Err(AppError::NotPartyLeader)
+                }
+                Some(party) => party.members.clone(),
+            }
+        }
+    };
+    if let Some(size) = payload.team_size {
+        if !TEAM_SIZES.contains(&size) || members.len() > usize::from(size) {
+            return Err(AppError::InvalidTeamSize);
+        }
+    }
+
+    // Add to queue if not already present; a player waits in one mode at a time
+    let mut queues = state.queue.write().await;
+    // deactivation holds the queue lock, so members that are still active
+    // here cannot drop out before the entry is queued
+    members.iter().try_for_each(|id| state.check_active(id))?;
+    let now = Utc::now();
+    let banned = members
+        .iter()
+        .filter_map(|id| state.profiles.get(id)?.ban_until)
+        .filter(|until| *until > now)
+        .max();
+    if let Some(until) = banned {
+        return Err(AppError::QueueBan { until });
+    }
+    // enqueue and force_match create matches under the queue lock, so the
+    // count holds until this entry is matched. queued players were checked
+    // when they joined
+    state.check_match_quota(&*state.matches.lock().await, &members)?;
+    let mmr = team_mmr(&state.profiles, &members, payload.mode)
+        .unwrap_or_default()
+        .round() as u32;
+    for (mode, q) in queues.iter() {
+        let queued = q
+            .iter()
+            .enumerate()
+            .find(|(_, e)| e.members.iter().any(|id| members.contains(id)));
+        if let Some((idx, e)) = queued {
+            if *mode == payload.mode {
+                return Ok(Enqueued::AlreadyQueued(QueuePosition {
+                    mode: *mode,
+                    position: idx + 1,
+                    queued_since: e.queued_since,
+                }));
+            }
+            return Err(AppError::QueuedForAnotherMode);
+        }
+    }
+    let queue = queues.entry(payload.mode).or_default();
+    let waiting: usize = queue.iter().map(|e| e.members.len()).sum();
+    telemetry::record_queue_depth(waiting);
+
+    let entry = QueueEntry {
+        profile_id: payload.profile_id,
+        party_id: payload.party_id,
+        members,
+        mmr,
+        region,
+        queued_at: Instant::now(),
+        queued_since: Utc::now(),
+        last_heartbeat: Instant::now(),
+        lane,
+        team_size: payload.team_size,
+    };
+
+    // with a background matcher the entry always waits for its next pass
+    let inline = state.config.matching_interval().is_none();
+    if let Some(picked) = inline
+        .then(|| matchmaking::find_opponents(state, payload.mode, queue, &entry))
+        .flatten()
+    {
+        let m = create_match(state, payload.mode, queue, &picked, entry).await;
+        drop(queues);
+        state.flush().await;
+        telemetry::record_match(m.id);
+        announce_match(state, &m).await;
+        return Ok(Enqueued::Matched(Box::new(m)));
+    }
+
+    // otherwise push to queue, if there is room for every member
+    if waiting + entry.members.len() > state.config.queue_capacity(payload.mode) {
+        return Err(AppError::QueueFull);
+    }
+    state.stage(vec![DbOp::UpsertQueueEntry(payload.mode, entry.clone())]);
+    let queue_position = queue.insert(entry) + 1;
+    drop(queues);
+    state.flush().await;
+    let _ = state.queue_changes.send(payload.mode);
+
+    // average of the recent time-to-match durations, if there are any
+    let wait_times = state.wait_times.lock().await;
+    let estimated_wait_seconds = if wait_times.is_empty() {
+        None
+    } else {
+        let total: Duration = wait_times.iter().sum();
+        Some(total.as_secs_f64() / wait_times.len() as f64)
+    };
+
+    let body = EnqueueResponse {
+        status: "enqueued",
+        lane,
+        queue_position,
+        estimated_wait_seconds,
+    };
+    Ok(Enqueued::Waiting(body))
+}
+
+// turns `entry` and the teammates and opponents `picked` from `queue` by
+// `matchmaking::find_opponents` into a new pending match. `entry` is not in
+// `queue`; a caller that took it out stages its removal first. the caller
+// holds the queue write lock throughout and calls `flush` and
+// `announce_match` once it is released
+#[tracing::instrument(skip_all, fields(?mode))]
+async fn create_match(
+    state: &Arc<AppState>,
+    mode: GameMode,
+    queue: &mut ModeQueue,
+    picked: &matchmaking::Picked,
+    entry: QueueEntry,
+) -> MatchInfo {
+    // remove from the back so the remaining indices stay valid
+    let mut taken: Vec<usize> = picked.indices().collect();
+    taken.sort_unstable();
+    let mut removed: HashMap<usize, QueueEntry> = taken
+        .iter()
+        .rev()
+        .map(|&idx| (idx, queue.remove(idx).unwrap()))
+        .collect();
+    let mut take = |indices: &[usize]| -> Vec<QueueEntry> {
+        indices.iter().map(|idx| removed.remove(idx).unwrap()).collect()
+    };
+    let teammates = take(&picked.teammates);
+    let opponents = take(&picked.opponents);
+
+    for waited in opponents.iter().chain(&teammates) {
+        state.record_wait(waited.queued_at.elapsed()).await;
+    }
+
+    // create match
+    let mut lobby = opponents.clone();
+    lobby.extend(teammates.iter().cloned());
+    lobby.push(entry.clone());
+    let team1: Vec<Uuid> = opponents
+        .iter()
+        .flat_map(|e| e.members.iter().copied())
+        .collect();
+    let team2: Vec<Uuid> = std::iter::once(&entry)
+        .chain(&teammates)
+        .flat_map(|e| e.members.iter().copied())
+        .collect();
+    let tiers = state.tiers(team1.iter().chain(&team2), mode);
+    let quality = state.quality(&team1, &team2, mode);
+    let m = MatchInfo {
+        id: Uuid::new_v4(),
+        player1: team1[0],
+        player2: entry.profile_id,
+        team1,
+        team2,
+        mode,
+        created_at: Utc::now(),
+        started_at: None,
+        ended_at: None,
+        result: None,
+        report_team1: None,
+        report_team2: None,
+        disputed: false,
+        status: MatchStatus::Pending,
+        cancel_reason: None,
+        cancel_note: None,
+        ready_player1: false,
+        ready_player2: false,
+        spectators: Vec::new(),
+        tiers,
+        quality,
+        metadata: HashMap::new(),
+        feedback: Vec::new(),
+        draft_state: None,
+        server_address: None,
+    };
+    let mut matches = state.matches.lock().await;
+    matches.insert(m.id, m.clone());
+    // the incoming entry was never queued, or its caller took it out
+    let mut ops: Vec<DbOp> = opponents
+        .iter()
+        .chain(&teammates)
+        .map(|e| DbOp::DeleteQueueEntry(e.profile_id))
+        .collect();
+    ops.push(DbOp::UpsertMatch(m.clone()));
+    state.stage(ops);
+    drop(matches);
+    state.count_created(&m).await;
+    state.lobbies.lock().await.insert(m.id, lobby);
+    tokio::spawn(expire_ready_check(state.clone(), m.id));
+    // nobody listening is fine, the match is still returned.
+    // sent before the queue lock is released so position streams that
+    // find the player gone already have the event waiting
+    state.notify(Notification {
+        profile_ids: m.participants().collect(),
+        event: PlayerEvent::Matched { r#match: Box::new(m.clone()) },
+    });
+    m
+}
+
+// the part of forming a match that runs after the queue lock is released
+async fn announce_match(state: &Arc<AppState>, m: &MatchInfo) {
+    let _ = state.queue_changes.send(m.mode);
+    state.remember_opponents(m);
+    webhooks::dispatch(state, WebhookEvent::MatchCreated, m).await;
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/queue/leave",
+    tag = "queue",
+    request_body = QueueRequest,
+    responses(
+        (status = 200, description = "Removed from the queue", body = Dequeued),
+        (status = 400, description = "Not in the queue", body = ApiError),
+        (status = 403, description = "Token does not belong to the profile", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(skip_all, fields(profile_id = %payload.profile_id, mode = ?payload.mode))]
+async fn leave_queue(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Json(payload): Json<QueueRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    remove_from_queue(&state, player, &payload).await?;
+    let body = Dequeued {
+        removed_from: vec![payload.mode],
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+// takes `payload.profile_id` out of the queue on behalf of `player`. shared
+// by HTTP, gRPC and GraphQL
+#[tracing::instrument(skip_all, fields(profile_id = %payload.profile_id, mode = ?payload.mode))]
+async fn remove_from_queue(state: &AppState, player: Uuid, payload: &QueueRequest) -> Result<(), AppError> {
+    // players may only take themselves out; admins use /admin/queue
+    if payload.profile_id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    let mut queues = state.queue.write().await;
+    let queue = queues.entry(payload.mode).or_default();
+    // a party leaves together when any of its members leaves
+    let pos = queue.iter().position(|e| e.members.contains(&payload.profile_id));
+    if let Some(pos) = pos {
+        let entry = queue.remove(pos).unwrap();
+        state.stage(vec![DbOp::DeleteQueueEntry(entry.profile_id)]);
+        drop(queues);
+        state.flush().await;
+        let _ = state.queue_changes.send(payload.mode);
+        Ok(())
+    } else {
+        Err(AppError::NotQueued)
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/queue/heartbeat",
+    tag = "queue",
+    request_body = QueueRequest,
+    responses(
+        (status = 200, description = "Heartbeat received, with where the entry waits", body = QueuePosition),
+        (status = 403, description = "Token does not belong to the profile", body = ApiError),
+        (status = 404, description = "Not in the queue", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn heartbeat(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Json(payload): Json<QueueRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if payload.profile_id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    // a player is only ever queued in one mode, so search them all
+    let mut queues = state.queue.write().await;
+    let entry = queues.iter_mut().find_map(|(mode, q)| {
+        let (idx, e) = q
+            .iter_mut()
+            .enumerate()
+            .find(|(_, e)| e.members.contains(&payload.profile_id))?;
+        Some((*mode, idx, e))
+    });
+    match entry {
+        Some((mode, idx, entry)) => {
+            entry.last_heartbeat = Instant::now();
+            let body = QueuePosition {
+                mode,
+                position: idx + 1,
+                queued_since: entry.queued_since,
+            };
+            Ok((StatusCode::OK, Json(body)))
+        }
+        None => Err(AppError::NotInQueue),
+    }
+}
+
+// periodically drops queue entries whose client stopped sending heartbeats
+// and players that have been waiting longer than `max_queue_time`
+async fn sweep_queue(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(state.config.stale_check_interval());
+    loop {
+        interval.tick().await;
+        let mut timed_out = Vec::new();
+        let mut stale = Vec::new();
+        let mut removed = Vec::new();
+        let mut changed = Vec::new();
+        let mut queues = state.queue.write().await;
+        for (mode, q) in queues.iter_mut() {
+            let before = q.len();
+            q.retain(|e| {
+                if e.last_heartbeat.elapsed() > state.config.stale_timeout() {
+                    tracing::warn!(profile_id = %e.profile_id, "removing stale queue entry");
+                    stale.extend(&e.members);
+                    removed.push(DbOp::DeleteQueueEntry(e.profile_id));
+                    return false;
+                }
+                if e.queued_at.elapsed() > state.config.max_queue_time() {
+                    tracing::info!(profile_id = %e.profile_id, "evicting player after max queue time");
+                    timed_out.extend(&e.members);
+                    removed.push(DbOp::DeleteQueueEntry(e.profile_id));
+                    return false;
+                }
+                true
+            });
+            if q.len() != before {
+                changed.push(*mode);
+            }
+        }
+        state.stage(removed);
+        // the players hear why before their streams see the queue change
+        for (profile_ids, reason) in [
+            (timed_out, DequeueReason::Timeout),
+            (stale, DequeueReason::Stale),
+        ] {
+            if !profile_ids.is_empty() {
+                state.notify(Notification {
+                    profile_ids,
+                    event: PlayerEvent::Dequeued { reason },
+                });
+            }
+        }
+        drop(queues);
+        state.flush().await;
+        for mode in changed {
+            let _ = state.queue_changes.send(mode);
+        }
+    }
+}
+
+// lowers the ranked mmr of players who have not finished a match for
+// `decay_start_days`, by `decay_rate_per_day` for every whole day past that
+async fn decay_inactive(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(state.config.decay_interval());
+    let grace = chrono::Duration::days(state.config.decay_start_days.into());
+    let floor = state.config.mmr_floor;
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let mut ops = Vec::new();
+        for mut p in state.profiles.iter_mut() {
+            let Some(last_game) = p.last_game_at else {
+                continue;
+            };
+            let from = match p.decayed_until {
+                Some(until) => until.max(last_game + grace),
+                None => last_game + grace,
+            };
+            let days = (now - from).num_days();
+            if days < 1 {
+                continue;
+            }
+            // remainders of a day carry over to the next run
+            p.decayed_until = Some(from + chrono::Duration::days(days));
+            for mode in GameMode::ALL.into_iter().filter(|mode| mode.is_ranked()) {
+                let old = get_mode_mmr(&p, mode);
+                if old > floor {
+                    let decayed = old as f64 - state.config.decay_rate_per_day * days as f64;
+                    p.set_mode_mmr(mode, (decayed.round().max(0.0) as u32).max(floor));
+                }
+                let new = get_mode_mmr(&p, mode);
+                tracing::debug!(profile_id = %p.id, ?mode, days, old, new, "inactivity decay applied");
+            }
+            ops.push(DbOp::UpsertProfile(p.clone()));
+        }
+        if !ops.is_empty() {
+            state.persist(ops).await;
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/queue",
+    tag = "queue",
+    params(QueueFilter),
+    responses((status = 200, description = "Queued entries", body = [QueueView]))
+)]
+async fn get_queue(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<QueueFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut list = Vec::new();
+    for shard in state.shard_states() {
+        list.extend(queue_view(&*shard.queue.read().await, filter.mode));
+    }
+    Ok((StatusCode::OK, Json(list)))
+}
+
+// queued entries of `mode`, or of every mode. shared by HTTP and GraphQL
+fn queue_view(queues: &HashMap<GameMode, ModeQueue>, mode: Option<GameMode>) -> Vec<QueueView> {
+    queues
+        .iter()
+        .filter(|(m, _)| mode.is_none_or(|mode| mode == **m))
+        .flat_map(|(mode, q)| {
+            q.iter().map(|e| QueueView {
+                profile_id: e.profile_id,
+                party_id: e.party_id,
+                region: e.region,
+                mode: *mode,
+                lane: e.lane,
+            })
+        })
+        .collect()
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/queue/stats",
+    tag = "queue",
+    params(QueueFilter),
+    responses((status = 200, description = "Queue health, over every mode unless one is given", body = QueueStats))
+)]
+async fn get_queue_stats(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<QueueFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    let in_filter = |mode: GameMode| filter.mode.is_none_or(|m| m == mode);
+    let (mut waits, mut depth, mut matches_created_last_minute) = (Vec::new(), 0, 0);
+    for shard in state.shard_states() {
+        let queues = shard.queue.read().await;
+        let entries = || {
+            queues
+                .iter()
+                .filter(|(mode, _)| in_filter(**mode))
+                .flat_map(|(_, q)| q.iter())
+        };
+        waits.extend(entries().map(|e| e.queued_at.elapsed().as_secs_f64()));
+        depth += entries().map(|e| e.members.len()).sum::<usize>();
+        drop(queues);
+
+        let recent = shard.recent_matches.lock().await;
+        matches_created_last_minute += recent
+            .iter()
+            .filter(|(at, mode)| at.elapsed() <= RECENT_MATCHES_WINDOW && in_filter(*mode))
+            .count() as u32;
+    }
+
+    let body = QueueStats {
+        depth,
+        avg_wait_seconds: (!waits.is_empty())
+            .then(|| waits.iter().sum::<f64>() / waits.len() as f64),
+        oldest_entry_seconds: waits.iter().copied().reduce(f64::max),
+        matches_created_last_minute,
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/queue/position/{profile_id}",
+    tag = "queue",
+    params(("profile_id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "Position in the queue", body = QueuePosition),
+        (status = 404, description = "Not in the queue", body = ApiError),
+    )
+)]
+async fn get_queue_position(
+    State(state): State<Arc<AppState>>,
+    Path(profile_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    match queue_position(&*state.queue.read().await, profile_id) {
+        Some(body) => Ok((StatusCode::OK, Json(body))),
+        None => Err(AppError::NotInQueue),
+    }
+}
+
+// where `profile_id` waits, in whichever mode they are queued for
+fn queue_position(
+    queues: &HashMap<GameMode, ModeQueue>,
+    profile_id: Uuid,
+) -> Option<QueuePosition> {
+    queues.iter().find_map(|(mode, q)| {
+        let (idx, e) = q.iter().enumerate().find(|(_, e)| e.members.contains(&profile_id))?;
+        Some(QueuePosition {
+            mode: *mode,
+            position: idx + 1,
+            queued_since: e.queued_since,
+        })
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/matches",
+    tag = "matches",
+    params(CursorPagination),
+    responses(
+        (status = 200, description = "Matches by creation time", body = MatchPage),
+        (status = 400, description = "Unknown cursor", body = ApiError),
+    )
+)]
+async fn list_matches(
+    State(state): State<Arc<AppState>>,
+    Query(page): Query<CursorPagination>,
+) -> Result<impl IntoResponse, AppError> {
+    // every shard's matches at once, so the cursor walks them all
+    let mut shards = Vec::new();
+    for shard in state.shard_states() {
+        shards.push(shard.matches.lock().await);
+    }
+    let mut list: Vec<&MatchInfo> = shards.iter().flat_map(|m| m.values()).collect();
+    list.sort_by_key(|m| (m.created_at, m.id));
+    if let SortOrder::CreatedAtDesc = page.sort {
+        list.reverse();
+    }
+    let page = paginate(&list, |m| m.id, page.after, page.limit)
+        .ok_or(AppError::UnknownCursor)?;
+    // copied out so the response does not borrow the locked map
+    let page = Page {
+        items: page.items.into_iter().cloned().collect(),
+        next_cursor: page.next_cursor,
+    };
+    Ok((StatusCode::OK, Json(page)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/matches/{id}",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    responses(
+        (status = 200, description = "The match", body = MatchInfo),
+        (status = 404, description = "Match not found", body = ApiError),
+    )
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn get_match(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((StatusCode::OK, Json(state.find_match(id).await?)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/result",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body = ReportResult,
+    responses(
+        (status = 200, description = "Result recorded, the match is Completed", body = MatchInfo),
+        (status = 202, description = "Report kept until the other side reports the same result", body = MatchInfo),
+        (status = 403, description = "Not a player of this match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Result already recorded or match not active", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id, winner = ?payload.winner))]
+async fn report_result(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ReportResult>,
+) -> Result<impl IntoResponse, AppError> {
+    let player = player.map(|Extension(AuthPlayer(id))| id);
+    let updated = record_result(&state, player, id, payload.winner).await?;
+    let status = if updated.status == MatchStatus::Completed {
+        StatusCode::OK
+    } else {
+        StatusCode::ACCEPTED
+    };
+    Ok((status, Json(updated)))
+}
+
+// completes the match and rates its players once both sides reported the same
+// result, or at once with no `player`, on the admin key. until then a
+// player's report is kept for their side, and one that differs from the other
+// side's marks the match disputed. shared by HTTP, gRPC and GraphQL
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn record_result(
+    state: &AppState,
+    player: Option<Uuid>,
+    id: Uuid,
+    winner: Winner,
+) -> Result<MatchInfo, AppError> {
+    let result = match winner {
+        Winner::Player1 => MatchResult::Player1Win,
+        Winner::Player2 => MatchResult::Player2Win,
+        Winner::Draw => MatchResult::Draw,
+    };
+
+    // the matches lock is kept until the new ratings are written, so results
+    // sharing players are rated one after the other
+    let mut matches = state.matches.lock().await;
+    let m = match matches.get_mut(&id) {
+        None => return Err(AppError::MatchNotFound),
+        Some(m) if player.is_some_and(|p| !m.involves(p)) => {
+            return Err(AppError::NotMatchParticipant)
+        }
+        Some(m) if m.result.is_some() => return Err(AppError::ResultAlreadyRecorded),
+        Some(m) if !m.status.can_transition_to(MatchStatus::Completed) => {
+            return Err(invalid_transition("complete", m))
+        }
+        Some(m) => m,
+    };
+    if let Some(p) = player {
+        let (own, other) = if m.team1.contains(&p) {
+            (&mut m.report_team1, m.report_team2)
+        } else {
+            (&mut m.report_team2, m.report_team1)
+        };
+        *own = Some(result);
+        if other != Some(result) {
+            m.disputed = other.is_some();
+            if m.disputed {
+                tracing::warn!(match_id = %id, result = ?result, by = %p, "match result disputed");
+            }
+            let pending = m.clone();
+            state.stage(vec![DbOp::UpsertMatch(pending.clone())]);
+            drop(matches);
+            state.flush().await;
+            return Ok(pending);
+        }
+    }
+    m.result = Some(result);
+    m.disputed = false;
+    m.transition(MatchStatus::Completed);
+    let updated = m.clone();
+
+    apply_elo(&state.profiles, &updated, state.config.k_factor, state.config.mmr_bounds());
+    record_outcome(&state.profiles, &updated);
+    let mut ops: Vec<DbOp> = updated
+        .participants()
+        .filter_map(|pid| state.profiles.get(&pid).map(|p| p.clone()))
+        .map(DbOp::UpsertProfile)
+        .collect();
+    ops.push(DbOp::UpsertMatch(updated.clone()));
+    state.stage(ops);
+    drop(matches);
+    state.flush().await;
+    state.metrics.matches_completed.inc();
+    webhooks::dispatch(state, WebhookEvent::MatchCompleted, &updated).await;
+    discord::notify(state, DiscordEvent::MatchCompleted, &updated);
+    Ok(updated)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/start",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    responses(
+        (status = 200, description = "Match started", body = MatchInfo),
+        (status = 403, description = "Not a player of this match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Match is not pending, or waits for its ready check", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn start_match(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut matches = state.matches.lock().await;
+    match matches.get_mut(&id) {
+        None => Err(AppError::MatchNotFound),
+        Some(m) if player.is_some_and(|Extension(AuthPlayer(p))| !m.involves(p)) => {
+            Err(AppError::NotMatchParticipant)
+        }
+        Some(m) if !m.status.can_transition_to(MatchStatus::Active) => {
+            Err(invalid_transition("start", m))
+        }
+        // a match from the queue starts once both sides are ready
+        Some(_) if state.lobbies.lock().await.contains_key(&id) => Err(AppError::ReadyCheckPending),
+        Some(m) => {
+            m.transition(MatchStatus::Active);
+            state.open_draft(m);
+            state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+            discord::notify(&state, DiscordEvent::MatchStarted, m);
+            let m = m.clone();
+            drop(matches);
+            state.flush().await;
+            Ok((StatusCode::OK, Json(m)))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/ready",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    responses(
+        (status = 200, description = "Ready state updated", body = MatchInfo),
+        (status = 403, description = "Not a player, or not a captain, of this match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Match is not pending", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn ready_match(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut matches = state.matches.lock().await;
+    let Some(m) = matches.get_mut(&id) else {
+        return Err(AppError::MatchNotFound);
+    };
+    if !m.involves(player) {
+        return Err(AppError::NotMatchParticipant);
+    }
+    if m.status != MatchStatus::Pending {
+        return Err(invalid_transition("ready up for", m));
+    }
+    if player == m.player1 {
+        m.ready_player1 = true;
+    } else if player == m.player2 {
+        m.ready_player2 = true;
+    } else {
+        return Err(AppError::NotMatchCaptain);
+    }
+
+    let started = m.ready_player1 && m.ready_player2;
+    if started {
+        m.transition(MatchStatus::Active);
+        state.open_draft(m);
+        state.lobbies.lock().await.remove(&id);
+    }
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    if started {
+        discord::notify(&state, DiscordEvent::MatchStarted, m);
+    }
+    let m = m.clone();
+    drop(matches);
+    state.flush().await;
+    Ok((StatusCode::OK, Json(m)))
+}
+
+// cancels a match whose players did not both ready up in time. the side
+// that did not confirm abandoned the match and gets a queue ban; everyone
+// else goes back in the queue with their original queue timestamps
+async fn expire_ready_check(state: Arc<AppState>, match_id: Uuid) {
+    tokio::time::sleep(state.config.ready_timeout()).await;
+
+    let mut queues = state.queue.write().await;
+    let mut matches = state.matches.lock().await;
+    let Some(lobby) = state.lobbies.lock().await.remove(&match_id) else {
+        // the ready check already passed
+        return;
+    };
+    let Some(m) = matches.get_mut(&match_id) else {
+        return;
+    };
+    if m.status != MatchStatus::Pending {
+        return;
+    }
+    tracing::info!(match_id = %match_id, "ready check timed out, requeueing players");
+
+    let mut ops = Vec::new();
+    let mut absent = Vec::new();
+    if !m.ready_player1 {
+        absent.extend(&m.team1);
+    }
+    if !m.ready_player2 {
+        absent.extend(&m.team2);
+    }
+    let now = Utc::now();
+    for id in &absent {
+        let Some(mut p) = state.profiles.get_mut(id) else {
+            continue;
+        };
+        p.penalty_count += 1;
+        let ban = chrono::Duration::from_std(state.config.queue_ban(p.penalty_count)).unwrap_or_default();
+        p.ban_until = Some(now + ban);
+        tracing::warn!(profile_id = %id, offences = p.penalty_count, until = %now + ban, "queue ban for abandoned match");
+        ops.push(DbOp::UpsertProfile(p.clone()));
+    }
+
+    let lobby = lobby
+        .into_iter()
+        .filter(|entry| !entry.members.iter().any(|id| absent.contains(id)))
+        .collect();
+    let note = Some("ready check timed out".to_string());
+    cancel_locked(&state, &mut queues, m, CancelReason::Timeout, note, Some(lobby), &mut ops);
+    let m = m.clone();
+    state.stage(ops);
+    drop((queues, matches));
+    state.flush().await;
+    announce_cancel(&state, &m).await;
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/cancel",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body(content = Option<CancelMatch>, description = "Optional cancel reason, player_request by default"),
+    responses(
+        (status = 200, description = "Match cancelled", body = MatchInfo),
+        (status = 401, description = "Reason admin without the admin key", body = ApiError),
+        (status = 403, description = "Not a player of this match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Match already finished", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn cancel_match(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+    payload: Option<Json<CancelMatch>>,
+) -> Result<impl IntoResponse, AppError> {
+    let CancelMatch { reason, note } = payload.map(|Json(p)| p).unwrap_or_default();
+    if reason == CancelReason::Admin && player.is_some() {
+        return Err(AppError::InvalidAdminKey);
+    }
+
+    let mut queues = state.queue.write().await;
+    let mut matches = state.matches.lock().await;
+    let m = matches.get_mut(&id).ok_or(AppError::MatchNotFound)?;
+    check_participant(m, player)?;
+    if !m.status.can_transition_to(MatchStatus::Cancelled) {
+        return Err(invalid_transition("cancel", m));
+    }
+    let lobby = state.lobbies.lock().await.remove(&id);
+    let mut ops = Vec::new();
+    cancel_locked(&state, &mut queues, m, reason, note, lobby, &mut ops);
+    let m = m.clone();
+    state.stage(ops);
+    drop((queues, matches));
+    state.flush().await;
+    announce_cancel(&state, &m).await;
+    Ok((StatusCode::OK, Json(m)))
+}
+
+// the one way a match is cancelled, under the queue and match locks. `lobby`
+// is what a pending match was made from; when `reason` requeues, its entries,
+// or fresh ones for the teams, go back in the queue. the caller stages `ops`,
+// flushes them once the locks are released and then calls `announce_cancel`
+fn cancel_locked(
+    state: &AppState,
+    queues: &mut HashMap<GameMode, ModeQueue>,
+    m: &mut MatchInfo,
+    reason: CancelReason,
+    note: Option<String>,
+    lobby: Option<Vec<QueueEntry>>,
+    ops: &mut Vec<DbOp>,
+) {
+    m.transition(MatchStatus::Cancelled);
+    m.cancel_reason = Some(reason);
+    m.cancel_note = note;
+    ops.push(DbOp::UpsertMatch(m.clone()));
+    if !reason.requeues() {
+        return;
+    }
+    let now = Instant::now();
+    let entries = lobby.unwrap_or_else(|| {
+        [&m.team1, &m.team2]
+            .into_iter()
+            .filter_map(|team| requeue_entry(state, team, m.mode, now))
+            .collect()
+    });
+    let queue = queues.entry(m.mode).or_default();
+    for mut entry in entries {
+        if entry.members.iter().any(|id| state.check_active(id).is_err()) {
+            continue;
+        }
+        if queue.iter().any(|e| e.members.iter().any(|id| entry.members.contains(id))) {
+            continue;
+        }
+        entry.last_heartbeat = now;
+        ops.push(DbOp::UpsertQueueEntry(m.mode, entry.clone()));
+        queue.insert(entry);
+    }
+    tracing::info!(match_id = %m.id, reason = reason.as_str(), "match cancelled, requeueing players");
+}
+
+// tells the queue streams and the match.cancelled webhooks, once the locks
+// are released
+async fn announce_cancel(state: &AppState, m: &MatchInfo) {
+    if m.cancel_reason.is_some_and(CancelReason::requeues) {
+        let _ = state.queue_changes.send(m.mode);
+    }
+    webhooks::dispatch(state, WebhookEvent::MatchCancelled, m).await;
+}
+
+// a fresh entry for a team whose match was cancelled after its ready check,
+// led by the team's first player
+fn requeue_entry(state: &AppState, team: &[Uuid], mode: GameMode, now: Instant) -> Option<QueueEntry> {
+    let leader = *team.first()?;
+    let (region, vip) = state.profiles.get(&leader).map(|p| (p.region, p.vip))?;
+    let mmr = team_mmr(&state.profiles, team, mode)?.round() as u32;
+    Some(QueueEntry {
+        profile_id: leader,
+        party_id: None,
+        members: team.to_vec(),
+        mmr,
+        region,
+        queued_at: now,
+        queued_since: Utc::now(),
+        last_heartbeat: now,
+        lane: if vip { Lane::Vip } else { Lane::Normal },
+        // already a full side
+        team_size: None,
+    })
+}
+
+// puts two players straight into an active match, pulling them out of any
+// queue they are waiting in
+#[utoipa::path(
+    post,
+    path = "/v1/admin/matches/force",
+    tag = "admin",
+    request_body = ForceMatch,
+    responses(
+        (status = 201, description = "Active match created", body = MatchInfo),
+        (status = 400, description = "Unknown or identical players", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 409, description = "A player is in too many open matches", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+#[tracing::instrument(
+    skip_all,
+    fields(player1 = %payload.player1, player2 = %payload.player2, mode = ?payload.mode)
+)]
+async fn force_match(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Json(payload): Json<ForceMatch>,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::warn!(
+        %admin,
+        player1 = %payload.player1,
+        player2 = %payload.player2,
+        mode = ?payload.mode,
+        "admin force match requested"
+    );
+    if payload.player1 == payload.player2 {
+        return Err(AppError::SamePlayer);
+    }
+
+    let mut queues = state.queue.write().await;
+    // checked under the queue lock, see enqueue
+    let players = [payload.player1, payload.player2];
+    players.iter().try_for_each(|id| state.check_active(id))?;
+    state.check_match_quota(&*state.matches.lock().await, &players)?;
+
+    let mut ops = Vec::new();
+    let mut changed = Vec::new();
+    for (mode, q) in queues.iter_mut() {
+        q.retain(|e| {
+            let keep = !e.members.iter().any(|id| players.contains(id));
+            if !keep {
+                ops.push(DbOp::DeleteQueueEntry(e.profile_id));
+                changed.push(*mode);
+            }
+            keep
+        });
+    }
+
+    let now = Utc::now();
+    let mut m = MatchInfo {
+        id: Uuid::new_v4(),
+        player1: payload.player1,
+        player2: payload.player2,
+        team1: vec![payload.player1],
+        team2: vec![payload.player2],
+        mode: payload.mode,
+        created_at: now,
+        started_at: Some(now),
+        ended_at: None,
+        result: None,
+        report_team1: None,
+        report_team2: None,
+        disputed: false,
+        status: MatchStatus::Active,
+        cancel_reason: None,
+        cancel_note: None,
+        ready_player1: false,
+        ready_player2: false,
+        spectators: Vec::new(),
+        tiers: state.tiers(&players, payload.mode),
+        quality: state.quality(&[payload.player1], &[payload.player2], payload.mode),
+        metadata: HashMap::new(),
+        feedback: Vec::new(),
+        draft_state: None,
+        server_address: None,
+    };
+    // active straight away, so its draft opens now
+    state.open_draft(&mut m);
+    let mut matches = state.matches.lock().await;
+    matches.insert(m.id, m.clone());
+    ops.push(DbOp::UpsertMatch(m.clone()));
+    state.stage(ops);
+    drop(matches);
+    discord::notify(&state, DiscordEvent::MatchStarted, &m);
+    state.notify(Notification {
+        profile_ids: m.participants().collect(),
+        event: PlayerEvent::Matched { r#match: Box::new(m.clone()) },
+    });
+    drop(queues);
+    state.flush().await;
+    for mode in changed {
+        let _ = state.queue_changes.send(mode);
+    }
+
+    state.count_created(&m).await;
+    state.remember_opponents(&m);
+    webhooks::dispatch(&state, WebhookEvent::MatchCreated, &m).await;
+    tracing::warn!(%admin, match_id = %m.id, "admin forced match created");
+    Ok((StatusCode::CREATED, Json(m)))
+}
+
+// points the match's players at their game server. a pending match starts
+// right away, as if its ready check had passed; an active one gets the new
+// address, e.g. after the first server went down
+#[utoipa::path(
+    post,
+    path = "/v1/admin/matches/{id}/assign_server",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body = AssignServer,
+    responses(
+        (status = 200, description = "Server assigned, the match is Active", body = MatchInfo),
+        (status = 400, description = "Not an ip:port address", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Match already finished", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn assign_server(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<AssignServer>,
+) -> Result<impl IntoResponse, AppError> {
+    let address: SocketAddr = payload
+        .address
+        .trim()
+        .parse()
+        .map_err(|_| AppError::InvalidServerAddress)?;
+
+    let mut matches = state.matches.lock().await;
+    let m = matches.get_mut(&id).ok_or(AppError::MatchNotFound)?;
+    let started = match m.status {
+        MatchStatus::Pending => {
+            m.transition(MatchStatus::Active);
+            state.open_draft(m);
+            state.lobbies.lock().await.remove(&id);
+            true
+        }
+        MatchStatus::Active => false,
+        _ => return Err(invalid_transition("assign a server to", m)),
+    };
+    m.server_address = Some(address);
+    let m = m.clone();
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    drop(matches);
+    state.flush().await;
+
+    if started {
+        discord::notify(&state, DiscordEvent::MatchStarted, &m);
+    }
+    state.notify(Notification {
+        profile_ids: m.participants().collect(),
+        event: PlayerEvent::ServerAssigned {
+            match_id: m.id,
+            address,
+        },
+    });
+    tracing::info!(%admin, match_id = %m.id, %address, "game server assigned");
+    Ok((StatusCode::OK, Json(m)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/audit",
+    tag = "admin",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Audit entries, oldest first", body = AuditPage),
+        (status = 400, description = "Unknown cursor, or one already trimmed from the log", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn list_audit(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let log = state.audit_log.lock().await;
+    let list: Vec<&AuditEntry> = log.iter().collect();
+    let page = paginate(&list, |e| e.id, query.after, query.limit).ok_or(AppError::UnknownCursor)?;
+    let page = Page {
+        items: page.items.into_iter().cloned().collect(),
+        next_cursor: page.next_cursor,
+    };
+    Ok((StatusCode::OK, Json(page)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/events",
+    tag = "admin",
+    params(EventQuery),
+    responses(
+        (status = 200, description = "State change events, oldest first", body = EventPage),
+        (status = 400, description = "Unknown cursor, or one already trimmed from the log", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn list_events(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EventQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let log = state.event_log.lock().unwrap();
+    let list: Vec<&LoggedEvent> = log.entries().iter().collect();
+    let page = paginate(&list, |e| e.id, query.after, query.limit).ok_or(AppError::UnknownCursor)?;
+    let page = Page {
+        items: page.items.into_iter().cloned().collect(),
+        next_cursor: page.next_cursor,
+    };
+    Ok((StatusCode::OK, Json(page)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/queue/{profile_id}",
+    tag = "admin",
+    params(("profile_id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "Queues the player was removed from", body = Dequeued),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Not in any queue", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(%profile_id))]
+async fn admin_dequeue(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(profile_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut queues = state.queue.write().await;
+    let mut ops = Vec::new();
+    let mut removed_from = Vec::new();
+    for (mode, q) in queues.iter_mut() {
+        q.retain(|e| {
+            let keep = !e.members.contains(&profile_id);
+            if !keep {
+                ops.push(DbOp::DeleteQueueEntry(e.profile_id));
+                removed_from.push(*mode);
+            }
+            keep
+        });
+    }
+    if removed_from.is_empty() {
+        return Err(AppError::NotInQueue);
+    }
+    state.stage(ops);
+    drop(queues);
+    state.flush().await;
+    for mode in &removed_from {
+        let _ = state.queue_changes.send(*mode);
+    }
+
+    tracing::warn!(%admin, %profile_id, ?removed_from, "admin removed player from queue");
+    Ok((StatusCode::OK, Json(Dequeued { removed_from })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/queue/flush",
+    tag = "admin",
+    request_body(content = Option<FlushQueue>, description = "Optional; flushes every mode without it"),
+    responses(
+        (status = 200, description = "Number of players removed", body = Flushed),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+#[tracing::instrument(skip_all)]
+async fn flush_queue(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    payload: Option<Json<FlushQueue>>,
+) -> Result<impl IntoResponse, AppError> {
+    let only = payload.and_then(|Json(p)| p.mode);
+    // held until the players are notified, so nothing can be enqueued mid-flush
+    let mut queues = state.queue.write().await;
+    let mut ops = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for (mode, q) in queues.iter_mut() {
+        if only.is_some_and(|m| m != *mode) || q.len() == 0 {
+            continue;
+        }
+        q.retain(|e| {
+            ops.push(DbOp::DeleteQueueEntry(e.profile_id));
+            removed.extend(&e.members);
+            false
+        });
+        changed.push(*mode);
+    }
+    state.stage(ops);
+    let count = removed.len();
+    if !removed.is_empty() {
+        state.notify(Notification {
+            profile_ids: removed,
+            event: PlayerEvent::Dequeued {
+                reason: DequeueReason::AdminFlush,
+            },
+        });
+    }
+    drop(queues);
+    state.flush().await;
+    for mode in changed {
+        let _ = state.queue_changes.send(mode);
+    }
+
+    tracing::warn!(%admin, mode = ?only, removed = count, "admin flushed queue");
+    Ok((StatusCode::OK, Json(Flushed { removed: count })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/spectate",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    responses(
+        (status = 200, description = "Spectating", body = MatchInfo),
+        (status = 400, description = "Unknown profile or a player of the match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Match not active or no slots left", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn spectate_match(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    state.check_active(&player)?;
+
+    let mut matches = state.matches.lock().await;
+    let Some(m) = matches.get_mut(&id) else {
+        return Err(AppError::MatchNotFound);
+    };
+    if m.status != MatchStatus::Active {
+        return Err(invalid_transition("spectate", m));
+    }
+    if m.involves(player) {
+        return Err(AppError::SpectatorIsPlayer);
+    }
+    if !m.spectators.contains(&player) {
+        if m.spectators.len() >= state.config.max_spectators {
+            return Err(AppError::SpectatorsFull);
+        }
+        m.spectators.push(player);
+        state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    }
+    let m = m.clone();
+    drop(matches);
+    state.flush().await;
+    Ok((StatusCode::OK, Json(m)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/metadata",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body = MetadataEntry,
+    responses(
+        (status = 200, description = "Match with the entry set", body = MatchInfo),
+        (status = 400, description = "Key or value too long", body = ApiError),
+        (status = 403, description = "Caller is not in the match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn set_match_metadata(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<MetadataEntry>,
+) -> Result<impl IntoResponse, AppError> {
+    let key_len = payload.key.chars().count();
+    if key_len == 0 || key_len > METADATA_KEY_MAX || payload.value.chars().count() > METADATA_VALUE_MAX {
+        return Err(AppError::InvalidMetadata);
+    }
+
+    let mut matches = state.matches.lock().await;
+    let m = matches.get_mut(&id).ok_or(AppError::MatchNotFound)?;
+    check_participant(m, player)?;
+    m.metadata.insert(payload.key, payload.value);
+    let m = m.clone();
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    drop(matches);
+    state.flush().await;
+    Ok((StatusCode::OK, Json(m)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/matches/{id}/feedback",
+    tag = "matches",
+    params(("id" = Uuid, Path, description = "Match id")),
+    request_body = SubmitFeedback,
+    responses(
+        (status = 201, description = "Feedback recorded", body = MatchFeedback),
+        (status = 400, description = "Rating out of range or comment too long", body = ApiError),
+        (status = 403, description = "Token does not belong to a player of the match", body = ApiError),
+        (status = 404, description = "Match not found", body = ApiError),
+        (status = 409, description = "Match not completed or feedback already given", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn submit_feedback(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<SubmitFeedback>,
+) -> Result<impl IntoResponse, AppError> {
+    if payload.player_id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    let stars = 1..=5;
+    if !stars.contains(&payload.match_quality)
+        || payload.opponent_sportsmanship.is_some_and(|s| !stars.contains(&s))
+        || payload.comment.as_ref().is_some_and(|c| c.chars().count() > FEEDBACK_COMMENT_MAX)
+    {
+        return Err(AppError::InvalidFeedback);
+    }
+
+    let mut matches = state.matches.lock().await;
+    let m = matches.get_mut(&id).ok_or(AppError::MatchNotFound)?;
+    if !m.involves(player) {
+        return Err(AppError::NotMatchParticipant);
+    }
+    if m.status != MatchStatus::Completed {
+        return Err(AppError::MatchNotCompleted);
+    }
+    if m.feedback.iter().any(|f| f.player_id == player) {
+        return Err(AppError::FeedbackAlreadySubmitted);
+    }
+    let feedback = MatchFeedback {
+        player_id: player,
+        match_quality: payload.match_quality,
+        opponent_sportsmanship: payload.opponent_sportsmanship,
+        comment: payload.comment,
+    };
+    m.feedback.push(feedback.clone());
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    drop(matches);
+    state.flush().await;
+    Ok((StatusCode::CREATED, Json(feedback)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/reports",
+    tag = "reports",
+    request_body = CreateReport,
+    responses(
+        (status = 201, description = "Report recorded", body = Report),
+        (status = 400, description = "Self report, unknown player or a match without both players", body = ApiError),
+        (status = 403, description = "Token does not belong to the reporter", body = ApiError),
+        (status = 410, description = "Reporter or reported player is deactivated", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_report(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Json(payload): Json<CreateReport>,
+) -> Result<impl IntoResponse, AppError> {
+    if payload.reporter_id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    if payload.reported_id == player {
+        return Err(AppError::InvalidReport);
+    }
+    state.check_active(&player)?;
+    state.check_active(&payload.reported_id)?;
+    if let Some(match_id) = payload.match_id {
+        let matches = state.matches.lock().await;
+        let both_played = matches
+            .get(&match_id)
+            .is_some_and(|m| m.involves(player) && m.involves(payload.reported_id));
+        if !both_played {
+            return Err(AppError::InvalidReport);
+        }
+    }
+
+    let report = Report {
+        id: Uuid::new_v4(),
+        reporter_id: player,
+        reported_id: payload.reported_id,
+        match_id: payload.match_id,
+        reason: payload.reason,
+        created_at: Utc::now(),
+    };
+    // distinct reporters, so a single player cannot get someone suspended
+    let reporters = {
+        let mut reports = state.reports.lock().await;
+        reports.push(report.clone());
+        let since = report.created_at - chrono::Duration::days(REPORT_WINDOW_DAYS);
+        reports
+            .iter()
+            .filter(|r| r.reported_id == report.reported_id && r.created_at > since)
+            .map(|r| r.reporter_id)
+            .collect::<HashSet<_>>()
+            .len()
+    };
+    if reporters > state.config.report_suspend_threshold {
+        // a concurrent report may have suspended them already
+        if state
+            .deactivate(report.reported_id, CancelReason::Admin, "player suspended")
+            .await.is_ok() {
+            tracing::error!(
+                player = %report.reported_id,
+                reporters,
+                "player suspended after repeated reports"
+            );
+        }
+    }
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/reports",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every report, oldest first", body = [Report]),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn list_reports(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let reports = state.reports.lock().await.clone();
+    Ok((StatusCode::OK, Json(reports)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/matches/{id}/metadata/{key}",
+    tag = "matches",
+    params(
+        ("id" = Uuid, Path, description = "Match id"),
+        ("key" = String, Path, description = "Metadata key"),
+    ),
+    responses(
+        (status = 204, description = "Entry removed"),
+        (status = 403, description = "Caller is not in the match", body = ApiError),
+        (status = 404, description = "Match or key not found", body = ApiError),
+    ),
+    security(("bearer" = []), ("admin_key" = []))
+)]
+#[tracing::instrument(skip_all, fields(match_id = %id))]
+async fn delete_match_metadata(
+    State(state): State<Arc<AppState>>,
+    player: Option<Extension<AuthPlayer>>,
+    Path((id, key)): Path<(Uuid, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut matches = state.matches.lock().await;
+    let m = matches.get_mut(&id).ok_or(AppError::MatchNotFound)?;
+    check_participant(m, player)?;
+    if m.metadata.remove(&key).is_none() {
+        return Err(AppError::MetadataKeyNotFound);
+    }
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    drop(matches);
+    state.flush().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// players may only act for their own profile. without a player the request
+// was let through on the admin key
+fn check_owner(id: Uuid, player: Option<Extension<AuthPlayer>>) -> Result<(), AppError> {
+    match player {
+        Some(Extension(AuthPlayer(player))) if player != id => Err(AppError::ProfileMismatch),
+        _ => Ok(()),
+    }
+}
+
+// players may only touch their own match. without a player the request was
+// let through on the admin key
+fn check_participant(m: &MatchInfo, player: Option<Extension<AuthPlayer>>) -> Result<(), AppError> {
+    match player {
+        Some(Extension(AuthPlayer(id))) if !m.involves(id) => Err(AppError::NotMatchParticipant),
+        _ => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/matches/{id}/spectate/{profile_id}",
+    tag = "matches",
+    params(
+        ("id" = Uuid, Path, description = "Match id"),
+        ("profile_id" = Uuid, Path, description = "Spectating profile"),
+    ),
+    responses(
+        (status = 204, description = "Stopped spectating"),
+        (status = 403, description = "Token does not belong to the profile", body = ApiError),
+        (status = 404, description = "Match not found or not spectating", body = ApiError),
+    ),
+    security(("bearer" = []))
+)]
+async fn stop_spectating(
+    State(state): State<Arc<AppState>>,
+    Extension(AuthPlayer(player)): Extension<AuthPlayer>,
+    Path((id, profile_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AppError> {
+    if profile_id != player {
+        return Err(AppError::ProfileMismatch);
+    }
+    let mut matches = state.matches.lock().await;
+    let Some(m) = matches.get_mut(&id) else {
+        return Err(AppError::MatchNotFound);
+    };
+    let Some(pos) = m.spectators.iter().position(|s| *s == profile_id) else {
+        return Err(AppError::NotSpectating);
+    };
+    m.spectators.remove(pos);
+    state.stage(vec![DbOp::UpsertMatch(m.clone())]);
+    drop(matches);
+    state.flush().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/profiles",
+    tag = "profiles",
+    params(NameSearch),
+    responses(
+        (status = 200, description = "Profiles whose name contains the query, by name, or the one with the external id", body = [ProfileView]),
+        (status = 400, description = "Query shorter than 2 characters", body = ApiError),
+    )
+)]
+async fn search_profiles(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<NameSearch>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(external_id) = &query.external_id {
+        let found: Vec<ProfileView> = state
+            .shard_states()
+            .into_iter()
+            .find_map(|s| {
+                let id = s.external_ids.get(external_id)?;
+                s.profiles.get(&*id).map(|p| p.value().clone())
+            })
+            .map(ProfileView::from)
+            .into_iter()
+            .collect();
+        return Ok((StatusCode::OK, Json(found)));
+    }
+    let needle = normalize_name(query.name.as_deref().unwrap_or_default());
+    if needle.chars().count() < 2 {
+        return Err(AppError::QueryTooShort);
+    }
+    let found: Vec<ProfileView> = profiles_named(&state, &needle)
+        .into_iter()
+        .take(query.limit)
+        .map(ProfileView::from)
+        .collect();
+    Ok((StatusCode::OK, Json(found)))
+}
+
+fn external_ids<'a>(profiles: impl IntoIterator<Item = &'a Profile>) -> DashMap<String, Uuid> {
+    profiles
+        .into_iter()
+        .filter_map(|p| Some((p.external_id.clone()?, p.id)))
+        .collect()
+}
+
+// profiles whose normalized name contains `needle` (already normalized),
+// sorted by name. a linear scan over every profile of every shard for now
+fn profiles_named(state: &AppState, needle: &str) -> Vec<Profile> {
+    let mut found: Vec<Profile> = state
+        .shard_states()
+        .into_iter()
+        .flat_map(|s| s.profiles.iter())
+        .filter(|p| !p.deactivated && normalize_name(&p.name).contains(needle))
+        .map(|p| p.value().clone())
+        .collect();
+    found.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+    found
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/tournaments",
+    tag = "tournaments",
+    request_body = CreateTournament,
+    responses(
+        (status = 201, description = "Tournament with its first round", body = Tournament),
+        (status = 400, description = "Fewer than two distinct or unknown participants", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn create_tournament(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Json(payload): Json<CreateTournament>,
+) -> Result<impl IntoResponse, AppError> {
+    let distinct: HashSet<Uuid> = payload.participants.iter().copied().collect();
+    if distinct.len() < 2 || distinct.len() != payload.participants.len() {
+        return Err(AppError::InvalidTournament);
+    }
+
+    let mut matches = state.matches.lock().await;
+    // checked under the matches lock, which profile deactivation also holds
+    let mut rated = Vec::with_capacity(payload.participants.len());
+    for id in &payload.participants {
+        state.check_active(id)?;
+        let p = state.profiles.get(id).ok_or(AppError::UnknownProfile)?;
+        rated.push((*id, get_mode_mmr(&p, payload.mode)));
+    }
+
+    let mut t = Tournament {
+        id: Uuid::new_v4(),
+        name: payload.name,
+        format: payload.format,
+        mode: payload.mode,
+        seeds: tournament::seed(rated),
+        rounds: Vec::new(),
+        byes: Vec::new(),
+        winner: None,
+    };
+    let created = match t.next() {
+        Ok(Next::Round { pairs, byes }) => open_round(&state, &mut matches, &mut t, pairs, byes),
+        _ => unreachable!("a new bracket always has a first round"),
+    };
+    state.stage(created.iter().cloned().map(DbOp::UpsertMatch).collect());
+    drop(matches);
+    state.flush().await;
+    state.tournaments.lock().await.insert(t.id, t.clone());
+    state.record_home(t.id);
+
+    tracing::info!(%admin, tournament_id = %t.id, format = ?t.format, players = t.seeds.len(), "tournament created");
+    announce_matches(&state, &created).await;
+    Ok((StatusCode::CREATED, Json(t)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/tournaments/{id}",
+    tag = "tournaments",
+    params(("id" = Uuid, Path, description = "Tournament id")),
+    responses(
+        (status = 200, description = "Bracket with the current state of every match", body = Tournament),
+        (status = 404, description = "Tournament not found", body = ApiError),
+    )
+)]
+async fn get_tournament(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let matches = state.matches.lock().await;
+    let mut tournaments = state.tournaments.lock().await;
+    let t = tournaments.get_mut(&id).ok_or(AppError::TournamentNotFound)?;
+    refresh_rounds(t, &matches);
+    Ok((StatusCode::OK, Json(t.clone())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/tournaments/{id}/advance",
+    tag = "tournaments",
+    params(("id" = Uuid, Path, description = "Tournament id")),
+    responses(
+        (status = 200, description = "Tournament with the next round, or its winner", body = Tournament),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Tournament not found", body = ApiError),
+        (status = 409, description = "Round still running or tournament finished", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn advance_tournament(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut matches = state.matches.lock().await;
+    let mut tournaments = state.tournaments.lock().await;
+    let t = tournaments.get_mut(&id).ok_or(AppError::TournamentNotFound)?;
+    refresh_rounds(t, &matches);
+    if t.winner.is_some() {
+        return Err(AppError::TournamentFinished);
+    }
+
+    let created = match t.next().map_err(|_| AppError::RoundNotFinished)? {
+        Next::Round { pairs, byes } => open_round(&state, &mut matches, t, pairs, byes),
+        Next::Finished(winner) => {
+            t.winner = Some(winner);
+            tracing::info!(%admin, tournament_id = %id, %winner, "tournament finished");
+            Vec::new()
+        }
+    };
+    state.stage(created.iter().cloned().map(DbOp::UpsertMatch).collect());
+    let t = t.clone();
+    drop(tournaments);
+    drop(matches);
+    state.flush().await;
+
+    announce_matches(&state, &created).await;
+    Ok((StatusCode::OK, Json(t)))
+}
+
+// creates the matches of a new round and appends it to the bracket
+fn open_round(
+    state: &AppState,
+    matches: &mut HashMap<Uuid, MatchInfo>,
+    t: &mut Tournament,
+    pairs: Vec<(Uuid, Uuid)>,
+    byes: Vec<Uuid>,
+) -> Vec<MatchInfo> {
+    let round: Vec<MatchInfo> = pairs
+        .into_iter()
+        .map(|(player1, player2)| MatchInfo {
+            id: Uuid::new_v4(),
+            player1,
+            player2,
+            team1: vec![player1],
+            team2: vec![player2],
+            mode: t.mode,
+            created_at: Utc::now(),
+            started_at: None,
+            ended_at: None,
+            result: None,
+            report_team1: None,
+            report_team2: None,
+            disputed: false,
+            status: MatchStatus::Pending,
+            cancel_reason: None,
+            cancel_note: None,
+            ready_player1: false,
+            ready_player2: false,
+            spectators: Vec::new(),
+            tiers: state.tiers(&[player1, player2], t.mode),
+            quality: state.quality(&[player1], &[player2], t.mode),
+            metadata: HashMap::new(),
+            feedback: Vec::new(),
+            draft_state: None,
+            server_address: None,
+        })
+        .collect();
+    for m in &round {
+        matches.insert(m.id, m.clone());
+    }
+    t.rounds.push(round.clone());
+    t.byes.push(byes);
+    round
+}
+
+// replaces the bracket's copies with the current state of each match
+fn refresh_rounds(t: &mut Tournament, matches: &HashMap<Uuid, MatchInfo>) {
+    for m in t.rounds.iter_mut().flatten() {
+        if let Some(current) = matches.get(&m.id) {
+            *m = current.clone();
+        }
+    }
+}
+
+// tells the players and subscribers about newly created matches
+async fn announce_matches(state: &AppState, created: &[MatchInfo]) {
+    for m in created {
+        state.notify(Notification {
+            profile_ids: m.participants().collect(),
+            event: PlayerEvent::Matched { r#match: Box::new(m.clone()) },
+        });
+        state.count_created(m).await;
+        state.remember_opponents(m);
+        webhooks::dispatch(state, WebhookEvent::MatchCreated, m).await;
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/profiles/{id}/set_vip",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    request_body = SetVip,
+    responses(
+        (status = 200, description = "The updated profile", body = ProfileView),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Profile not found", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn set_vip(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<SetVip>,
+) -> Result<impl IntoResponse, AppError> {
+    // an entry already waiting keeps its lane until the player queues again
+    let profile = {
+        let mut p = state.profiles.get_mut(&id).ok_or(AppError::ProfileNotFound)?;
+        p.vip = payload.vip;
+        p.clone()
+    };
+    state.persist(vec![DbOp::UpsertProfile(profile.clone())]).await;
+    tracing::warn!(%admin, profile_id = %id, vip = payload.vip, "vip flag set");
+    Ok((StatusCode::OK, Json(ProfileView::from(profile))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/maintenance/on",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Maintenance mode is on", body = MaintenanceStatus),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn maintenance_on(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminIdentity>,
+) -> Result<impl IntoResponse, AppError> {
+    set_maintenance(&state, admin, true)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/maintenance/off",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Maintenance mode is off", body = MaintenanceStatus),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn maintenance_off(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<AdminIdentity>,
+) -> Result<impl IntoResponse, AppError> {
+    set_maintenance(&state, admin, false)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/snapshot/save",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Profiles, queues and matches written to snapshot_path", body = SnapshotSaved),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 500, description = "The file could not be written", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn save_snapshot(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+) -> Result<impl IntoResponse, AppError> {
+    let saved = snapshot::save(&state).await?;
+    tracing::warn!(%admin, path = %saved.path, bytes = saved.written_bytes, "snapshot saved");
+    Ok((StatusCode::OK, Json(saved)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/snapshot/restore",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Profiles, queues and matches replaced from snapshot_path", body = SnapshotRestored),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 500, description = "The file is missing, malformed or of another schema_version", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn restore_snapshot(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+) -> Result<impl IntoResponse, AppError> {
+    let restored = snapshot::restore(&state).await?;
+    tracing::warn!(
+        %admin,
+        path = %restored.path,
+        profiles = restored.profiles,
+        queued = restored.queued,
+        matches = restored.matches,
+        "snapshot restored"
+    );
+    Ok((StatusCode::OK, Json(restored)))
+}
+
+fn set_maintenance(
+    state: &AppState,
+    AdminIdentity(admin): AdminIdentity,
+    on: bool,
+) -> Result<(StatusCode, Json<MaintenanceStatus>), AppError> {
+    state.maintenance.store(on, Ordering::SeqCst);
+    tracing::warn!(%admin, maintenance = on, "maintenance mode toggled");
+    Ok((StatusCode::OK, Json(MaintenanceStatus { maintenance: on })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/seasons/reset",
+    tag = "admin",
+    request_body = SeasonReset,
+    responses(
+        (status = 200, description = "The new season", body = Season),
+        (status = 400, description = "decay_fraction outside 0..=1", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn reset_season(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Json(payload): Json<SeasonReset>,
+) -> Result<impl IntoResponse, AppError> {
+    if !(0.0..=1.0).contains(&payload.decay_fraction) {
+        return Err(AppError::InvalidSeasonReset);
+    }
+
+    let mut season = state.season.lock().await;
+    let baseline = payload.baseline as f64;
+    let keep = 1.0 - payload.decay_fraction;
+    let mut ops = Vec::new();
+    for mut p in state.profiles.iter_mut() {
+        for mode in GameMode::ALL.into_iter().filter(|mode| mode.is_ranked()) {
+            let mmr = baseline + (get_mode_mmr(&p, mode) as f64 - baseline) * keep;
+            p.set_mode_mmr(mode, (mmr.round().max(0.0) as u32).max(state.config.season_min_mmr));
+        }
+        p.season_peak_mmr = p.best_ranked_mmr();
+        ops.push(DbOp::UpsertProfile(p.clone()));
+    }
+    let reset = ops.len();
+    *season = Season {
+        number: season.number + 1,
+        started_at: Utc::now(),
+    };
+    ops.push(DbOp::InsertSeason(season.clone()));
+    state.stage(ops);
+    let current = season.clone();
+    drop(season);
+    state.flush().await;
+
+    tracing::warn!(
+        %admin,
+        season = current.number,
+        decay_fraction = payload.decay_fraction,
+        baseline = payload.baseline,
+        profiles = reset,
+        "season reset"
+    );
+    Ok((StatusCode::OK, Json(current)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/seasons/current",
+    tag = "seasons",
+    responses((status = 200, description = "Number and start of the running season", body = Season))
+)]
+async fn current_season(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let season = state.season.lock().await.clone();
+    Ok((StatusCode::OK, Json(season)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/leaderboard",
+    tag = "profiles",
+    params(LeaderboardQuery),
+    responses((status = 200, description = "Rated players, best first", body = Leaderboard))
+)]
+async fn get_leaderboard(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((StatusCode::OK, Json(leaderboard_page(&state, &query).await)))
+}
+
+// shared by HTTP and GraphQL
+async fn leaderboard_page(state: &AppState, query: &LeaderboardQuery) -> Leaderboard {
+    let mut ranked = leaderboard(state, query.mode, query.sort).await;
+    if query.exclude_provisional {
+        ranked.retain(|p| !p.is_provisional());
+    }
+    let total = ranked.len();
+    let entries = ranked
+        .into_iter()
+        .enumerate()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|(i, profile)| LeaderboardEntry {
+            rank: i + 1,
+            mmr: get_mode_mmr(&profile, query.mode),
+            profile: profile.into(),
+        })
+        .collect();
+    Leaderboard { total, entries }
+}
+
+// every player with a completed match on `mode`'s rating track, ordered by
+// that mmr (or the peak, see `sort`), then wins, then oldest profile first.
+// rebuilt on each call, from every shard if there are any
+async fn leaderboard(state: &AppState, mode: GameMode, sort: LeaderboardSort) -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = Vec::new();
+    for shard in state.shard_states() {
+        let rated: HashSet<Uuid> = shard
+            .matches
+            .lock()
+            .await
+            .values()
+            .filter(|m| m.status == MatchStatus::Completed && m.mode.is_ranked() == mode.is_ranked())
+            .flat_map(|m| m.team1.iter().chain(&m.team2).copied())
+            .collect();
+        profiles.extend(
+            shard
+                .profiles
+                .iter()
+                .filter(|p| !p.deactivated && rated.contains(p.key()))
+                .map(|p| p.value().clone()),
+        );
+    }
+    let key = |p: &Profile| match sort {
+        LeaderboardSort::Mmr => get_mode_mmr(p, mode),
+        LeaderboardSort::PeakMmr => p.mode_peak_mmr(mode),
+    };
+    profiles.sort_by(|a, b| {
+        key(b)
+            .cmp(&key(a))
+            .then(b.wins.cmp(&a.wins))
+            .then(a.created_at.cmp(&b.created_at))
+    });
+    profiles
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/analytics/match_duration",
+    tag = "analytics",
+    responses((
+        status = 200,
+        description = "Duration of completed matches in seconds, all zero when there are none",
+        body = DurationStats
+    ))
+)]
+async fn match_duration_stats(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let mut secs: Vec<f64> = Vec::new();
+    for shard in state.shard_states() {
+        let matches = shard.matches.lock().await;
+        secs.extend(matches.values().filter_map(|m| m.duration()).map(|d| d.as_secs_f64()));
+    }
+    secs.sort_by(f64::total_cmp);
+
+    let body = if secs.is_empty() {
+        DurationStats {
+            avg_seconds: 0.0,
+            p50: 0.0,
+            p95: 0.0,
+        }
+    } else {
+        DurationStats {
+            avg_seconds: secs.iter().sum::<f64>() / secs.len() as f64,
+            p50: percentile(&secs, 0.5),
+            p95: percentile(&secs, 0.95),
+        }
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/analytics/feedback",
+    tag = "analytics",
+    params(FeedbackQuery),
+    responses((status = 200, description = "Averages over all submitted feedback", body = FeedbackStats))
+)]
+async fn feedback_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedbackQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut shards = Vec::new();
+    for shard in state.shard_states() {
+        shards.push(shard.matches.lock().await);
+    }
+    let reviews: Vec<&MatchFeedback> = shards
+        .iter()
+        .flat_map(|m| m.values())
+        .filter(|m| query.mode.is_none_or(|mode| m.mode == mode))
+        .flat_map(|m| &m.feedback)
+        .collect();
+
+    let average = |values: Vec<u8>| {
+        (!values.is_empty())
+            .then(|| values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64)
+    };
+    let body = FeedbackStats {
+        reviews: reviews.len(),
+        avg_match_quality: average(reviews.iter().map(|f| f.match_quality).collect()),
+        avg_opponent_sportsmanship: average(
+            reviews.iter().filter_map(|f| f.opponent_sportsmanship).collect(),
+        ),
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/analytics/match_quality",
+    tag = "analytics",
+    params(QualityQuery),
+    responses((
+        status = 200,
+        description = "Quality of the newest matches of any status, all zero when there are none",
+        body = QualityStats
+    ))
+)]
+async fn match_quality_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<QualityQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut rated: Vec<(DateTime<Utc>, f64)> = Vec::new();
+    for shard in state.shard_states() {
+        let matches = shard.matches.lock().await;
+        rated.extend(matches.values().filter_map(|m| Some((m.created_at, m.quality?))));
+    }
+    rated.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+    let mut scores: Vec<f64> = rated.iter().take(query.last).map(|(_, q)| *q).collect();
+    scores.sort_by(f64::total_cmp);
+
+    let body = if scores.is_empty() {
+        QualityStats {
+            avg: 0.0,
+            p10: 0.0,
+            p90: 0.0,
+        }
+    } else {
+        QualityStats {
+            avg: scores.iter().sum::<f64>() / scores.len() as f64,
+            p10: percentile(&scores, 0.1),
+            p90: percentile(&scores, 0.9),
+        }
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+// nearest-rank percentile of a sorted, non-empty slice
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "Webhook registered", body = Webhook),
+        (status = 400, description = "Invalid url or event list", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn create_webhook(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Json(payload): Json<CreateWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let valid_url = payload.url.starts_with("https://") || payload.url.starts_with("http://");
+    if !valid_url || payload.events.is_empty() {
+        return Err(AppError::InvalidWebhook);
+    }
+
+    let hook = Webhook {
+        id: Uuid::new_v4(),
+        url: payload.url,
+        events: payload.events,
+        key: webhooks::SigningKey::new(&payload.secret),
+        disabled: false,
+    };
+    state.webhooks.lock().await.push(hook.clone());
+    tracing::info!(%admin, webhook_id = %hook.id, url = %hook.url, "webhook registered");
+    Ok((StatusCode::CREATED, Json(hook)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Registered webhooks", body = [Webhook]),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn list_webhooks(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let hooks = state.webhooks.lock().await.clone();
+    Ok((StatusCode::OK, Json(hooks)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = Uuid, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Webhook removed"),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Webhook not found", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn delete_webhook(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut hooks = state.webhooks.lock().await;
+    let Some(pos) = hooks.iter().position(|h| h.id == id) else {
+        return Err(AppError::WebhookNotFound);
+    };
+    hooks.remove(pos);
+    tracing::info!(%admin, webhook_id = %id, "webhook removed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookStatus {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub circuit: CircuitStatus,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/webhooks",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Registered webhooks with their circuit breakers", body = [WebhookStatus]),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+async fn admin_webhooks(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let mut hooks = state.webhooks.lock().await;
+    let list: Vec<WebhookStatus> = hooks
+        .iter_mut()
+        .map(|hook| {
+            state.webhook_circuits.disable_if_stuck(hook);
+            WebhookStatus {
+                circuit: state.webhook_circuits.status(&hook.url),
+                webhook: hook.clone(),
+            }
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(list)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/health",
+    tag = "ops",
+    responses((status = 200, description = "The process is alive", body = Probe))
+)]
+async fn health() -> Result<impl IntoResponse, AppError> {
+    let body = Probe {
+        status: "ok",
+        reason: None,
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+// ready once the shared state can be locked and the database, if any, hands
+// out connections
+#[utoipa::path(
+    get,
+    path = "/v1/ready",
+    tag = "ops",
+    responses(
+        (status = 200, description = "Ready to serve", body = Probe),
+        (status = 503, description = "Not ready", body = Probe),
+    )
+)]
+async fn ready(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let locks = async {
+        drop(state.queue.read().await);
+        drop(state.matches.lock().await);
+    };
+    let mut reason = None;
+    if state.shutting_down.load(Ordering::SeqCst) {
+        reason = Some("shutting down".to_string());
+    } else if tokio::time::timeout(READY_LOCK_TIMEOUT, locks).await.is_err() {
+        reason = Some("state locks unavailable".to_string());
+    } else if let Err(e) = state.store.ping().await {
+        reason = Some(format!("database unavailable: {e}"));
+    }
+
+    match reason {
+        None => {
+            let body = Probe {
+                status: "ready",
+                reason: None,
+            };
+            Ok((StatusCode::OK, Json(body)))
+        }
+        Some(reason) => {
+            let body = Probe {
+                status: "unavailable",
+                reason: Some(reason),
+            };
+            Ok((StatusCode::SERVICE_UNAVAILABLE, Json(body)))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/metrics",
+    tag = "ops",
+    responses((status = 200, description = "Prometheus metrics", content_type = "text/plain"))
+)]
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let (mut profiles, mut waiting) = (0, 0);
+    for shard in state.shard_states() {
+        profiles += shard.profiles.len();
+        waiting += shard
+            .queue
+            .read()
+            .await
+            .values()
+            .flat_map(ModeQueue::iter)
+            .map(|e| e.members.len())
+            .sum::<usize>();
+    }
+    state.metrics.profiles_total.set(profiles as i64);
+    state.metrics.queue_depth.set(waiting as i64);
+
+    Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/ws/matches",
+    tag = "matches",
+    params(WsParams),
+    responses((status = 101, description = "WebSocket with match notifications"))
+)]
+async fn ws_matches(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WsParams>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(ws.on_upgrade(move |socket| notify_player(socket, state, params.profile_id)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/stream/queue",
+    tag = "queue",
+    params(WsParams),
+    responses((
+        status = 200,
+        description = "Server-sent events: `position` { \"position\": N } on every change, \
+                       then `matched` with the match or `dequeued` when removed from the queue, \
+                       or `error` when not queued",
+        content_type = "text/event-stream"
+    ))
+)]
+async fn stream_queue(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WsParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(watch_position(state, params.profile_id, tx));
+    Ok(Sse::new(ReceiverStream::new(rx)).keep_alive(KeepAlive::default()))
+}
+
+// sends the queue position of `profile_id` whenever it changes, until they
+// are matched, leave the queue or the client goes away
+async fn watch_position(
+    state: Arc<AppState>,
+    profile_id: Uuid,
+    tx: mpsc::Sender<Result<Event, Infallible>>,
+) {
+    // subscribe before the first look at the queue so no change is missed
+    let mut events = state.events.subscribe();
+    let mut changes = state.queue_changes.subscribe();
+    let mut last = None;
+
+    loop {
+        let current = queue_position(&*state.queue.read().await, profile_id);
+        let Some(position) = current.map(|p| p.position) else {
+            // match and dequeue events are published before the player
+            // leaves the queue, so if there is one it is already buffered
+            let exit = take_exit(&mut events, profile_id);
+            let matched = match &exit {
+                Some(PlayerEvent::Matched { r#match }) => Some(r#match.id),
+                _ => None,
+            };
+            let event = match exit {
+                Some(PlayerEvent::Matched { r#match }) => Event::default().event("matched").json_data(r#match),
+                Some(dequeued) => Event::default().event("dequeued").json_data(dequeued),
+                None => Event::default()
+                    .event("error")
+                    .json_data(ApiError::from(AppError::NotInQueue)),
+            };
+            if tx.send(Ok(event.unwrap())).await.is_err() {
+                return;
+            }
+            // a matched player also hears where the match is played
+            if let Some(match_id) = matched {
+                send_server_address(&state, &mut events, profile_id, match_id, &tx).await;
+            }
+            return;
+        };
+        if last != Some(position) {
+            last = Some(position);
+            let event = Event::default()
+                .event("position")
+                .json_data(PositionUpdate { position })
+                .unwrap();
+            if tx.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            change = changes.recv() => {
+                if let Err(broadcast::error::RecvError::Closed) = change {
+                    return;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+// waits for the server_assigned event of `match_id` and sends it on. gives up
+// when the match is completed or cancelled first, when `profile_id` queues
+// again, or when the client goes away
+async fn send_server_address(
+    state: &AppState,
+    events: &mut broadcast::Receiver<Notification>,
+    profile_id: Uuid,
+    match_id: Uuid,
+    tx: &mpsc::Sender<Result<Event, Infallible>>,
+) {
+    let mut match_changes = state.match_changes.subscribe();
+    let mut queue_changes = state.queue_changes.subscribe();
+    let still_waiting = || async {
+        let open = state
+            .matches
+            .lock()
+            .await
+            .get(&match_id)
+            .is_some_and(|m| matches!(m.status, MatchStatus::Pending | MatchStatus::Active));
+        open && queue_position(&*state.queue.read().await, profile_id).is_none()
+    };
+    // the match may have ended before the channels were subscribed
+    if !still_waiting().await {
+        return;
+    }
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(Notification { event: PlayerEvent::ServerAssigned { match_id: id, address }, .. })
+                    if id == match_id =>
+                {
+                    let assigned = PlayerEvent::ServerAssigned { match_id, address };
+                    let event = Event::default().event("server_assigned").json_data(assigned).unwrap();
+                    let _ = tx.send(Ok(event)).await;
+                    return;
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            change = match_changes.recv() => match change {
+                Ok(id) if id != match_id => {}
+                Err(broadcast::error::RecvError::Closed) => return,
+                _ => {
+                    if !still_waiting().await {
+                        return;
+                    }
+                }
+            },
+            change = queue_changes.recv() => {
+                if matches!(change, Err(broadcast::error::RecvError::Closed)) || !still_waiting().await {
+                    return;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+// the already received event that took `profile_id` out of the queue (a
+// match or a dequeue), if any, skipping other events
+fn take_exit(events: &mut broadcast::Receiver<Notification>, profile_id: Uuid) -> Option<PlayerEvent> {
+    loop {
+        match events.try_recv() {
+            // a server for another of the player's matches is no exit
+            Ok(Notification {
+                event: PlayerEvent::ServerAssigned { .. },
+                ..
+            }) => {}
+            Ok(Notification { profile_ids, event }) if profile_ids.contains(&profile_id) => {
+                return Some(event)
+            }
+            Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+// forwards events for `profile_id` to the socket until the client goes away
+async fn notify_player(mut socket: WebSocket, state: Arc<AppState>, profile_id: Uuid) {
+    let mut events = state.events.subscribe();
+    let mut ping = tokio::time::interval(WS_PING_INTERVAL);
+    let mut last_pong = Instant::now();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(n) if n.profile_ids.contains(&profile_id) => {
+                    let text = serde_json::to_string(&n.event).unwrap();
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = ping.tick() => {
+                // no pong for two intervals means the client is gone
+                if last_pong.elapsed() > WS_PING_INTERVAL * 2 {
+                    return;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    return;
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Pong(_))) => last_pong = Instant::now(),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// average `mode` mmr of the listed players that still exist, None if none do
+fn team_mmr(profiles: &DashMap<Uuid, Profile>, team: &[Uuid], mode: GameMode) -> Option<f64> {
+    let mmrs: Vec<f64> = team
+        .iter()
+        .filter_map(|id| profiles.get(id).map(|p| get_mode_mmr(&p, mode) as f64))
+        .collect();
+    if mmrs.is_empty() {
+        None
+    } else {
+        Some(mmrs.iter().sum::<f64>() / mmrs.len() as f64)
+    }
+}
+
+// teams are rated by their average mmr and every member moves by the
+// rating change of their team, scaled by their own K-factor (provisional
+// players move faster, losers on a long streak lose less) and clamped into
+// `bounds`. only the track of the
+// match's mode is touched
+#[tracing::instrument(skip_all, fields(match_id = %m.id))]
+fn apply_elo(profiles: &DashMap<Uuid, Profile>, m: &MatchInfo, k: f64, bounds: elo::Bounds) {
+    let (Some(mmr1), Some(mmr2)) = (
+        team_mmr(profiles, &m.team1, m.mode),
+        team_mmr(profiles, &m.team2, m.mode),
+    ) else {
+        // one side no longer exists, nothing to rate
+        return;
+    };
+
+    // Some(true) when team1 won, None for a draw
+    let team1_won = match m.result {
+        Some(MatchResult::Player1Win) => Some(true),
+        Some(MatchResult::Player2Win) => Some(false),
+        Some(MatchResult::Draw) => None,
+        None => return,
+    };
+
+    let sides = [
+        (&m.team1, mmr1, mmr2, team1_won),
+        (&m.team2, mmr2, mmr1, team1_won.map(|won| !won)),
+    ];
+    for (team, own_team, other_team, won) in sides {
+        for id in team {
+            if let Some(mut p) = profiles.get_mut(id) {
+                let mut k = p.k_factor(k);
+                if won == Some(false) && p.current_loss_streak >= elo::COMEBACK_STREAK {
+                    k *= elo::COMEBACK_MULTIPLIER;
+                }
+                // rated against the other team shifted by the member's distance
+                // from their own average: same expected score as the team, but
+                // the update and its clamping apply to the member's own mmr
+                let own = get_mode_mmr(&p, m.mode) as f64;
+                let other = other_team + own - own_team;
+                let new = match won {
+                    Some(true) => elo::update_elo(own, other, k, bounds).0,
+                    Some(false) => elo::update_elo(other, own, k, bounds).1,
+                    None => elo::update_elo_draw(own, other, k, bounds).0,
+                };
+                let new = new.round() as u32;
+                p.set_mode_mmr(m.mode, new);
+                p.raise_peaks(m.mode, new);
+            }
+        }
+    }
+}
+
+// bumps the win/loss/draw counters, games played and streaks of both participants
+fn record_outcome(profiles: &DashMap<Uuid, Profile>, m: &MatchInfo) {
+    let (p1_won, p2_won) = match m.result {
+        Some(MatchResult::Player1Win) => (Some(true), Some(false)),
+        Some(MatchResult::Player2Win) => (Some(false), Some(true)),
+        Some(MatchResult::Draw) => (None, None),
+        None => return,
+    };
+
+    let sides = m.team1.iter().map(|id| (id, p1_won));
+    for (id, won) in sides.chain(m.team2.iter().map(|id| (id, p2_won))) {
+        let Some(mut p) = profiles.get_mut(id) else {
+            continue;
+        };
+        p.record_game(won);
+        p.last_game_at = m.ended_at;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Profile {
+        Profile {
+            id: Uuid::new_v4(),
+            name: "player".to_string(),
+            ranked_mmr: 1000,
+            casual_mmr: 1000,
+            mmr_by_mode: HashMap::new(),
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            region: Region::default(),
+            created_at: Utc::now(),
+            season_peak_mmr: 1000,
+            peak_mmr: 1000,
+            peak_mmr_by_mode: HashMap::new(),
+            games_played: 0,
+            last_game_at: None,
+            decayed_until: None,
+            friends: HashSet::new(),
+            friend_requests: HashSet::new(),
+            blocked: HashSet::new(),
+            guild_id: None,
+            external_id: None,
+            current_loss_streak: 0,
+            deactivated: false,
+            vip: false,
+            penalty_count: 0,
+            ban_until: None,
+        }
+    }
+
+    #[test]
+    fn loss_extends_streak() {
+        let mut p = profile();
+        p.record_game(Some(false));
+        p.record_game(Some(false));
+        assert_eq!(p.current_loss_streak, 2);
+        assert_eq!(p.losses, 2);
+    }
+
+    #[test]
+    fn win_resets_streak() {
+        let mut p = profile();
+        p.current_loss_streak = 4;
+        p.record_game(Some(true));
+        assert_eq!(p.current_loss_streak, 0);
+        assert_eq!(p.wins, 1);
+    }
+
+    #[test]
+    fn draw_keeps_streak() {
+        let mut p = profile();
+        p.current_loss_streak = 3;
+        p.record_game(None);
+        assert_eq!(p.current_loss_streak, 3);
+        assert_eq!((p.draws, p.games_played), (1, 1));
+    }
+
+    #[test]
+    fn legacy_tracks_seed_every_mode() {
+        let mut p = profile();
+        p.ranked_mmr = 1400;
+        p.casual_mmr = 900;
+        p.mmr_by_mode.insert(GameMode::Arcade, 1100);
+        p.fill_mode_mmr();
+        assert_eq!(get_mode_mmr(&p, GameMode::RankedDuo), 1400);
+        assert_eq!(get_mode_mmr(&p, GameMode::CasualSolo), 900);
+        assert_eq!(get_mode_mmr(&p, GameMode::Arcade), 1100);
+    }
+
+    #[test]
+    fn headline_fields_follow_their_modes() {
+        let mut p = profile();
+        p.set_mode_mmr(GameMode::RankedDuo, 1300);
+        p.set_mode_mmr(GameMode::CasualSolo, 950);
+        assert_eq!((p.ranked_mmr, p.casual_mmr), (1000, 950));
+    }
+
+    #[tokio::test]
+    async fn assigning_a_server_starts_the_match() {
+        let config = config::Config {
+            jwt_secret: Some("assign-server".to_string()),
+            matching_interval_ms: 0,
+            ..config::Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let mut joined = Vec::new();
+        for name in ["alice", "bob"] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr: 1000,
+                region: Region::Europe,
+            };
+            let id = new_profile(&state, payload).await.unwrap().id;
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::CasualSolo,
+                team_size: None,
+            };
+            joined.push(join_queue(&state, id, payload).await.unwrap());
+        }
+        let Some(Enqueued::Matched(m)) = joined.pop() else {
+            panic!("alice and bob should be matched");
+        };
+        let mut events = state.events.subscribe();
+        let admin = || Extension(AdminIdentity("ops".to_string()));
+        let assign = |address: &str| {
+            Json(AssignServer {
+                address: address.to_string(),
+            })
+        };
+
+        let bad = assign_server(State(state.clone()), admin(), Path(m.id), assign("localhost")).await;
+        assert!(matches!(bad, Err(AppError::InvalidServerAddress)));
+        assign_server(State(state.clone()), admin(), Path(m.id), assign("192.168.1.100:7777"))
+            .await
+            .unwrap();
+
+        let stored = state.matches.lock().await[&m.id].clone();
+        let address: SocketAddr = "192.168.1.100:7777".parse().unwrap();
+        assert_eq!(stored.status, MatchStatus::Active);
+        assert_eq!(stored.server_address, Some(address));
+        let sent = events.try_recv().unwrap();
+        assert_eq!(sent.profile_ids.len(), 2);
+        let json = serde_json::to_value(&sent.event).unwrap();
+        assert_eq!(json["event"], "server_assigned");
+        assert_eq!(json["address"], "192.168.1.100:7777");
+    }
+
+    #[tokio::test]
+    async fn queue_stream_ends_when_the_match_is_cancelled_before_a_server() {
+        let config = config::Config {
+            jwt_secret: Some("stream-cancel".to_string()),
+            matching_interval_ms: 0,
+            ..config::Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut joined = Vec::new();
+        for name in ["alice", "bob"] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr: 1000,
+                region: Region::Europe,
+            };
+            let id = new_profile(&state, payload).await.unwrap().id;
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::CasualSolo,
+                team_size: None,
+            };
+            joined.push(join_queue(&state, id, payload).await.unwrap());
+            if joined.len() == 1 {
+                tokio::spawn(watch_position(state.clone(), id, tx.clone()));
+                // the first position, so the watcher is subscribed
+                assert!(rx.recv().await.is_some());
+            }
+        }
+        drop(tx);
+        let Some(Enqueued::Matched(m)) = joined.pop() else {
+            panic!("alice and bob should be matched");
+        };
+        // matched, then waiting for a server
+        assert!(rx.recv().await.is_some());
+
+        cancel_match(State(state.clone()), None, Path(m.id), None)
+            .await
+            .unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert!(matches!(ended, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn stale_players_hear_why_before_the_queue_changes() {
+        let config = config::Config {
+            jwt_secret: Some("sweep-stale".to_string()),
+            stale_timeout_secs: 0,
+            ..config::Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let payload = CreateProfile {
+            name: "alice".to_string(),
+            mmr: 1000,
+            region: Region::Europe,
+        };
+        let id = new_profile(&state, payload).await.unwrap().id;
+        let payload = QueueRequest {
+            profile_id: id,
+            party_id: None,
+            mode: GameMode::CasualSolo,
+            team_size: None,
+        };
+        join_queue(&state, id, payload).await.unwrap();
+        let mut events = state.events.subscribe();
+        let mut changes = state.queue_changes.subscribe();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let sweep = tokio::spawn(sweep_queue(state.clone()));
+        let changed = tokio::time::timeout(Duration::from_secs(5), changes.recv()).await;
+        sweep.abort();
+        assert!(matches!(changed, Ok(Ok(GameMode::CasualSolo))));
+        let n = events.try_recv().unwrap();
+        assert_eq!(n.profile_ids, vec![id]);
+        assert!(matches!(
+            n.event,
+            PlayerEvent::Dequeued {
+                reason: DequeueReason::Stale
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn peak_sort_follows_the_requested_mode() {
+        let config = config::Config {
+            jwt_secret: Some("peak-sort".to_string()),
+            matching_interval_ms: 0,
+            ..config::Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let mut players = Vec::new();
+        let mut joined = None;
+        for name in ["alice", "bob"] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr: 1000,
+                region: Region::Europe,
+            };
+            let id = new_profile(&state, payload).await.unwrap().id;
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::RankedSolo,
+                team_size: None,
+            };
+            joined = Some(join_queue(&state, id, payload).await.unwrap());
+            players.push(id);
+        }
+        let Some(Enqueued::Matched(m)) = joined else {
+            panic!("alice and bob should be matched");
+        };
+        state.matches.lock().await.get_mut(&m.id).unwrap().transition(MatchStatus::Active);
+        record_result(&state, None, m.id, Winner::Draw).await.unwrap();
+        // alice once peaked on duo, bob on solo
+        for (id, mode) in [(players[0], GameMode::RankedDuo), (players[1], GameMode::RankedSolo)] {
+            state.profiles.get_mut(&id).unwrap().raise_peaks(mode, 1600);
+        }
+
+        for (mode, first) in [(GameMode::RankedSolo, players[1]), (GameMode::RankedDuo, players[0])] {
+            let board = leaderboard(&state, mode, LeaderboardSort::PeakMmr).await;
+            assert_eq!(board.len(), 2);
+            assert_eq!(board[0].id, first, "{mode:?}");
+        }
+    }
+
+    // a store whose writes wait while the test holds `gate`
+    struct Gated {
+        inner: Arc<dyn db::MatchmakerStore>,
+        gate: Arc<RwLock<()>>,
+    }
+
+    #[axum::async_trait]
+    impl db::MatchmakerStore for Gated {
+        async fn load(&self) -> Result<db::Loaded, sqlx::Error> {
+            self.inner.load().await
+        }
+        async fn apply(&self, ops: Vec<DbOp>) -> Result<(), sqlx::Error> {
+            let _open = self.gate.read().await;
+            self.inner.apply(ops).await
+        }
+        async fn ping(&self) -> Result<(), sqlx::Error> {
+            self.inner.ping().await
+        }
+        async fn close(&self) {
+            self.inner.close().await
+        }
+        async fn find_profile(&self, id: Uuid) -> Result<Option<Profile>, sqlx::Error> {
+            self.inner.find_profile(id).await
+        }
+        async fn get_queue_entries(&self, mode: GameMode) -> Result<Vec<QueueEntry>, sqlx::Error> {
+            self.inner.get_queue_entries(mode).await
+        }
+    }
+
+    #[tokio::test]
+    async fn the_queue_is_not_locked_while_its_changes_are_written() {
+        let config = config::Config {
+            jwt_secret: Some("slow-store".to_string()),
+            ..config::Config::default()
+        };
+        let gate = Arc::new(RwLock::new(()));
+        let store: Arc<dyn db::MatchmakerStore> = Arc::new(Gated {
+            inner: db::memory(),
+            gate: gate.clone(),
+        });
+        let state = Arc::new(AppState::new(config, store.clone(), db::Loaded::default()));
+        let payload = CreateProfile {
+            name: "alice".to_string(),
+            mmr: 1000,
+            region: Region::Europe,
+        };
+        let id = new_profile(&state, payload).await.unwrap().id;
+        let payload = QueueRequest {
+            profile_id: id,
+            party_id: None,
+            mode: GameMode::CasualSolo,
+            team_size: None,
+        };
+
+        let closed = gate.write().await;
+        let joining = tokio::spawn({
+            let state = state.clone();
+            async move { join_queue(&state, id, payload).await.map(|_| ()) }
+        });
+        let queued = async {
+            loop {
+                if state.queue.read().await.values().any(|q| q.len() > 0) {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), queued).await.unwrap();
+        assert!(!joining.is_finished());
+
+        drop(closed);
+        joining.await.unwrap().unwrap();
+        let stored = store.get_queue_entries(GameMode::CasualSolo).await.unwrap();
+        assert_eq!(stored.len(), 1);
+    }
+}
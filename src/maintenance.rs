@@ -0,0 +1,33 @@
+// Maintenance mode: while `AppState::maintenance` is set every request gets
+// 503, except the health probes and anything sent with the admin key.
+
+use std::{sync::atomic::Ordering, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{auth, error::AppError, AppState};
+
+pub async fn reject_during_maintenance<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !state.maintenance.load(Ordering::SeqCst) {
+        return next.run(req).await;
+    }
+    let path = req.uri().path();
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    let exempt = matches!(path, "/health" | "/ready")
+        || path.starts_with("/admin/")
+        || auth::admin_identity(&state, &req).is_some();
+    if exempt {
+        next.run(req).await
+    } else {
+        AppError::Maintenance.into_response()
+    }
+}
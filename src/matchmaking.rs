@@ -0,0 +1,524 @@
+// Opponent selection for the queue, and the background matcher used instead
+// of matching inside enqueue when `matching_interval_ms` is set.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    announce_match, create_match, db::DbOp, lanes::ModeQueue, AppState, GameMode, MatchInfo,
+    QueueEntry,
+};
+
+// the queue entries joining an incoming entry in its match, as indices in
+// queue order
+#[derive(Debug, Default)]
+pub struct Picked {
+    // on the incoming entry's side, only with a team size
+    pub teammates: Vec<usize>,
+    pub opponents: Vec<usize>,
+}
+
+impl Picked {
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.teammates.iter().chain(&self.opponents).copied()
+    }
+}
+
+// players who just faced each other, or where either side blocked the
+// other, are kept apart whatever the mode's strategy. entries asking for
+// different team sizes never meet
+fn allowed(state: &AppState, waiting: &QueueEntry, incoming: &QueueEntry) -> bool {
+    waiting.team_size == incoming.team_size
+        && !state.played_recently(&incoming.members, &waiting.members)
+        && !state.blocked_between(&incoming.members, &waiting.members)
+}
+
+// whether `waiting` may be matched against `incoming`: same region and mmr
+// within the waiting entry's window, with the region filter dropped once the
+// window is fully expanded
+fn compatible(state: &AppState, waiting: &QueueEntry, incoming: &QueueEntry) -> bool {
+    if !allowed(state, waiting, incoming) {
+        return false;
+    }
+    let waited = waiting.queued_at.elapsed();
+    if waiting.region != incoming.region && !state.region_relaxed(waited) {
+        return false;
+    }
+    waiting.mmr.abs_diff(incoming.mmr) <= state.mmr_window(waited)
+}
+
+// picks the queue entries that will form the opposing side for `incoming`.
+// an entry of the same size (a solo player or an equally sized party) is
+// preferred; a party can otherwise be matched against solo players filling
+// the side. VIP entries come first in the queue, so they are preferred over
+// equally good normal ones. in GuildPractice an entry from the same guild
+// beats every other, wherever it is in the queue. a mode's strategy, if set,
+// picks the same-sized opponent; solo players filling a party's side are
+// always picked by `compatible`. entries with a team size are pooled by
+// `fill_teams` instead
+#[tracing::instrument(skip_all, fields(?mode, queued = queue.len()))]
+pub fn find_opponents(
+    state: &AppState,
+    mode: GameMode,
+    queue: &ModeQueue,
+    incoming: &QueueEntry,
+) -> Option<Picked> {
+    if let Some(team_size) = incoming.team_size {
+        return fill_teams(state, queue, incoming, team_size.into());
+    }
+    let size = incoming.members.len();
+    let opponents = |opponents| {
+        Some(Picked {
+            teammates: Vec::new(),
+            opponents,
+        })
+    };
+
+    if mode == GameMode::GuildPractice {
+        if let Some(guild) = state.guild_of(&incoming.profile_id) {
+            if let Some(idx) = queue.iter().position(|e| {
+                e.members.len() == size
+                    && state.guild_of(&e.profile_id) == Some(guild)
+                    && compatible(state, e, incoming)
+            }) {
+                return opponents(vec![idx]);
+            }
+        }
+    }
+
+    if let Some(strategy) = state.strategies.get(&mode) {
+        let (indices, candidates): (Vec<usize>, Vec<&QueueEntry>) = queue
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.members.len() == size && allowed(state, e, incoming))
+            .unzip();
+        let mut rng = state.rng.lock().unwrap();
+        if let Some(i) = strategy.find_opponent(incoming, &candidates, &mut rng) {
+            return opponents(vec![indices[i]]);
+        }
+    } else if let Some(idx) = queue
+        .iter()
+        .position(|e| e.members.len() == size && compatible(state, e, incoming))
+    {
+        return opponents(vec![idx]);
+    }
+    if size == 1 {
+        return None;
+    }
+
+    let solos: Vec<usize> = queue
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.members.len() == 1 && compatible(state, e, incoming))
+        .map(|(idx, _)| idx)
+        .take(size)
+        .collect();
+    if solos.len() < size {
+        return None;
+    }
+    opponents(solos)
+}
+
+// entries in queue order fill the incoming entry's side and then the other
+// one, each up to `size` players, parties kept whole. every entry must be
+// within the incoming one's window, like a single opponent, and may not
+// have met or blocked anyone already picked. sides are balanced only by
+// that window; their average mmr rates the match
+fn fill_teams(
+    state: &AppState,
+    queue: &ModeQueue,
+    incoming: &QueueEntry,
+    size: usize,
+) -> Option<Picked> {
+    let mut picked = Picked::default();
+    let (mut own, mut other) = (incoming.members.len(), 0);
+    for (idx, e) in queue.iter().enumerate() {
+        if own == size && other == size {
+            break;
+        }
+        if !compatible(state, e, incoming)
+            || picked
+                .indices()
+                .filter_map(|i| queue.iter().nth(i))
+                .any(|p| !allowed(state, p, e))
+        {
+            continue;
+        }
+        let n = e.members.len();
+        if own + n <= size {
+            own += n;
+            picked.teammates.push(idx);
+        } else if other + n <= size {
+            other += n;
+            picked.opponents.push(idx);
+        }
+    }
+    (own == size && other == size).then_some(picked)
+}
+
+// forms every match the queues allow each `interval`, under one queue lock
+// per pass, so a burst of joins is matched in batches
+pub async fn match_queues(state: Arc<AppState>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        for m in match_waiting(&state).await {
+            announce_match(&state, &m).await;
+        }
+    }
+}
+
+// one pass: each entry in queue order looks for opponents among the others,
+// as if it had just joined. entries that find none keep their place. with a
+// Redis queue the other instances' entries are pulled in first, and a match
+// is only made once its entries are claimed there
+async fn match_waiting(state: &Arc<AppState>) -> Vec<MatchInfo> {
+    let mut created = Vec::new();
+    let mut queues = state.queue.write().await;
+    if let Some(redis) = &state.redis {
+        // changes staged before this lock was taken must be in the lists
+        // first, or their entries would look matched elsewhere
+        state.flush().await;
+        for mode in GameMode::ALL {
+            redis
+                .pull(state, mode, queues.entry(mode).or_default())
+                .await;
+        }
+    }
+    for (&mode, queue) in queues.iter_mut() {
+        let mut idx = 0;
+        while idx < queue.len() {
+            let entry = queue.remove(idx).unwrap();
+            let Some(picked) = find_opponents(state, mode, queue, &entry) else {
+                queue.insert(entry);
+                idx += 1;
+                continue;
+            };
+            if let Some(redis) = &state.redis {
+                let others = picked.indices().filter_map(|i| queue.iter().nth(i));
+                let players = std::iter::once(entry.profile_id).chain(others.map(|e| e.profile_id));
+                if !redis.claim(mode, players).await {
+                    queue.insert(entry);
+                    idx += 1;
+                    continue;
+                }
+            }
+            // entries taken from in front of `idx` move the rest forward
+            idx -= picked.indices().filter(|&p| p < idx).count();
+            state.record_wait(entry.queued_at.elapsed()).await;
+            state.stage(vec![DbOp::DeleteQueueEntry(entry.profile_id)]);
+            created.push(create_match(state, mode, queue, &picked, entry).await);
+        }
+    }
+    drop(queues);
+    state.flush().await;
+    created
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashMap, sync::Arc};
+
+    use proptest::{
+        prelude::*,
+        test_runner::{Config as ProptestConfig, RngSeed, TestRunner},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        config::Config, db, error::AppError, join_queue, new_profile, record_result,
+        remove_from_queue, CreateProfile, Enqueued, MatchStatus, QueueRequest, Region, Winner,
+    };
+
+    use super::*;
+
+    const PLAYERS: usize = 6;
+    const MODES: [GameMode; 2] = [GameMode::RankedSolo, GameMode::CasualSolo];
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Enqueue(usize, usize),
+        Leave(usize, usize),
+        // both captains ready up, moving the player's pending match to Active
+        Start(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..PLAYERS, 0..MODES.len()).prop_map(|(p, m)| Op::Enqueue(p, m)),
+            (0..PLAYERS, 0..MODES.len()).prop_map(|(p, m)| Op::Leave(p, m)),
+            (0..PLAYERS).prop_map(Op::Start),
+        ]
+    }
+
+    // queues every player is in, counted by member
+    async fn queued(state: &AppState) -> HashMap<Uuid, usize> {
+        let mut seen = HashMap::new();
+        for q in state.queue.read().await.values() {
+            for id in q.iter().flat_map(|e| &e.members) {
+                *seen.entry(*id).or_default() += 1;
+            }
+        }
+        seen
+    }
+
+    async fn open_matches(state: &AppState, player: Uuid, status: &[MatchStatus]) -> Vec<Uuid> {
+        let matches = state.matches.lock().await;
+        matches
+            .values()
+            .filter(|m| m.involves(player) && status.contains(&m.status))
+            .map(|m| m.id)
+            .collect()
+    }
+
+    // the number of matches `ops` made
+    async fn run(ops: Vec<Op>) -> Result<usize, TestCaseError> {
+        let config = Config {
+            jwt_secret: Some("proptest".to_string()),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let mut players = Vec::new();
+        for i in 0..PLAYERS {
+            let payload = CreateProfile {
+                name: format!("player{i}"),
+                mmr: 1000,
+                region: Region::Europe,
+            };
+            players.push(new_profile(&state, payload).await.unwrap().id);
+        }
+
+        let mut matched = 0;
+        for op in ops {
+            match op {
+                Op::Enqueue(p, m) => {
+                    let payload = QueueRequest {
+                        profile_id: players[p],
+                        party_id: None,
+                        mode: MODES[m],
+                        team_size: None,
+                    };
+                    // rejections (open match, other mode) are part of the game
+                    if let Ok(Enqueued::Matched(m)) = join_queue(&state, players[p], payload).await
+                    {
+                        matched += 1;
+                        for id in m.participants() {
+                            prop_assert!(!queued(&state).await.contains_key(&id));
+                            let open = [MatchStatus::Pending, MatchStatus::Active];
+                            prop_assert_eq!(open_matches(&state, id, &open).await, vec![m.id]);
+                        }
+                    }
+                }
+                Op::Leave(p, m) => {
+                    let payload = QueueRequest {
+                        profile_id: players[p],
+                        party_id: None,
+                        mode: MODES[m],
+                        team_size: None,
+                    };
+                    let _ = remove_from_queue(&state, players[p], &payload).await;
+                }
+                Op::Start(p) => {
+                    let mut matches = state.matches.lock().await;
+                    let pending = matches
+                        .values_mut()
+                        .find(|m| m.involves(players[p]) && m.status == MatchStatus::Pending);
+                    if let Some(m) = pending {
+                        m.transition(MatchStatus::Active);
+                    }
+                }
+            }
+
+            for (id, count) in queued(&state).await {
+                prop_assert!(count <= 1, "{id} is queued {count} times");
+            }
+            for &id in &players {
+                let active = open_matches(&state, id, &[MatchStatus::Active]).await;
+                prop_assert!(
+                    active.len() <= 1,
+                    "{id} is in {} active matches",
+                    active.len()
+                );
+            }
+        }
+        Ok(matched)
+    }
+
+    #[tokio::test]
+    async fn background_pass_matches_waiting_players() {
+        let config = Config {
+            jwt_secret: Some("matcher".to_string()),
+            matching_interval_ms: 100,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let mut players = Vec::new();
+        for (name, mmr) in [("alice", 1000), ("bob", 2000), ("carol", 1000)] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr,
+                region: Region::Europe,
+            };
+            let id = new_profile(&state, payload).await.unwrap().id;
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::RankedSolo,
+                team_size: None,
+            };
+            // enqueue only queues, even once an opponent is waiting
+            let joined = join_queue(&state, id, payload).await.unwrap();
+            assert!(matches!(joined, Enqueued::Waiting(_)));
+            players.push(id);
+        }
+
+        let created = match_waiting(&state).await;
+        assert_eq!(created.len(), 1);
+        // alice searched first, so the match has alice joining carol
+        assert_eq!(created[0].team1, [players[2]]);
+        assert_eq!(created[0].team2, [players[0]]);
+        let queued: Vec<Uuid> = queued(&state).await.into_keys().collect();
+        assert_eq!(queued, [players[1]]);
+        assert!(match_waiting(&state).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mode_strategy_replaces_the_window() {
+        let config = Config {
+            jwt_secret: Some("strategy".to_string()),
+            matching_strategies: HashMap::from([(GameMode::CasualSolo, "closest".to_string())]),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let mut players = Vec::new();
+        for (name, mmr) in [
+            ("alice", 1000),
+            ("bob", 1700),
+            ("carol", 1000),
+            ("dave", 1700),
+        ] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr,
+                region: Region::Europe,
+            };
+            players.push(new_profile(&state, payload).await.unwrap().id);
+        }
+        let join = |id, mode| {
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode,
+                team_size: None,
+            };
+            join_queue(&state, id, payload)
+        };
+
+        // far outside the default 150 window, the closest rating still wins
+        let joined = join(players[0], GameMode::CasualSolo).await.unwrap();
+        assert!(matches!(joined, Enqueued::Waiting(_)));
+        let joined = join(players[1], GameMode::CasualSolo).await.unwrap();
+        assert!(matches!(joined, Enqueued::Matched(_)));
+
+        // other modes keep the window
+        let joined = join(players[2], GameMode::RankedSolo).await.unwrap();
+        assert!(matches!(joined, Enqueued::Waiting(_)));
+        let joined = join(players[3], GameMode::RankedSolo).await.unwrap();
+        assert!(matches!(joined, Enqueued::Waiting(_)));
+    }
+
+    #[tokio::test]
+    async fn solo_players_are_pooled_into_teams() {
+        let config = Config {
+            jwt_secret: Some("teams".to_string()),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let mut players = Vec::new();
+        for (name, mmr) in [
+            ("alice", 1000),
+            ("bob", 1050),
+            ("carol", 950),
+            ("dave", 1000),
+            ("erin", 1000),
+        ] {
+            let payload = CreateProfile {
+                name: name.to_string(),
+                mmr,
+                region: Region::Europe,
+            };
+            players.push(new_profile(&state, payload).await.unwrap().id);
+        }
+        let join = |id, team_size| {
+            let payload = QueueRequest {
+                profile_id: id,
+                party_id: None,
+                mode: GameMode::RankedSolo,
+                team_size,
+            };
+            join_queue(&state, id, payload)
+        };
+
+        let rejected = join(players[4], Some(3)).await;
+        assert!(matches!(rejected, Err(AppError::InvalidTeamSize)));
+        // without a team size erin waits for a 1v1, apart from the 2v2 pool
+        let joined = join(players[4], None).await.unwrap();
+        assert!(matches!(joined, Enqueued::Waiting(_)));
+        for &id in &players[..3] {
+            let joined = join(id, Some(2)).await.unwrap();
+            assert!(matches!(joined, Enqueued::Waiting(_)));
+        }
+        let Enqueued::Matched(m) = join(players[3], Some(2)).await.unwrap() else {
+            panic!("the fourth player should complete both teams");
+        };
+        // dave's side is filled first, with the longest waiting
+        assert_eq!(m.team2, [players[3], players[0]]);
+        assert_eq!(m.team1, [players[1], players[2]]);
+        let queued: Vec<Uuid> = queued(&state).await.into_keys().collect();
+        assert_eq!(queued, [players[4]]);
+
+        state
+            .matches
+            .lock()
+            .await
+            .get_mut(&m.id)
+            .unwrap()
+            .transition(MatchStatus::Active);
+        record_result(&state, None, m.id, Winner::Player1).await.unwrap();
+        // everyone is rated, against the other side's average
+        for (id, before, won) in [
+            (players[0], 1000, false),
+            (players[1], 1050, true),
+            (players[2], 950, true),
+            (players[3], 1000, false),
+        ] {
+            let p = state.profiles.get(&id).unwrap();
+            assert_eq!(p.ranked_mmr > before, won, "{}", p.name);
+            assert_eq!(p.wins == 1, won, "{}", p.name);
+            assert_eq!(p.losses == 1, !won, "{}", p.name);
+        }
+    }
+
+    #[test]
+    fn queue_and_match_invariants_hold() {
+        // a fixed seed and no persisted failures, so every run tries the same cases
+        let mut runner = TestRunner::new(ProptestConfig {
+            cases: 64,
+            rng_seed: RngSeed::Fixed(0x6d61_7463_686d_6b72),
+            failure_persistence: None,
+            ..ProptestConfig::default()
+        });
+        let matched = Cell::new(0);
+        runner
+            .run(&prop::collection::vec(op(), 1..40), |ops| {
+                let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+                matched.set(matched.get() + rt.block_on(run(ops))?);
+                Ok(())
+            })
+            .unwrap();
+        // the invariants say nothing if no case got as far as a match
+        assert!(matched.get() > 0, "no generated case made a match");
+    }
+}
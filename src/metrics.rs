@@ -0,0 +1,65 @@
+// Prometheus metrics exposed on GET /metrics.
+
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+
+pub struct Metrics {
+    registry: Registry,
+    // gauges are refreshed from the app state on every scrape
+    pub queue_depth: IntGauge,
+    pub profiles_total: IntGauge,
+    pub matches_created: IntCounter,
+    pub matches_completed: IntCounter,
+    pub enqueue_duration: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new();
+        let queue_depth = IntGauge::new(
+            "matchmaker_queue_depth",
+            "Players waiting in all queues",
+        )
+        .unwrap();
+        let profiles_total =
+            IntGauge::new("matchmaker_profiles_total", "Number of profiles").unwrap();
+        let matches_created = IntCounter::new(
+            "matchmaker_matches_created_total",
+            "Matches created by the matchmaker",
+        )
+        .unwrap();
+        let matches_completed = IntCounter::new(
+            "matchmaker_matches_completed_total",
+            "Matches with a recorded result",
+        )
+        .unwrap();
+        let enqueue_duration = Histogram::with_opts(HistogramOpts::new(
+            "matchmaker_enqueue_duration_seconds",
+            "Time spent handling enqueue requests",
+        ))
+        .unwrap();
+
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(profiles_total.clone())).unwrap();
+        registry.register(Box::new(matches_created.clone())).unwrap();
+        registry.register(Box::new(matches_completed.clone())).unwrap();
+        registry.register(Box::new(enqueue_duration.clone())).unwrap();
+
+        Metrics {
+            registry,
+            queue_depth,
+            profiles_total,
+            matches_created,
+            matches_completed,
+            enqueue_duration,
+        }
+    }
+
+    // all metrics in the text exposition format
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
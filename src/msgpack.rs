@@ -0,0 +1,209 @@
+// MessagePack bodies next to JSON. a request body is read as MessagePack when
+// sent with `Content-Type: application/msgpack` (see `MsgpackOrJson`), and a
+// JSON response is re-encoded as MessagePack when the request's `Accept`
+// names application/msgpack (see `negotiate`). MessagePack maps use the same
+// field names as the JSON bodies.
+
+use axum::{
+    async_trait,
+    body::{self, Bytes, Full, HttpBody},
+    extract::FromRequest,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
+use serde::de::DeserializeOwned;
+
+pub const MSGPACK: &str = "application/msgpack";
+
+// both spellings are in use; the unregistered x- one by older clients
+pub(crate) fn is_msgpack(value: Option<&HeaderValue>) -> bool {
+    value.and_then(|v| v.to_str().ok()).is_some_and(|v| {
+        v.split(',').any(|part| {
+            let mime = part.split(';').next().unwrap_or("").trim();
+            mime.eq_ignore_ascii_case(MSGPACK) || mime.eq_ignore_ascii_case("application/x-msgpack")
+        })
+    })
+}
+
+// a request body in either format, picked by Content-Type. JSON bodies go
+// through `Json`, so they are accepted and rejected exactly as before
+#[derive(Debug)]
+pub struct MsgpackOrJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for MsgpackOrJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_msgpack(req.headers().get(CONTENT_TYPE)) {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(MsgpackOrJson(value));
+        }
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        // as for JSON: 422 for a well-formed body of the wrong shape, 400 for
+        // one that does not decode at all
+        // human readable, so ids and timestamps are strings as in JSON
+        let mut de = rmp_serde::Deserializer::new(&bytes[..]).with_human_readable();
+        T::deserialize(&mut de).map(MsgpackOrJson).map_err(|e| {
+            use rmp_serde::decode::Error;
+            let status = match e {
+                Error::Syntax(_) | Error::TypeMismatch(_) | Error::OutOfRange | Error::LengthMismatch(_) => {
+                    StatusCode::UNPROCESSABLE_ENTITY
+                }
+                _ => StatusCode::BAD_REQUEST,
+            };
+            (status, format!("Failed to deserialize the MessagePack body: {e}")).into_response()
+        })
+    }
+}
+
+// re-encodes JSON responses for clients that accept MessagePack. anything
+// else (text, event streams, websocket upgrades) passes through untouched
+pub async fn negotiate<B>(req: Request<B>, next: Next<B>) -> Response {
+    let wants_msgpack = is_msgpack(req.headers().get(ACCEPT));
+    let res = next.run(req).await;
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !wants_msgpack || !is_json {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let encoded = hyper::body::to_bytes(body)
+        .await
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        .and_then(|value| rmp_serde::to_vec_named(&value).ok());
+    let Some(encoded) = encoded else {
+        tracing::error!("could not re-encode a JSON response as MessagePack");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    parts.headers.insert(CONTENT_TYPE, HeaderValue::from_static(MSGPACK));
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Full::from(encoded)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header::AUTHORIZATION, Method},
+        Router,
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{config::Config, db::{self, Loaded}, AppState};
+
+    const SECRET: &str = "msgpack-test-secret";
+
+    fn app() -> Router {
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            ..Config::default()
+        };
+        crate::app(Arc::new(AppState::new(config, db::memory(), Loaded::default())))
+    }
+
+    fn token(player: Uuid) -> String {
+        let claims = json!({ "sub": player, "exp": chrono::Utc::now().timestamp() + 3600 });
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        format!("Bearer {}", jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap())
+    }
+
+    // a msgpack POST as `player`, with the peer address the rate limiter reads
+    fn post(path: &str, player: Uuid, body: &Value) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(path)
+            .header(CONTENT_TYPE, MSGPACK)
+            .header(ACCEPT, MSGPACK)
+            .header(AUTHORIZATION, token(player))
+            .body(Body::from(rmp_serde::to_vec_named(body).unwrap()))
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        req
+    }
+
+    async fn decode(res: Response) -> Value {
+        assert_eq!(res.headers()[CONTENT_TYPE], MSGPACK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        rmp_serde::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn queue_request_round_trips_as_msgpack() {
+        let app = app();
+        let res = app
+            .clone()
+            .oneshot(post("/v1/profiles", Uuid::nil(), &json!({ "name": "alice", "mmr": 1200 })))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let profile = decode(res).await;
+        assert_eq!(profile["ranked_mmr"], 1200);
+        let id: Uuid = profile["id"].as_str().unwrap().parse().unwrap();
+
+        let queue = json!({ "profile_id": id, "mode": "CasualSolo" });
+        let res = app.oneshot(post("/v1/queue/enqueue", id, &queue)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let waiting = decode(res).await;
+        assert_eq!(waiting["status"], "enqueued");
+        assert_eq!(waiting["queue_position"], 1);
+    }
+
+    #[tokio::test]
+    async fn errors_are_msgpack_too() {
+        let res = app()
+            .oneshot(post("/v1/queue/leave", Uuid::nil(), &json!({ "profile_id": Uuid::nil() })))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(decode(res).await["code"], "NOT_IN_QUEUE");
+    }
+
+    #[tokio::test]
+    async fn undecodable_body_is_rejected() {
+        let mut req = post("/v1/profiles", Uuid::nil(), &json!({}));
+        // a one-entry map that ends before its entry
+        *req.body_mut() = Body::from(vec![0x81]);
+        assert_eq!(app().oneshot(req).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        let missing_name = post("/v1/profiles", Uuid::nil(), &json!({ "mmr": 1000 }));
+        let res = app().oneshot(missing_name).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn json_stays_the_default() {
+        let req = Request::builder().uri("/v1/health").body(Body::empty()).unwrap();
+        let res = app().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+    }
+}
@@ -0,0 +1,215 @@
+// Response key naming. JSON responses use snake_case keys; a client can ask
+// for camelCase with `?naming=camelCase` or an `Accept: application/json;
+// naming=camelCase` parameter, and `negotiate` then renames the keys of the
+// finished body, errors included. a layer rather than a response type, like
+// msgpack.rs, so every handler answers the same way without changes. keys are
+// renamed wherever they appear, so underscores in user-chosen keys (match
+// metadata) are renamed too. camelCase is deprecated: the plan is snake_case
+// only.
+
+use axum::{
+    body::{self, Full},
+    extract::Query,
+    http::{
+        header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Naming {
+    SnakeCase,
+    CamelCase,
+}
+
+impl Naming {
+    fn parse(value: &str) -> Option<Naming> {
+        match value.trim() {
+            "snake_case" => Some(Naming::SnakeCase),
+            "camelCase" => Some(Naming::CamelCase),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NamingQuery {
+    naming: Option<String>,
+}
+
+// the `naming` parameter of an Accept header, as in `application/json;
+// naming=camelCase`
+fn from_accept(headers: &HeaderMap) -> Option<&str> {
+    let accept = headers.get(ACCEPT)?.to_str().ok()?;
+    accept
+        .split([',', ';'])
+        .filter_map(|part| part.trim().strip_prefix("naming="))
+        .next()
+}
+
+// the query parameter wins over the header; anything unknown is a 400
+fn requested<B>(req: &Request<B>) -> Result<Naming, AppError> {
+    let query = Query::<NamingQuery>::try_from_uri(req.uri())
+        .ok()
+        .and_then(|Query(q)| q.naming);
+    match query.as_deref().or_else(|| from_accept(req.headers())) {
+        None => Ok(Naming::SnakeCase),
+        Some(value) => Naming::parse(value).ok_or(AppError::InvalidNaming),
+    }
+}
+
+// snake_case to camelCase; keys without underscores stay as they are
+fn camel_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' && !out.is_empty() {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// renames every object key in `value`, however deeply nested
+fn rename(value: Value, naming: Naming) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, v)| {
+                    let key = match naming {
+                        Naming::SnakeCase => key,
+                        Naming::CamelCase => camel_case(&key),
+                    };
+                    (key, rename(v, naming))
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|v| rename(v, naming)).collect()),
+        other => other,
+    }
+}
+
+// renames the keys of JSON responses for clients that ask for camelCase.
+// sits inside msgpack::negotiate, so MessagePack bodies get the same keys
+pub async fn negotiate<B>(req: Request<B>, next: Next<B>) -> Response {
+    let naming = match requested(&req) {
+        Ok(naming) => naming,
+        Err(e) => return e.into_response(),
+    };
+    if naming == Naming::SnakeCase {
+        return next.run(req).await;
+    }
+    tracing::warn!(
+        path = %req.uri().path(),
+        "camelCase response keys are deprecated and will be removed, use snake_case"
+    );
+
+    let res = next.run(req).await;
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    let renamed = hyper::body::to_bytes(body)
+        .await
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+        .and_then(|value| serde_json::to_vec(&rename(value, naming)).ok());
+    let Some(renamed) = renamed else {
+        tracing::error!("could not rename the keys of a JSON response");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Full::from(renamed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{body::Body, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        db::{self, Loaded},
+        AppState,
+    };
+
+    fn app() -> Router {
+        let config = Config {
+            jwt_secret: Some("naming-test-secret".to_string()),
+            ..Config::default()
+        };
+        crate::app(Arc::new(AppState::new(
+            config,
+            db::memory(),
+            Loaded::default(),
+        )))
+    }
+
+    async fn get(uri: &str, accept: Option<&str>) -> (StatusCode, Value) {
+        let mut req = Request::builder().uri(uri);
+        if let Some(accept) = accept {
+            req = req.header(ACCEPT, accept);
+        }
+        let res = app()
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn keys_become_camel_case() {
+        assert_eq!(camel_case("queue_position"), "queuePosition");
+        assert_eq!(camel_case("estimated_wait_seconds"), "estimatedWaitSeconds");
+        assert_eq!(camel_case("player1"), "player1");
+        assert_eq!(camel_case("_id"), "_id");
+    }
+
+    #[tokio::test]
+    async fn errors_and_bodies_follow_the_requested_naming() {
+        let missing = format!("/v1/profiles/{}", uuid::Uuid::nil());
+        let (status, body) = get(&format!("{missing}?naming=camelCase"), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "PROFILE_NOT_FOUND");
+
+        let (_, stats) = get("/v1/queue/stats?naming=camelCase", None).await;
+        let (_, plain) = get("/v1/queue/stats", None).await;
+        let renamed = rename(plain.clone(), Naming::CamelCase);
+        assert_eq!(stats, renamed);
+        assert!(stats.to_string().contains("matchesCreatedLastMinute"));
+        assert!(!stats.to_string().contains("matches_created_last_minute"));
+        let (_, header) = get(
+            "/v1/queue/stats",
+            Some("application/json; naming=camelCase"),
+        )
+        .await;
+        assert_eq!(header, renamed);
+        let (_, snake) = get("/v1/queue/stats?naming=snake_case", None).await;
+        assert_eq!(snake, plain);
+
+        let (status, body) = get("/v1/queue/stats?naming=kebab-case", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "INVALID_NAMING");
+    }
+}
@@ -0,0 +1,337 @@
+// Tokens from an external OAuth2 / OpenID Connect provider (Google, Cognito,
+// ...), accepted next to the HS256 tokens signed with JWT_SECRET. with
+// OAUTH2_JWKS_URL set, a bearer token signed with any other algorithm is
+// checked against the provider's published keys, picked by the token's
+// `kid`. the key set is cached and fetched again when a token names a kid
+// it does not have, at most once per JWKS_REFRESH_MIN so garbage tokens
+// cannot hammer the provider. the token's `sub` is the player's external id,
+// linked to a profile by creating it with that token.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::error::AppError;
+
+// shortest time between two key set fetches
+pub const JWKS_REFRESH_MIN: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+struct Key {
+    decoding: DecodingKey,
+    // the algorithm the provider published for the key, if any
+    alg: Option<Algorithm>,
+}
+
+pub struct Jwks {
+    url: String,
+    // checked against `aud` and `iss` when set
+    audience: Option<String>,
+    issuer: Option<String>,
+    refresh_min: Duration,
+    http: reqwest::Client,
+    keys: RwLock<HashMap<String, Key>>,
+    // also held while fetching, so concurrent misses fetch once
+    fetched_at: Mutex<Option<Instant>>,
+}
+
+impl Jwks {
+    pub fn new(
+        url: String,
+        audience: Option<String>,
+        issuer: Option<String>,
+        refresh_min: Duration,
+    ) -> Jwks {
+        Jwks {
+            url,
+            audience,
+            issuer,
+            refresh_min,
+            http: reqwest::Client::new(),
+            keys: RwLock::new(HashMap::new()),
+            fetched_at: Mutex::new(None),
+        }
+    }
+
+    // the external id a provider token was issued for, if it is valid
+    pub async fn verify(&self, token: &str) -> Result<String, AppError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| rejected(&e))?;
+        let kid = header.kid.ok_or_else(|| rejected(&"token has no kid"))?;
+        if !self.keys.read().await.contains_key(&kid) {
+            self.refresh(&kid).await;
+        }
+        let keys = self.keys.read().await;
+        let key = keys
+            .get(&kid)
+            .ok_or_else(|| rejected(&format!("unknown kid {kid}")))?;
+        // only the published algorithm, never one picked by the token
+        if key.alg.is_some_and(|alg| alg != header.alg) {
+            return Err(rejected(&format!(
+                "kid {kid} does not sign {:?}",
+                header.alg
+            )));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        match &self.audience {
+            Some(aud) => validation.set_audience(&[aud]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(iss) = &self.issuer {
+            validation.set_issuer(&[iss]);
+        }
+        let data = jsonwebtoken::decode::<Claims>(token, &key.decoding, &validation)
+            .map_err(|e| rejected(&e))?;
+        Ok(data.claims.sub)
+    }
+
+    async fn refresh(&self, kid: &str) {
+        let mut fetched_at = self.fetched_at.lock().await;
+        // someone else fetched it while this one waited for the lock
+        if self.keys.read().await.contains_key(kid) {
+            return;
+        }
+        if fetched_at.is_some_and(|at| at.elapsed() < self.refresh_min) {
+            return;
+        }
+        *fetched_at = Some(Instant::now());
+        match self.fetch().await {
+            Ok(keys) => {
+                tracing::info!(url = %self.url, keys = keys.len(), "fetched JWKS");
+                *self.keys.write().await = keys;
+            }
+            // the keys already cached stay in use
+            Err(e) => tracing::error!(url = %self.url, "could not fetch JWKS: {e}"),
+        }
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, Key>, reqwest::Error> {
+        let set: JwkSet = self
+            .http
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(set.keys.iter().filter_map(key).collect())
+    }
+}
+
+// keys without a kid or of an unsupported kind are skipped
+fn key(jwk: &Jwk) -> Option<(String, Key)> {
+    let kid = jwk.common.key_id.clone()?;
+    let decoding = DecodingKey::from_jwk(jwk)
+        .map_err(|e| tracing::warn!(%kid, "skipping JWKS key: {e}"))
+        .ok()?;
+    let alg = jwk
+        .common
+        .key_algorithm
+        .and_then(|alg| alg.to_string().parse().ok());
+    Some((kid, Key { decoding, alg }))
+}
+
+fn rejected(reason: &dyn std::fmt::Display) -> AppError {
+    tracing::debug!("rejected provider token: {reason}");
+    AppError::Unauthorized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Method, Request, StatusCode,
+        },
+        routing::get,
+        Json, Router,
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        db::{self, Loaded},
+        AppState,
+    };
+
+    const PRIVATE_KEY: &[u8] = include_bytes!("../tests/fixtures/oauth_rsa.pem");
+    // the modulus of PRIVATE_KEY, base64url
+    const MODULUS: &str = "6BeMIqTaHe2nadvRjyJprNcHz1zQ5oamyplfP0gTdUm5LxhTV6zMN6KTk3jCeay_IIa6EsqnYseFxyaGjMV9iCEq96iMlltmtljL_yKULUPni5lORWVbKe11LgUNd3Z8rTvR2qTG-7e3nnICm6QOIpAr8QOUOrMPLJF3tu-fkwllcDPxi8pWR3mwS2jg5OIpe9Zl0ba7bJZct38Wmo50YqBGiqJ4RTrbHdmMsiWKf39QndnL6KQnEQHQeH4dOGgxOeVaJxFEvE-_so2KTnR1zmY1cBbezcF9kg7ZR0oO_LMeN_BVScZxvFGorUYvoPWhWKWrvLw5TuB99C1hOiKHtQ";
+
+    struct Provider {
+        url: String,
+        kids: Arc<std::sync::Mutex<Vec<&'static str>>>,
+        fetches: Arc<AtomicUsize>,
+    }
+
+    // a JWKS endpoint publishing PRIVATE_KEY's public half under `kids`
+    async fn provider(kids: &[&'static str]) -> Provider {
+        let kids = Arc::new(std::sync::Mutex::new(kids.to_vec()));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let (published, counter) = (kids.clone(), fetches.clone());
+        let app = Router::new().route(
+            "/jwks",
+            get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let keys: Vec<Value> = published
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|kid| {
+                        json!({ "kty": "RSA", "kid": kid, "alg": "RS256", "use": "sig", "n": MODULUS, "e": "AQAB" })
+                    })
+                    .collect();
+                Json(json!({ "keys": keys }))
+            }),
+        );
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}/jwks", server.local_addr());
+        tokio::spawn(server);
+        Provider { url, kids, fetches }
+    }
+
+    fn token(kid: &str, sub: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let claims = json!({
+            "sub": sub,
+            "aud": "matchmaker",
+            "exp": chrono::Utc::now().timestamp() + 3600,
+        });
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY).unwrap();
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    fn request(method: Method, path: &str, token: &str, body: Value) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        req
+    }
+
+    async fn send(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn provider_token_creates_and_finds_a_linked_profile() {
+        let provider = provider(&["k1"]).await;
+        let config = Config {
+            jwt_secret: Some("oauth-test-secret".to_string()),
+            oauth2_jwks_url: Some(provider.url.clone()),
+            ..Config::default()
+        };
+        let app = crate::app(Arc::new(AppState::new(
+            config,
+            db::memory(),
+            Loaded::default(),
+        )));
+        let google = token("k1", "google-oauth2|42");
+
+        let alice = json!({ "name": "alice" });
+        let (status, profile) =
+            send(&app, request(Method::POST, "/v1/profiles", &google, alice)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(profile["external_id"], "google-oauth2|42");
+        let id = profile["id"].clone();
+
+        let lookup = "/v1/profiles?external_id=google-oauth2%7C42";
+        let (status, found) = send(&app, request(Method::GET, lookup, &google, Value::Null)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found[0]["id"], id);
+
+        // later requests act as the linked profile
+        let queue = json!({ "profile_id": id, "mode": "CasualSolo" });
+        let (status, _) = send(
+            &app,
+            request(Method::POST, "/v1/queue/enqueue", &google, queue),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let again = json!({ "name": "alice2" });
+        let (status, body) =
+            send(&app, request(Method::POST, "/v1/profiles", &google, again)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "EXTERNAL_ID_TAKEN");
+
+        // unknown kid: rejected, and the refresh is rate limited
+        let forged = token("k2", "google-oauth2|42");
+        let bob = json!({ "name": "bob" });
+        let (status, _) = send(&app, request(Method::POST, "/v1/profiles", &forged, bob)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_refetches_the_key_set() {
+        let provider = provider(&["old"]).await;
+        let jwks = Jwks::new(
+            provider.url.clone(),
+            Some("matchmaker".to_string()),
+            None,
+            Duration::ZERO,
+        );
+        assert_eq!(
+            jwks.verify(&token("old", "player-1")).await.unwrap(),
+            "player-1"
+        );
+        assert!(jwks.verify(&token("new", "player-1")).await.is_err());
+
+        provider.kids.lock().unwrap().push("new");
+        assert_eq!(
+            jwks.verify(&token("new", "player-1")).await.unwrap(),
+            "player-1"
+        );
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 3);
+
+        let other_audience = Jwks::new(
+            provider.url.clone(),
+            Some("other".to_string()),
+            None,
+            Duration::ZERO,
+        );
+        assert!(other_audience
+            .verify(&token("old", "player-1"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,200 @@
+// OpenAPI description of the HTTP API, served on /openapi.json and /docs.
+// every route registered in lib.rs and routes/ should be listed here.
+
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::*;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Matchmaker"),
+    paths(
+        routes::api_root,
+        create_profile,
+        get_profile,
+        update_profile,
+        delete_profile,
+        reactivate_profile,
+        get_profile_matches,
+        export_profile_matches,
+        export_matches,
+        get_recent_opponents,
+        create_party,
+        get_party,
+        delete_party,
+        enqueue,
+        leave_queue,
+        heartbeat,
+        get_queue,
+        get_queue_stats,
+        get_queue_position,
+        stream_queue,
+        list_matches,
+        get_match,
+        report_result,
+        start_match,
+        ready_match,
+        cancel_match,
+        spectate_match,
+        stop_spectating,
+        set_match_metadata,
+        delete_match_metadata,
+        submit_feedback,
+        draft::ban,
+        draft::pick,
+        create_report,
+        create_guild,
+        get_guild,
+        add_guild_member,
+        remove_guild_member,
+        get_guild_leaderboard,
+        send_friend_request,
+        accept_friend_request,
+        remove_friend,
+        friends_status,
+        block_player,
+        unblock_player,
+        list_reports,
+        list_audit,
+        list_events,
+        admin_webhooks,
+        tenants::create_tenant,
+        tenants::delete_tenant,
+        shards::list_shards,
+        maintenance_on,
+        maintenance_off,
+        save_snapshot,
+        restore_snapshot,
+        ws_matches,
+        force_match,
+        assign_server,
+        admin_dequeue,
+        flush_queue,
+        search_profiles,
+        get_leaderboard,
+        set_vip,
+        reset_season,
+        current_season,
+        create_tournament,
+        get_tournament,
+        advance_tournament,
+        match_duration_stats,
+        match_quality_stats,
+        feedback_stats,
+        create_webhook,
+        list_webhooks,
+        delete_webhook,
+        health,
+        ready,
+        get_metrics,
+        graphql::playground,
+        graphql::execute,
+    ),
+    components(schemas(
+        ApiError,
+        routes::ApiRoot,
+        Profile,
+        ProfileView,
+        Region,
+        GameMode,
+        CreateProfile,
+        UpdateProfile,
+        Party,
+        CreateParty,
+        QueueRequest,
+        EnqueueResponse,
+        QueueView,
+        QueuePosition,
+        QueueStats,
+        MatchInfo,
+        MatchStatus,
+        MatchResult,
+        MatchPage,
+        AuditPage,
+        AuditEntry,
+        EventPage,
+        event_log::LoggedEvent,
+        event_log::Event,
+        tenants::Tenant,
+        tenants::CreateTenant,
+        shards::ShardStats,
+        MaintenanceStatus,
+        Winner,
+        ReportResult,
+        CancelMatch,
+        CancelReason,
+        Probe,
+        DurationStats,
+        QualityStats,
+        LeaderboardEntry,
+        Leaderboard,
+        RankTier,
+        SortOrder,
+        LeaderboardSort,
+        MetadataEntry,
+        MatchFeedback,
+        SubmitFeedback,
+        draft::DraftState,
+        draft::DraftPhase,
+        draft::DraftChoice,
+        FeedbackStats,
+        Report,
+        ReportReason,
+        CreateReport,
+        Guild,
+        GuildView,
+        CreateGuild,
+        AddGuildMember,
+        FriendRequest,
+        FriendStatus,
+        Presence,
+        tournament::Format,
+        Tournament,
+        CreateTournament,
+        Season,
+        SeasonReset,
+        SetVip,
+        Lane,
+        Webhook,
+        WebhookEvent,
+        CreateWebhook,
+        WebhookStatus,
+        CircuitStatus,
+        webhooks::CircuitState,
+        ForceMatch,
+        AssignServer,
+        Dequeued,
+        FlushQueue,
+        Flushed,
+        SnapshotSaved,
+        SnapshotRestored,
+    )),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+
+// the `bearer` scheme used by the write endpoints and the `admin_key` one
+// used by /admin
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "admin_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Admin-Key"))),
+        );
+    }
+}
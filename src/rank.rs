@@ -0,0 +1,32 @@
+// Display tiers derived from a player's mmr.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// lowest mmr of each tier; anything below SILVER_MIN is Bronze
+pub const SILVER_MIN: u32 = 800;
+pub const GOLD_MIN: u32 = 1200;
+pub const PLATINUM_MIN: u32 = 1600;
+pub const DIAMOND_MIN: u32 = 2000;
+pub const MASTER_MIN: u32 = 2400;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub enum RankTier {
+    Bronze,
+    Silver,
+    Gold,
+    Platinum,
+    Diamond,
+    Master,
+}
+
+pub fn mmr_to_tier(mmr: u32) -> RankTier {
+    match mmr {
+        m if m >= MASTER_MIN => RankTier::Master,
+        m if m >= DIAMOND_MIN => RankTier::Diamond,
+        m if m >= PLATINUM_MIN => RankTier::Platinum,
+        m if m >= GOLD_MIN => RankTier::Gold,
+        m if m >= SILVER_MIN => RankTier::Silver,
+        _ => RankTier::Bronze,
+    }
+}
@@ -0,0 +1,268 @@
+// Per-IP rate limiting: a fixed window for the enqueue endpoint, and a
+// sliding window over every route (`limit_requests`) so unauthenticated
+// lookups can't be scanned at full speed. reads (GET, HEAD, OPTIONS) and
+// writes are counted apart, against `read_rps` and `write_rps`. the health
+// and readiness probes are never limited.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::RETRY_AFTER, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{error::AppError, AppState};
+
+// requests seen from one address in the current window
+#[derive(Debug)]
+pub struct RateWindow {
+    started: Instant,
+    count: u32,
+}
+
+// allows `enqueue_rate_limit` requests per address every `enqueue_rate_window`,
+// answering 429 with `Retry-After` once the budget is spent
+pub async fn limit_enqueue<B>(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let ip: IpAddr = addr.ip();
+    let window = state.config.enqueue_rate_window();
+
+    let mut limits = state.enqueue_limits.lock().await;
+    // forget addresses whose window ran out so the map stays small
+    limits.retain(|_, w| w.started.elapsed() < window);
+    let w = limits.entry(ip).or_insert(RateWindow {
+        started: Instant::now(),
+        count: 0,
+    });
+    if w.count >= state.config.enqueue_rate_limit {
+        let retry_after = window.saturating_sub(w.started.elapsed()).as_secs().max(1);
+        tracing::warn!(%ip, "enqueue rate limit exceeded");
+        return (
+            [(RETRY_AFTER, retry_after.to_string())],
+            AppError::RateLimited,
+        )
+            .into_response();
+    }
+    w.count += 1;
+    drop(limits);
+
+    next.run(req).await
+}
+
+// the sliding window's length; the limits are per second
+const WINDOW: Duration = Duration::from_secs(1);
+
+// how often addresses that went quiet are forgotten
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+// one direction's counts for the current and the previous second. the
+// previous one is weighted by how much of it is still inside the last second
+#[derive(Debug)]
+struct Sliding {
+    started: Instant,
+    current: u32,
+    previous: u32,
+}
+
+impl Sliding {
+    fn new(now: Instant) -> Sliding {
+        Sliding {
+            started: now,
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    // counts a request unless that would go over `limit`
+    fn try_count(&mut self, limit: u32, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= WINDOW {
+            // a gap of more than a whole window leaves nothing behind
+            self.previous = if elapsed < WINDOW * 2 {
+                self.current
+            } else {
+                0
+            };
+            self.current = 0;
+            self.started += WINDOW * elapsed.as_secs() as u32;
+        }
+        let into = now.duration_since(self.started).as_secs_f64() / WINDOW.as_secs_f64();
+        let estimate = self.previous as f64 * (1.0 - into) + self.current as f64;
+        if estimate + 1.0 > limit as f64 {
+            return false;
+        }
+        self.current += 1;
+        true
+    }
+}
+
+// requests seen from one address, reads and writes apart
+#[derive(Debug)]
+pub struct WindowState {
+    reads: Sliding,
+    writes: Sliding,
+    last_seen: Instant,
+}
+
+// answers 429 with `Retry-After` once an address goes over `read_rps` reads
+// or `write_rps` writes in the last second. a limit of 0 turns it off, and
+// requests without a peer address (in-process calls) or to the probes are
+// not counted
+pub async fn limit_requests<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = req.uri().path();
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    if matches!(path, "/health" | "/ready") {
+        return next.run(req).await;
+    }
+    let read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let limit = if read {
+        state.config.read_rps
+    } else {
+        state.config.write_rps
+    };
+    let peer = req.extensions().get::<ConnectInfo<SocketAddr>>();
+    let (Some(ConnectInfo(addr)), true) = (peer, limit > 0) else {
+        return next.run(req).await;
+    };
+    let ip = addr.ip();
+
+    let now = Instant::now();
+    let allowed = {
+        let mut w = state.ip_limits.entry(ip).or_insert_with(|| WindowState {
+            reads: Sliding::new(now),
+            writes: Sliding::new(now),
+            last_seen: now,
+        });
+        w.last_seen = now;
+        let window = if read { &mut w.reads } else { &mut w.writes };
+        window.try_count(limit, now)
+    };
+    if !allowed {
+        tracing::warn!(%ip, read, "request rate limit exceeded");
+        // the window is a second long, so a second is always enough
+        return ([(RETRY_AFTER, "1")], AppError::RateLimited).into_response();
+    }
+    next.run(req).await
+}
+
+// drops the windows of addresses that sent nothing for longer than the
+// window, so scanners rotating addresses don't grow the map without bound
+pub async fn forget_idle(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before = state.ip_limits.len();
+        state
+            .ip_limits
+            .retain(|_, w| w.last_seen.elapsed() < WINDOW * 2);
+        let forgotten = before.saturating_sub(state.ip_limits.len());
+        if forgotten > 0 {
+            tracing::debug!(forgotten, "forgot idle rate limit windows");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        db::{self, Loaded},
+    };
+
+    #[test]
+    fn previous_second_still_counts() {
+        let start = Instant::now();
+        let mut window = Sliding::new(start);
+        assert!((0..4).all(|_| window.try_count(4, start)));
+        assert!(!window.try_count(4, start));
+        // halfway into the next second half of the last one still weighs in
+        let half = start + WINDOW + WINDOW / 2;
+        assert!(window.try_count(4, half));
+        assert!(window.try_count(4, half));
+        assert!(!window.try_count(4, half));
+        // and after a quiet second nothing does
+        let later = start + WINDOW * 4;
+        assert!((0..4).all(|_| window.try_count(4, later)));
+    }
+
+    fn request(method: Method, path: &str, addr: [u8; 4]) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("content-type", "application/json")
+            .body(Body::from(json!({}).to_string()))
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((addr, 4000))));
+        req
+    }
+
+    #[tokio::test]
+    async fn writes_are_limited_per_address() {
+        let config = Config {
+            write_rps: 2,
+            jwt_secret: Some("rate-limit".to_string()),
+            ..Config::default()
+        };
+        let app: Router = crate::app(Arc::new(AppState::new(
+            config,
+            db::memory(),
+            Loaded::default(),
+        )));
+        let send = |method, addr| app.clone().oneshot(request(method, "/v1/profiles", addr));
+
+        for _ in 0..2 {
+            let res = send(Method::POST, [10, 0, 0, 1]).await.unwrap();
+            assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        let res = send(Method::POST, [10, 0, 0, 1]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[RETRY_AFTER], "1");
+
+        // reads and other addresses have budgets of their own
+        let res = send(Method::GET, [10, 0, 0, 1]).await.unwrap();
+        assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = send(Method::POST, [10, 0, 0, 2]).await.unwrap();
+        assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn probes_are_not_limited() {
+        let config = Config {
+            read_rps: 1,
+            jwt_secret: Some("rate-limit".to_string()),
+            ..Config::default()
+        };
+        let app: Router = crate::app(Arc::new(AppState::new(
+            config,
+            db::memory(),
+            Loaded::default(),
+        )));
+        for path in ["/v1/health", "/v1/ready"] {
+            for _ in 0..3 {
+                let req = request(Method::GET, path, [10, 0, 0, 3]);
+                let res = app.clone().oneshot(req).await.unwrap();
+                assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+            }
+        }
+    }
+}
@@ -0,0 +1,408 @@
+// Queue entries shared through Redis, for several instances behind one load
+// balancer, with REDIS_URL set. every entry is also kept as its stored JSON
+// in a LIST per mode, `matchmaker:queue:<mode>`, written along with the
+// database in `AppState::persist`. each pass of the background matcher first
+// pulls every entry in the lists, fetching the profiles it has not seen, and
+// only makes a match once a Lua script has taken all of its entries out of
+// the list (LRANGE, then LREM) in one go, so two instances never both match
+// the same player. profiles and matches are shared too: each write is kept
+// as JSON in the hashes `matchmaker:profiles` and `matchmaker:matches`, and
+// published on `matchmaker:changes` along with every player notification,
+// so the other instances replace their copies and reach the players
+// connected to them. an instance loads both hashes when it starts
+// following the channel. without REDIS_URL the queue is in memory only.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use redis::{aio::ConnectionManager, RedisResult, Script};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    db::{DbOp, StoredQueueEntry},
+    lanes::ModeQueue,
+    normalize_name, AppState, GameMode, MatchInfo, Notification, Profile, QueueEntry,
+};
+
+const PROFILES: &str = "matchmaker:profiles";
+const MATCHES: &str = "matchmaker:matches";
+const CHANNEL: &str = "matchmaker:changes";
+
+// how long `follow` waits before subscribing again after losing Redis
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+// replaces the entry of the player ARGV[1], if any, with ARGV[2]
+const PUSH: &str = r#"
+for _, item in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+  if cjson.decode(item)['profile_id'] == ARGV[1] then
+    redis.call('LREM', KEYS[1], 0, item)
+  end
+end
+return redis.call('RPUSH', KEYS[1], ARGV[2])
+"#;
+
+// takes the entry of the player ARGV[1] out of every list in KEYS
+const REMOVE: &str = r#"
+local removed = 0
+for _, key in ipairs(KEYS) do
+  for _, item in ipairs(redis.call('LRANGE', key, 0, -1)) do
+    if cjson.decode(item)['profile_id'] == ARGV[1] then
+      removed = removed + redis.call('LREM', key, 0, item)
+    end
+  end
+end
+return removed
+"#;
+
+// takes the entries of every player in ARGV, or none if one of them is gone
+const CLAIM: &str = r#"
+local found = {}
+for _, item in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+  found[cjson.decode(item)['profile_id']] = item
+end
+for _, id in ipairs(ARGV) do
+  if not found[id] then
+    return 0
+  end
+end
+for _, id in ipairs(ARGV) do
+  redis.call('LREM', KEYS[1], 0, found[id])
+end
+return 1
+"#;
+
+// a write of one instance, for the others
+#[derive(Serialize, Deserialize)]
+enum Change {
+    Profile(Profile),
+    Match(MatchInfo),
+    Notification(Notification),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Published {
+    // the instance that made the change, which already has it
+    origin: Uuid,
+    change: Change,
+}
+
+pub struct RedisQueue {
+    client: redis::Client,
+    conn: ConnectionManager,
+    // tells apart this instance's own messages on the channel
+    instance: Uuid,
+    // messages for the channel, published in order by one task
+    outbox: mpsc::UnboundedSender<String>,
+    push: Script,
+    remove: Script,
+    claim: Script,
+    // players whose entries were pulled from another instance; their
+    // heartbeats go there, so here the list holding them stands in for one
+    pulled: Mutex<HashSet<Uuid>>,
+}
+
+fn key(mode: GameMode) -> String {
+    format!("matchmaker:queue:{mode:?}")
+}
+
+impl RedisQueue {
+    pub async fn connect(url: &str) -> RedisResult<RedisQueue> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client.clone()).await?;
+        let (outbox, mut outgoing) = mpsc::unbounded_channel::<String>();
+        let mut publisher = conn.clone();
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                let sent: RedisResult<()> = redis::cmd("PUBLISH")
+                    .arg(CHANNEL)
+                    .arg(message)
+                    .query_async(&mut publisher)
+                    .await;
+                if let Err(e) = sent {
+                    tracing::error!("failed to publish a change to Redis: {e}");
+                }
+            }
+        });
+        Ok(RedisQueue {
+            client,
+            conn,
+            instance: Uuid::new_v4(),
+            outbox,
+            push: Script::new(PUSH),
+            remove: Script::new(REMOVE),
+            claim: Script::new(CLAIM),
+            pulled: Mutex::new(HashSet::new()),
+        })
+    }
+
+    // writes the queue, profile and match changes among `ops` to Redis
+    pub async fn mirror(&self, ops: &[DbOp]) {
+        for op in ops {
+            let written = match op {
+                DbOp::UpsertQueueEntry(mode, e) => self.push_entry(*mode, e).await,
+                DbOp::DeleteQueueEntry(profile_id) => self.remove_entry(*profile_id).await,
+                DbOp::UpsertProfile(p) => {
+                    self.store(PROFILES, p.id, Change::Profile(p.clone())).await
+                }
+                DbOp::UpsertMatch(m) => self.store(MATCHES, m.id, Change::Match(m.clone())).await,
+                _ => continue,
+            };
+            if let Err(e) = written {
+                tracing::error!("failed to write to Redis: {e}");
+            }
+        }
+    }
+
+    // sends `n` to the players connected to the other instances
+    pub fn publish(&self, n: &Notification) {
+        self.send(Change::Notification(n.clone()));
+    }
+
+    fn send(&self, change: Change) {
+        let published = Published {
+            origin: self.instance,
+            change,
+        };
+        let message = serde_json::to_string(&published).expect("changes serialize");
+        // the publisher only stops with the state
+        let _ = self.outbox.send(message);
+    }
+
+    // keeps a profile or match in `hash`, then tells the other instances
+    async fn store(&self, hash: &str, id: Uuid, change: Change) -> RedisResult<()> {
+        let json = match &change {
+            Change::Profile(p) => serde_json::to_string(p),
+            Change::Match(m) => serde_json::to_string(m),
+            Change::Notification(_) => unreachable!("notifications are not stored"),
+        }
+        .expect("profiles and matches serialize");
+        redis::cmd("HSET")
+            .arg(hash)
+            .arg(id.to_string())
+            .arg(json)
+            .query_async::<_, ()>(&mut self.conn.clone())
+            .await?;
+        self.send(change);
+        Ok(())
+    }
+
+    // every profile or match kept in `hash` that still parses
+    async fn stored<T: for<'de> Deserialize<'de>>(&self, hash: &str) -> RedisResult<Vec<T>> {
+        let items: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(hash)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(items
+            .values()
+            .filter_map(|item| serde_json::from_str(item).ok())
+            .collect())
+    }
+
+    // the stored profiles of `ids`, skipping unknown ones
+    async fn profiles(&self, ids: &[Uuid]) -> RedisResult<Vec<Profile>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut cmd = redis::cmd("HMGET");
+        cmd.arg(PROFILES);
+        for id in ids {
+            cmd.arg(id.to_string());
+        }
+        let items: Vec<Option<String>> = cmd.query_async(&mut self.conn.clone()).await?;
+        Ok(items
+            .iter()
+            .flatten()
+            .filter_map(|item| serde_json::from_str(item).ok())
+            .collect())
+    }
+
+    async fn push_entry(&self, mode: GameMode, entry: &QueueEntry) -> RedisResult<()> {
+        // queued here now, with heartbeats of its own
+        self.pulled.lock().unwrap().remove(&entry.profile_id);
+        let stored = StoredQueueEntry::from(entry.clone());
+        let json = serde_json::to_string(&stored).expect("queue entries serialize");
+        self.push
+            .key(key(mode))
+            .arg(entry.profile_id.to_string())
+            .arg(json)
+            .invoke_async(&mut self.conn.clone())
+            .await
+    }
+
+    async fn remove_entry(&self, profile_id: Uuid) -> RedisResult<()> {
+        self.pulled.lock().unwrap().remove(&profile_id);
+        let mut invocation = self.remove.prepare_invoke();
+        for mode in GameMode::ALL {
+            invocation.key(key(mode));
+        }
+        invocation
+            .arg(profile_id.to_string())
+            .invoke_async(&mut self.conn.clone())
+            .await
+    }
+
+    // brings the local queue of `mode` in line with its list: entries gone
+    // from it were matched or removed elsewhere, and those of other
+    // instances' players join, with their stored profiles if this instance
+    // has not seen them yet. called under the queue lock once the staged
+    // writes are flushed, so every local change is already in the list
+    pub async fn pull(&self, state: &AppState, mode: GameMode, queue: &mut ModeQueue) {
+        let items: Vec<String> = match redis::cmd("LRANGE")
+            .arg(key(mode))
+            .arg(0)
+            .arg(-1)
+            .query_async(&mut self.conn.clone())
+            .await
+        {
+            Ok(items) => items,
+            Err(e) => {
+                tracing::error!("failed to read the queue from Redis: {e}");
+                return;
+            }
+        };
+        let mut listed: HashMap<Uuid, QueueEntry> = items
+            .iter()
+            .filter_map(|item| serde_json::from_str::<StoredQueueEntry>(item).ok())
+            .map(|stored| {
+                let entry = stored.into_entry();
+                (entry.profile_id, entry)
+            })
+            .collect();
+        let missing: Vec<Uuid> = listed
+            .values()
+            .flat_map(|e| &e.members)
+            .filter(|m| !state.profiles.contains_key(m))
+            .copied()
+            .collect();
+        match self.profiles(&missing).await {
+            Ok(profiles) => {
+                for p in profiles {
+                    adopt_profile(state, p).await;
+                }
+            }
+            Err(e) => tracing::error!("failed to read profiles from Redis: {e}"),
+        }
+        // then the store, shared with the other instances when it is a
+        // database, for profiles written before they were kept in Redis
+        for &id in missing.iter().filter(|id| !state.profiles.contains_key(id)) {
+            match state.store.find_profile(id).await {
+                Ok(Some(p)) => adopt_profile(state, p).await,
+                Ok(None) => {}
+                Err(e) => tracing::error!("failed to read a profile from the store: {e}"),
+            }
+        }
+
+        let mut pulled = self.pulled.lock().unwrap();
+        queue.retain(|e| listed.contains_key(&e.profile_id));
+        let now = Instant::now();
+        for e in queue.iter_mut() {
+            listed.remove(&e.profile_id);
+            if pulled.contains(&e.profile_id) {
+                e.last_heartbeat = now;
+            }
+        }
+        for (id, entry) in listed {
+            if entry.members.iter().all(|m| state.profiles.contains_key(m)) {
+                pulled.insert(id);
+                queue.insert(entry);
+            }
+        }
+    }
+
+    // takes the entries of `players` out of the list of `mode` for a match,
+    // false if another instance got to one of them first
+    pub async fn claim(&self, mode: GameMode, players: impl Iterator<Item = Uuid>) -> bool {
+        let mut invocation = self.claim.key(key(mode));
+        for id in players {
+            invocation.arg(id.to_string());
+        }
+        match invocation
+            .invoke_async::<_, i64>(&mut self.conn.clone())
+            .await
+        {
+            Ok(claimed) => claimed == 1,
+            Err(e) => {
+                tracing::error!("failed to claim queue entries in Redis: {e}");
+                false
+            }
+        }
+    }
+}
+
+// a profile written by another instance, in place of this one's copy
+async fn adopt_profile(state: &AppState, p: Profile) {
+    let mut names = state.name_index.lock().await;
+    if let Some(old) = state.profiles.insert(p.id, p.clone()) {
+        let key = normalize_name(&old.name);
+        if names.get(&key) == Some(&p.id) {
+            names.remove(&key);
+        }
+    }
+    names.insert(normalize_name(&p.name), p.id);
+    if let Some(external_id) = &p.external_id {
+        state.external_ids.insert(external_id.clone(), p.id);
+    }
+}
+
+// applies the other instances' changes to `state` for as long as it runs:
+// their profiles and matches replace the local copies, and their
+// notifications go to the players connected here
+pub async fn follow(state: Arc<AppState>) {
+    let Some(redis) = &state.redis else {
+        return;
+    };
+    loop {
+        if let Err(e) = follow_once(&state, redis).await {
+            tracing::error!("lost the Redis change channel: {e}");
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+async fn follow_once(state: &AppState, redis: &RedisQueue) -> RedisResult<()> {
+    let mut pubsub = redis.client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(CHANNEL).await?;
+    // subscribed first, so nothing written meanwhile is missed
+    for p in redis.stored::<Profile>(PROFILES).await? {
+        adopt_profile(state, p).await;
+    }
+    let stored = redis.stored::<MatchInfo>(MATCHES).await?;
+    state
+        .matches
+        .lock()
+        .await
+        .extend(stored.into_iter().map(|m| (m.id, m)));
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let published = message
+            .get_payload::<String>()
+            .ok()
+            .and_then(|payload| serde_json::from_str::<Published>(&payload).ok());
+        let Some(published) = published else {
+            tracing::warn!("skipped an unreadable change from Redis");
+            continue;
+        };
+        if published.origin == redis.instance {
+            continue;
+        }
+        match published.change {
+            Change::Profile(p) => adopt_profile(state, p).await,
+            Change::Match(m) => {
+                let id = m.id;
+                state.matches.lock().await.insert(id, m);
+                let _ = state.match_changes.send(id);
+            }
+            Change::Notification(n) => {
+                let _ = state.events.send(n);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+// X-Request-ID propagation: every request runs inside a span carrying its id,
+// so log lines emitted while handling it can be matched to the client call.
+// the same span is the request's OpenTelemetry server span, see telemetry.rs.
+
+use axum::{
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{field::Empty, Instrument};
+use uuid::Uuid;
+
+use crate::telemetry;
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// reuses the caller's X-Request-ID or makes up a new one, and echoes it back
+pub async fn propagate<B>(req: Request<B>, next: Next<B>) -> Response {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|v| v.to_str().is_ok_and(|s| !s.is_empty()))
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id.to_str().unwrap(),
+        method = %req.method(),
+        path = %req.uri().path(),
+        otel.kind = "server",
+        status = Empty,
+    );
+    telemetry::continue_trace(&span, req.headers());
+    let mut res = next.run(req).instrument(span.clone()).await;
+    span.record("status", res.status().as_u16());
+    res.headers_mut().insert(REQUEST_ID_HEADER, id);
+    res
+}
@@ -0,0 +1,46 @@
+// Versioned routers. each version is mounted under its own prefix; the old
+// unversioned paths answer 301 to their /v1 equivalent while clients move over.
+
+use std::sync::Arc;
+
+use axum::{
+    http::{header::LOCATION, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+pub mod v1;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiRoot {
+    latest: &'static str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "meta",
+    responses((status = 200, description = "Prefix of the newest API version", body = ApiRoot))
+)]
+pub async fn api_root() -> Json<ApiRoot> {
+    Json(ApiRoot { latest: "/v1" })
+}
+
+// the v1 routes again at the root, each only redirecting to /v1. the route
+// layer runs for matched routes alone, so unknown paths still get a 404
+pub fn unversioned_redirects(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    v1::v1_router(state).route_layer(middleware::from_fn(redirect_to_v1))
+}
+
+async fn redirect_to_v1<B>(req: Request<B>, _next: Next<B>) -> Response {
+    let target = match req.uri().query() {
+        Some(query) => format!("/v1{}?{query}", req.uri().path()),
+        None => format!("/v1{}", req.uri().path()),
+    };
+    (StatusCode::MOVED_PERMANENTLY, [(LOCATION, target)]).into_response()
+}
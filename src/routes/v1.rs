@@ -0,0 +1,660 @@
+// Routes of the v1 API, mounted under /v1. the request and response types
+// they use are the v1 wire format; a breaking change to any of them belongs
+// in a v2 router with its own types.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    middleware,
+    routing::{delete, get, patch, post},
+    Json, Router,
+};
+use uuid::Uuid;
+
+use crate::{msgpack::MsgpackOrJson, *};
+
+pub fn v1_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/profiles",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 external: Option<Extension<ExternalIdentity>>,
+                 MsgpackOrJson(payload): MsgpackOrJson<CreateProfile>| async move {
+                    create_profile(State(state), external, Json(payload)).await
+                },
+            )
+            .get(
+                |State(state): State<Arc<AppState>>, Query(query): Query<NameSearch>| async move {
+                    search_profiles(State(state), Query(query)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_profile(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/profiles/:id/matches",
+            get(
+                |State(state): State<Arc<AppState>>,
+                 Path(id): Path<Uuid>,
+                 Query(page): Query<Pagination>| async move {
+                    get_profile_matches(State(state), Path(id), Query(page)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id/matches.csv",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                export_profile_matches(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/export/matches",
+            get(
+                |State(state): State<Arc<AppState>>, Query(range): Query<ExportRange>| async move {
+                    export_matches(State(state), Query(range)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id/recent_opponents",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_recent_opponents(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/parties",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 MsgpackOrJson(payload): MsgpackOrJson<CreateParty>| async move {
+                    create_party(State(state), Extension(player), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/parties/:id",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_party(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/queue/enqueue",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 MsgpackOrJson(payload): MsgpackOrJson<QueueRequest>| async move {
+                    enqueue(State(state), Extension(player), Json(payload)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), rate_limit::limit_enqueue)),
+        )
+        .route(
+            "/queue/leave",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 MsgpackOrJson(payload): MsgpackOrJson<QueueRequest>| async move {
+                    leave_queue(State(state), Extension(player), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/queue/heartbeat",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 MsgpackOrJson(payload): MsgpackOrJson<QueueRequest>| async move {
+                    heartbeat(State(state), Extension(player), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/queue",
+            get(|State(state): State<Arc<AppState>>, Query(filter): Query<QueueFilter>| async move {
+                get_queue(State(state), Query(filter)).await
+            }),
+        )
+        .route(
+            "/queue/stats",
+            get(|State(state): State<Arc<AppState>>, Query(filter): Query<QueueFilter>| async move {
+                get_queue_stats(State(state), Query(filter)).await
+            }),
+        )
+        .route(
+            "/queue/position/:profile_id",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_queue_position(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/matches",
+            get(
+                |State(state): State<Arc<AppState>>, Query(page): Query<CursorPagination>| async move {
+                    list_matches(State(state), Query(page)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_match(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/matches/:id/ready",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>| async move {
+                    ready_match(State(state), Extension(player), Path(id)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id/spectate",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>| async move {
+                    spectate_match(State(state), Extension(player), Path(id)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id/spectate/:profile_id",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(ids): Path<(Uuid, Uuid)>| async move {
+                    stop_spectating(State(state), Extension(player), Path(ids)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id/feedback",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<SubmitFeedback>| async move {
+                    submit_feedback(State(state), Extension(player), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id/draft/ban",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<draft::DraftChoice>| async move {
+                    draft::ban(State(state), Extension(player), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id/draft/pick",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<draft::DraftChoice>| async move {
+                    draft::pick(State(state), Extension(player), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/seasons/current",
+            get(|State(state): State<Arc<AppState>>| async move { current_season(State(state)).await }),
+        )
+        .route(
+            "/leaderboard",
+            get(
+                |State(state): State<Arc<AppState>>,
+                 Query(query): Query<LeaderboardQuery>| async move {
+                    get_leaderboard(State(state), Query(query)).await
+                },
+            ),
+        )
+        .route(
+            "/analytics/match_duration",
+            get(|State(state): State<Arc<AppState>>| async move {
+                match_duration_stats(State(state)).await
+            }),
+        )
+        .route(
+            "/analytics/match_quality",
+            get(|State(state): State<Arc<AppState>>, Query(query): Query<QualityQuery>| async move {
+                match_quality_stats(State(state), Query(query)).await
+            }),
+        )
+        .route(
+            "/profiles/:id/friends",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<FriendRequest>| async move {
+                    send_friend_request(State(state), Extension(player), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id/friends/:friend_id/accept",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(ids): Path<(Uuid, Uuid)>| async move {
+                    accept_friend_request(State(state), Extension(player), Path(ids)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id/friends/:friend_id",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(ids): Path<(Uuid, Uuid)>| async move {
+                    remove_friend(State(state), Extension(player), Path(ids)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id/block/:target_id",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(ids): Path<(Uuid, Uuid)>| async move {
+                    block_player(State(state), Extension(player), Path(ids)).await
+                },
+            )
+            .delete(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(ids): Path<(Uuid, Uuid)>| async move {
+                    unblock_player(State(state), Extension(player), Path(ids)).await
+                },
+            ),
+        )
+        .route(
+            "/guilds",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 MsgpackOrJson(payload): MsgpackOrJson<CreateGuild>| async move {
+                    create_guild(State(state), Extension(player), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/guilds/:id",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_guild(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/guilds/:id/members",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<AddGuildMember>| async move {
+                    add_guild_member(State(state), Extension(player), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/guilds/:id/members/:profile_id",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(ids): Path<(Uuid, Uuid)>| async move {
+                    remove_guild_member(State(state), Extension(player), Path(ids)).await
+                },
+            ),
+        )
+        .route(
+            "/guilds/:id/leaderboard",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_guild_leaderboard(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/reports",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 MsgpackOrJson(payload): MsgpackOrJson<CreateReport>| async move {
+                    create_report(State(state), Extension(player), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/analytics/feedback",
+            get(
+                |State(state): State<Arc<AppState>>, Query(query): Query<FeedbackQuery>| async move {
+                    feedback_stats(State(state), Query(query)).await
+                },
+            ),
+        )
+        .route("/health", get(|| async move { health().await }))
+        .route(
+            "/ready",
+            get(|State(state): State<Arc<AppState>>| async move { ready(State(state)).await }),
+        )
+        .route(
+            "/metrics",
+            get(|State(state): State<Arc<AppState>>| async move { get_metrics(State(state)).await }),
+        )
+        .route(
+            "/stream/queue",
+            get(|State(state): State<Arc<AppState>>, Query(params): Query<WsParams>| async move {
+                stream_queue(State(state), Query(params)).await
+            }),
+        )
+        .route(
+            "/ws/matches",
+            get(
+                |ws: WebSocketUpgrade,
+                 State(state): State<Arc<AppState>>,
+                 Query(params): Query<WsParams>| async move {
+                    ws_matches(ws, State(state), Query(params)).await
+                },
+            ),
+        )
+        // reads stay public, everything else needs a bearer token
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_auth))
+        // added after the token check above: game servers write these with
+        // the admin key instead
+        .route(
+            "/matches/:id/metadata",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<MetadataEntry>| async move {
+                    set_match_metadata(State(state), player, Path(id), Json(payload)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        .route(
+            "/matches/:id/metadata/:key",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(ids): Path<(Uuid, String)>| async move {
+                    delete_match_metadata(State(state), player, Path(ids)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        .route(
+            "/matches/:id/result",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<ReportResult>| async move {
+                    report_result(State(state), player, Path(id), Json(payload)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        .route(
+            "/matches/:id/start",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>| async move {
+                    start_match(State(state), player, Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        .route(
+            "/matches/:id/cancel",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>,
+                 payload: Option<MsgpackOrJson<CancelMatch>>| async move {
+                    cancel_match(State(state), player, Path(id), payload.map(|MsgpackOrJson(p)| Json(p))).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        .route(
+            "/parties/:id",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>| async move {
+                    delete_party(State(state), player, Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        .route(
+            "/profiles/:id",
+            patch(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<UpdateProfile>| async move {
+                    update_profile(State(state), player, Path(id), Json(payload)).await
+                },
+            )
+            .delete(
+                |State(state): State<Arc<AppState>>,
+                 player: Option<Extension<AuthPlayer>>,
+                 Path(id): Path<Uuid>| async move {
+                    delete_profile(State(state), player, Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_auth_or_admin)),
+        )
+        // a read, but only for the player themselves
+        .route(
+            "/profiles/:id/friends/status",
+            get(
+                |State(state): State<Arc<AppState>>,
+                 Extension(player): Extension<AuthPlayer>,
+                 Path(id): Path<Uuid>| async move {
+                    friends_status(State(state), Extension(player), Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_token)),
+        )
+        .route(
+            "/profiles/:id/reactivate",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 Path(id): Path<Uuid>| async move {
+                    reactivate_profile(State(state), Extension(admin), Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_admin)),
+        )
+        .route(
+            "/tournaments",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 MsgpackOrJson(payload): MsgpackOrJson<CreateTournament>| async move {
+                    create_tournament(State(state), Extension(admin), Json(payload)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_admin)),
+        )
+        .route(
+            "/tournaments/:id",
+            get(|State(state): State<Arc<AppState>>, Path(id): Path<Uuid>| async move {
+                get_tournament(State(state), Path(id)).await
+            }),
+        )
+        .route(
+            "/tournaments/:id/advance",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 Path(id): Path<Uuid>| async move {
+                    advance_tournament(State(state), Extension(admin), Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_admin)),
+        )
+        // webhooks receive every match event, and their URLs are the
+        // integrators' own, so only operators manage them
+        .route(
+            "/webhooks",
+            get(|State(state): State<Arc<AppState>>| async move { list_webhooks(State(state)).await })
+                .post(
+                    |State(state): State<Arc<AppState>>,
+                     Extension(admin): Extension<AdminIdentity>,
+                     MsgpackOrJson(payload): MsgpackOrJson<CreateWebhook>| async move {
+                        create_webhook(State(state), Extension(admin), Json(payload)).await
+                    },
+                )
+                .layer(middleware::from_fn_with_state(state.clone(), auth::require_admin)),
+        )
+        .route(
+            "/webhooks/:id",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 Path(id): Path<Uuid>| async move {
+                    delete_webhook(State(state), Extension(admin), Path(id)).await
+                },
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), auth::require_admin)),
+        )
+        // admin routes are guarded by the admin key instead
+        .nest("/admin", admin_routes(state))
+}
+
+fn admin_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/matches/force",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 MsgpackOrJson(payload): MsgpackOrJson<ForceMatch>| async move {
+                    force_match(State(state), Extension(admin), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/matches/:id/assign_server",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<AssignServer>| async move {
+                    assign_server(State(state), Extension(admin), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/queue/flush",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 payload: Option<MsgpackOrJson<FlushQueue>>| async move {
+                    flush_queue(State(state), Extension(admin), payload.map(|MsgpackOrJson(p)| Json(p))).await
+                },
+            ),
+        )
+        .route(
+            "/queue/:profile_id",
+            delete(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 Path(id): Path<Uuid>| async move {
+                    admin_dequeue(State(state), Extension(admin), Path(id)).await
+                },
+            ),
+        )
+        .route(
+            "/profiles/:id/set_vip",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 Path(id): Path<Uuid>,
+                 MsgpackOrJson(payload): MsgpackOrJson<SetVip>| async move {
+                    set_vip(State(state), Extension(admin), Path(id), Json(payload)).await
+                },
+            ),
+        )
+        .route(
+            "/reports",
+            get(|State(state): State<Arc<AppState>>| async move { list_reports(State(state)).await }),
+        )
+        .route(
+            "/audit",
+            get(|State(state): State<Arc<AppState>>, Query(query): Query<AuditQuery>| async move {
+                list_audit(State(state), Query(query)).await
+            }),
+        )
+        .route(
+            "/events",
+            get(|State(state): State<Arc<AppState>>, Query(query): Query<EventQuery>| async move {
+                list_events(State(state), Query(query)).await
+            }),
+        )
+        .route(
+            "/webhooks",
+            get(|State(state): State<Arc<AppState>>| async move { admin_webhooks(State(state)).await }),
+        )
+        .route(
+            "/maintenance/on",
+            post(
+                |State(state): State<Arc<AppState>>, Extension(admin): Extension<AdminIdentity>| async move {
+                    maintenance_on(State(state), Extension(admin)).await
+                },
+            ),
+        )
+        .route(
+            "/maintenance/off",
+            post(
+                |State(state): State<Arc<AppState>>, Extension(admin): Extension<AdminIdentity>| async move {
+                    maintenance_off(State(state), Extension(admin)).await
+                },
+            ),
+        )
+        .route(
+            "/snapshot/save",
+            post(
+                |State(state): State<Arc<AppState>>, Extension(admin): Extension<AdminIdentity>| async move {
+                    save_snapshot(State(state), Extension(admin)).await
+                },
+            ),
+        )
+        .route(
+            "/snapshot/restore",
+            post(
+                |State(state): State<Arc<AppState>>, Extension(admin): Extension<AdminIdentity>| async move {
+                    restore_snapshot(State(state), Extension(admin)).await
+                },
+            ),
+        )
+        .route(
+            "/seasons/reset",
+            post(
+                |State(state): State<Arc<AppState>>,
+                 Extension(admin): Extension<AdminIdentity>,
+                 MsgpackOrJson(payload): MsgpackOrJson<SeasonReset>| async move {
+                    reset_season(State(state), Extension(admin), Json(payload)).await
+                },
+            ),
+        )
+        .route_layer(middleware::from_fn_with_state(state, auth::require_admin))
+}
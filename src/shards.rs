@@ -0,0 +1,523 @@
+// Region shards: with SHARD_BY_REGION set the instance keeps one AppState per
+// region, each with its own profiles, queues, matches and locks, so an
+// enqueue in one region never waits on another's. only the name index is
+// shared, so names stay unique across regions. players only meet players of
+// their own region, and parties, friends and guilds stay within one. a v1
+// request is handed to the shard holding the first id in its path (profile,
+// match, party, guild or tournament), else the bearer token's player, else
+// the profile (`profile_id`, `player1`) or `region` in the body or query,
+// else the Other region's shard. which shard holds an id is looked up in an
+// index the shards add to as they store things, so routing takes no shard's
+// locks. admin requests naming none of these go to every shard: lists come
+// back concatenated, anything else as an object keyed by region. the
+// leaderboard, metrics and the public reads over everything (name search,
+// match and queue lists, queue stats, analytics and the match export) cover
+// every shard, maintenance mode is the instance's, and GET /v1/admin/shards
+// reports each shard's queue depth and match counts. GraphQL, gRPC and
+// tenants keep using the instance's own, unsharded state.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Query, State},
+    http::{header::CONTENT_TYPE, request::Parts, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use dashmap::DashMap;
+use hyper::upgrade::OnUpgrade;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tower::ServiceExt;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    audit, auth,
+    config::Config,
+    db::{self, Loaded, MatchmakerStore},
+    error::AppError,
+    msgpack, routes, snapshot, AppState, MatchStatus, Region,
+};
+
+// one shard each, indexed by `region as usize`
+const REGIONS: [Region; 5] = [
+    Region::NorthAmerica,
+    Region::Europe,
+    Region::AsiaPacific,
+    Region::SouthAmerica,
+    Region::Other,
+];
+
+pub struct ShardedAppState {
+    pub shards: Vec<Arc<AppState>>,
+    // behind locks only because a Router is not Sync; requests use clones
+    routers: Vec<Mutex<Router>>,
+    // the shard holding each profile, match, party, guild and tournament
+    homes: Arc<DashMap<Uuid, usize>>,
+}
+
+// a shard's place in its instance's index of homes
+pub(crate) struct Home {
+    homes: Arc<DashMap<Uuid, usize>>,
+    shard: usize,
+}
+
+// what a request body or query may say about where it belongs
+#[derive(Debug, Default, Deserialize)]
+struct Keys {
+    profile_id: Option<Uuid>,
+    player1: Option<Uuid>,
+    region: Option<Region>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ShardStats {
+    region: Region,
+    profiles: usize,
+    // players waiting in the shard's queues
+    queued: usize,
+    matches: usize,
+    // Pending or Active
+    open_matches: usize,
+}
+
+// the instance's state with a shard per region, `loaded` split between them
+// by the region of each profile. a match goes with its player1, a guild with
+// its owner
+pub fn sharded(config: Config, store: Arc<dyn MatchmakerStore>, mut loaded: Loaded) -> AppState {
+    let mut parts: Vec<Loaded> = REGIONS
+        .iter()
+        .map(|_| Loaded {
+            season: loaded.season.clone(),
+            ..Loaded::default()
+        })
+        .collect();
+    let home = |id: &Uuid| loaded.profiles.get(id).map_or(Region::Other, |p| p.region) as usize;
+    for (id, m) in loaded.matches.drain() {
+        parts[home(&m.player1)].matches.insert(id, m);
+    }
+    for (id, g) in loaded.guilds.drain() {
+        parts[home(&g.owner)].guilds.insert(id, g);
+    }
+    for (mode, q) in &loaded.queues {
+        for e in q.iter() {
+            let queue = parts[e.region as usize].queues.entry(*mode).or_default();
+            queue.insert(e.clone());
+        }
+    }
+    for (id, p) in loaded.profiles {
+        parts[p.region as usize].profiles.insert(id, p);
+    }
+
+    let mut state = AppState::new(config.clone(), db::memory(), Loaded::default());
+    let homes = Arc::new(DashMap::new());
+    let mut shards: Vec<AppState> = REGIONS
+        .iter()
+        .zip(parts)
+        .enumerate()
+        .map(|(i, (region, part))| {
+            let held = part.profiles.keys().chain(part.matches.keys());
+            for id in held.chain(part.guilds.keys()) {
+                homes.insert(*id, i);
+            }
+            let config = Config {
+                snapshot_path: snapshot::sibling_path(
+                    &config.snapshot_path,
+                    &format!("{region:?}"),
+                ),
+                ..config.clone()
+            };
+            let mut shard = AppState::new(config, store.clone(), part);
+            // one set of counters for the whole instance
+            shard.metrics = state.metrics.clone();
+            shard.home = Some(Home {
+                homes: homes.clone(),
+                shard: i,
+            });
+            shard
+        })
+        .collect();
+    let mut names = HashMap::new();
+    for shard in &mut shards {
+        let own = Arc::get_mut(&mut shard.name_index).expect("not shared yet");
+        names.extend(own.get_mut().drain());
+    }
+    let names = Arc::new(tokio::sync::Mutex::new(names));
+    let shards: Vec<Arc<AppState>> = shards
+        .into_iter()
+        .map(|mut shard| {
+            shard.name_index = names.clone();
+            Arc::new(shard)
+        })
+        .collect();
+    let routers = shards
+        .iter()
+        .map(|shard| {
+            let router = routes::v1::v1_router(shard.clone())
+                .layer(middleware::from_fn_with_state(shard.clone(), audit::record))
+                .with_state(shard.clone());
+            Mutex::new(router)
+        })
+        .collect();
+    state.shards = Some(ShardedAppState {
+        shards,
+        routers,
+        homes,
+    });
+    state
+}
+
+impl AppState {
+    // the states holding the data: the shards if there are any, else itself
+    pub(crate) fn shard_states(&self) -> Vec<&AppState> {
+        match &self.shards {
+            Some(sharded) => sharded.shards.iter().map(|s| &**s).collect(),
+            None => vec![self],
+        }
+    }
+
+    // in a shard, notes that it holds the profile, match, party, guild or
+    // tournament `id`; nothing without shards
+    pub(crate) fn record_home(&self, id: Uuid) {
+        if let Some(home) = &self.home {
+            home.homes.insert(id, home.shard);
+        }
+    }
+}
+
+impl ShardedAppState {
+    // the shard with a profile, match, party, guild or tournament of this id
+    fn holding(&self, id: Option<Uuid>) -> Option<usize> {
+        self.homes.get(&id?).map(|shard| *shard)
+    }
+
+    // the shard `req` belongs to, None if it names nothing that says so
+    async fn target(&self, state: &AppState, req: &Request<Bytes>) -> Option<usize> {
+        let in_path = req.uri().path().split('/').find_map(|s| s.parse().ok());
+        if let Some(i) = self.holding(in_path) {
+            return Some(i);
+        }
+        if let Some(token) = auth::bearer_token(req) {
+            if let Ok(identity) = auth::verify_token(state, &token).await {
+                let player = match identity.external {
+                    // linked on the shard the profile was created on
+                    Some(auth::ExternalIdentity(sub)) => self
+                        .shards
+                        .iter()
+                        .find_map(|s| s.external_ids.get(&sub).map(|id| *id)),
+                    None => Some(identity.player.0),
+                };
+                if let Some(i) = self.holding(player) {
+                    return Some(i);
+                }
+            }
+        }
+        let body = if msgpack::is_msgpack(req.headers().get(CONTENT_TYPE)) {
+            // human readable, so ids are strings as in JSON
+            let mut de = rmp_serde::Deserializer::new(&req.body()[..]).with_human_readable();
+            Keys::deserialize(&mut de).unwrap_or_default()
+        } else {
+            serde_json::from_slice(req.body()).unwrap_or_default()
+        };
+        let query =
+            Query::<Keys>::try_from_uri(req.uri()).map_or_else(|_| Keys::default(), |q| q.0);
+        for keys in [body, query] {
+            if let Some(i) = self.holding(keys.profile_id.or(keys.player1)) {
+                return Some(i);
+            }
+            if let Some(region) = keys.region {
+                return Some(region as usize);
+            }
+        }
+        None
+    }
+
+    async fn forward(&self, shard: usize, req: Request<Body>) -> Response {
+        let router = self.routers[shard].lock().unwrap().clone();
+        router.oneshot(req).await.into_response()
+    }
+
+    // sends `req` to every shard. the first failure is the answer, else the
+    // lists concatenated or the bodies keyed by region
+    async fn fan_out(&self, mut parts: Parts, body: Bytes) -> Response {
+        let mut status = StatusCode::OK;
+        let mut bodies = Vec::new();
+        for shard in 0..self.shards.len() {
+            let res = self.forward(shard, rebuild(&mut parts, body.clone())).await;
+            if !res.status().is_success() {
+                return res;
+            }
+            status = res.status();
+            let bytes = hyper::body::to_bytes(res.into_body())
+                .await
+                .unwrap_or_default();
+            bodies.push(serde_json::from_slice(&bytes).unwrap_or(Value::Null));
+        }
+        let merged = if bodies.iter().all(Value::is_array) {
+            let items = bodies.into_iter().flat_map(|b| match b {
+                Value::Array(items) => items,
+                _ => Vec::new(),
+            });
+            Value::Array(items.collect())
+        } else {
+            let keyed = REGIONS
+                .iter()
+                .zip(bodies)
+                .map(|(region, b)| (format!("{region:?}"), b));
+            Value::Object(keyed.collect::<Map<_, _>>())
+        };
+        (status, Json(merged)).into_response()
+    }
+}
+
+// a fresh request from `parts`, as tenants.rs builds one: only the peer
+// address (rate limits) and the websocket upgrade are carried over
+fn rebuild(parts: &mut Parts, body: Bytes) -> Request<Body> {
+    let mut req = Request::builder()
+        .method(parts.method.clone())
+        .uri(parts.uri.clone())
+        .version(parts.version)
+        .body(Body::from(body))
+        .unwrap();
+    *req.headers_mut() = parts.headers.clone();
+    if let Some(peer) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        req.extensions_mut().insert(*peer);
+    }
+    if let Some(upgrade) = parts.extensions.remove::<OnUpgrade>() {
+        req.extensions_mut().insert(upgrade);
+    }
+    req
+}
+
+// hands a v1 request to its shard, see the top of the file. a layer over the
+// instance's own v1 routes, which only serve it without shards
+pub async fn route(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(sharded) = &state.shards else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path();
+    let instance = matches!(path, "/health" | "/ready" | "/metrics" | "/leaderboard")
+        || path.starts_with("/admin/maintenance/")
+        || (req.method() == Method::GET && covers_every_shard(path));
+    if instance {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let Ok(body) = hyper::body::to_bytes(body).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let req = Request::from_parts(parts, body);
+    let target = sharded.target(&state, &req).await;
+    let (mut parts, body) = req.into_parts();
+    match target {
+        Some(shard) => sharded.forward(shard, rebuild(&mut parts, body)).await,
+        None if parts.uri.path().starts_with("/admin/") => sharded.fan_out(parts, body).await,
+        None => {
+            let shard = Region::Other as usize;
+            sharded.forward(shard, rebuild(&mut parts, body)).await
+        }
+    }
+}
+
+// public reads whose handlers go through `AppState::shard_states`, so the
+// instance serves them from every shard
+fn covers_every_shard(path: &str) -> bool {
+    matches!(
+        path,
+        "/profiles" | "/matches" | "/queue" | "/queue/stats" | "/export/matches"
+    ) || path.starts_with("/analytics/")
+}
+
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/v1/admin/shards", get(list_shards))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_admin,
+        ))
+        .layer(middleware::from_fn_with_state(state, audit::record))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/shards",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every region shard, empty without SHARD_BY_REGION", body = [ShardStats]),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+pub async fn list_shards(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut stats = Vec::new();
+    let shards = state.shards.iter().flat_map(|s| &s.shards);
+    for (region, shard) in REGIONS.iter().zip(shards) {
+        let queued = shard
+            .queue
+            .read()
+            .await
+            .values()
+            .flat_map(|q| q.iter())
+            .map(|e| e.members.len())
+            .sum();
+        let matches = shard.matches.lock().await;
+        let open = matches
+            .values()
+            .filter(|m| matches!(m.status, MatchStatus::Pending | MatchStatus::Active));
+        stats.push(ShardStats {
+            region: *region,
+            profiles: shard.profiles.len(),
+            queued,
+            matches: matches.len(),
+            open_matches: open.count(),
+        });
+    }
+    Ok((StatusCode::OK, Json(stats)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::AUTHORIZATION, Method};
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const SECRET: &str = "shard-test-secret";
+    const ADMIN_KEY: &str = "shard-admin-key";
+
+    fn app() -> Router {
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            admin_key: Some(ADMIN_KEY.to_string()),
+            shard_by_region: true,
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        crate::app(Arc::new(sharded(config, db::memory(), Loaded::default())))
+    }
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        path: &str,
+        player: Uuid,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let claims = json!({ "sub": player, "exp": chrono::Utc::now().timestamp() + 3600 });
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        let token = jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap();
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header("x-admin-key", ADMIN_KEY)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn players_are_matched_within_their_region() {
+        let app = app();
+        let mut ids = Vec::new();
+        for (name, region) in [
+            ("alice", "Europe"),
+            ("bob", "NorthAmerica"),
+            ("carol", "Europe"),
+        ] {
+            let body = json!({ "name": name, "region": region });
+            let (status, profile) =
+                send(&app, Method::POST, "/v1/profiles", Uuid::nil(), body).await;
+            assert_eq!(status, StatusCode::CREATED);
+            ids.push(profile["id"].as_str().unwrap().parse::<Uuid>().unwrap());
+        }
+        let enqueue = |player: Uuid| {
+            let body = json!({ "profile_id": player, "mode": "CasualSolo" });
+            send(&app, Method::POST, "/v1/queue/enqueue", player, body)
+        };
+
+        // same mmr and mode, but alice and bob are on different shards
+        let (status, _) = enqueue(ids[0]).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, _) = enqueue(ids[1]).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (_, shards) = send(
+            &app,
+            Method::GET,
+            "/v1/admin/shards",
+            Uuid::nil(),
+            Value::Null,
+        )
+        .await;
+        let queued = |region: &str| {
+            let shard = shards
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|s| s["region"] == region);
+            shard.unwrap()["queued"].clone()
+        };
+        assert_eq!(
+            (queued("Europe"), queued("NorthAmerica")),
+            (json!(1), json!(1))
+        );
+        // public reads without an id or region cover every shard
+        let (_, queue) = send(&app, Method::GET, "/v1/queue", Uuid::nil(), Value::Null).await;
+        assert_eq!(queue.as_array().unwrap().len(), 2);
+        let search = "/v1/profiles?name=BO";
+        let (_, found) = send(&app, Method::GET, search, Uuid::nil(), Value::Null).await;
+        assert_eq!(found[0]["id"], ids[1].to_string());
+        // and a name taken in one region is taken in all of them
+        let body = json!({ "name": "Alice", "region": "SouthAmerica" });
+        let (status, body) = send(&app, Method::POST, "/v1/profiles", Uuid::nil(), body).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "NAME_TAKEN");
+
+        let (status, m) = enqueue(ids[2]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(m["player1"], ids[0].to_string());
+        let path = format!("/v1/matches/{}", m["id"].as_str().unwrap());
+        let (status, _) = send(&app, Method::GET, &path, Uuid::nil(), Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let path = format!("/v1/profiles/{}", ids[1]);
+        let (status, bob) = send(&app, Method::GET, &path, Uuid::nil(), Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bob["region"], "NorthAmerica");
+
+        // admin lists without an id come from every shard
+        let (status, stats) = send(
+            &app,
+            Method::GET,
+            "/v1/admin/reports",
+            Uuid::nil(),
+            Value::Null,
+        )
+        .await;
+        assert_eq!((status, stats), (StatusCode::OK, json!([])));
+    }
+}
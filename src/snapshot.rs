@@ -0,0 +1,175 @@
+// JSON snapshots of the in-memory state, for deployments without a database.
+// a snapshot holds profiles, queues and matches; everything else (parties,
+// lobbies, webhooks, ...) starts empty after a restore. a configured database
+// is not touched by either direction.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    db::StoredQueueEntry, error::AppError, lanes::ModeQueue, external_ids, normalize_name, AppState, GameMode,
+    MatchInfo, Profile,
+};
+
+// bumped whenever the layout below changes incompatibly
+pub const SCHEMA_VERSION: u32 = 1;
+
+// `path` with `-<suffix>` before its extension, for states that keep a
+// snapshot of their own next to the instance's
+pub fn sibling_path(path: &str, suffix: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("snapshot");
+    let file = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{stem}-{suffix}.{ext}"),
+        None => format!("{stem}-{suffix}"),
+    };
+    path.with_file_name(file).to_string_lossy().into_owned()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    schema_version: u32,
+    profiles: Vec<Profile>,
+    // each mode's entries in queue order
+    queues: HashMap<GameMode, Vec<StoredQueueEntry>>,
+    matches: Vec<MatchInfo>,
+}
+
+// read first, so a newer layout is reported as such rather than as a parse error
+#[derive(Deserialize)]
+struct Version {
+    schema_version: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SnapshotSaved {
+    pub path: String,
+    pub written_bytes: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SnapshotRestored {
+    pub path: String,
+    pub profiles: usize,
+    // players, party members included
+    pub queued: usize,
+    pub matches: usize,
+}
+
+// writes next to the target and renames, so a crash never leaves half a file
+pub async fn save(state: &AppState) -> Result<SnapshotSaved, AppError> {
+    let queues = state.queue.read().await;
+    let matches = state.matches.lock().await;
+    let snapshot = Snapshot {
+        schema_version: SCHEMA_VERSION,
+        profiles: state.profiles.iter().map(|p| p.clone()).collect(),
+        queues: queues
+            .iter()
+            .map(|(mode, q)| (*mode, q.iter().cloned().map(StoredQueueEntry::from).collect()))
+            .collect(),
+        matches: matches.values().cloned().collect(),
+    };
+    drop(matches);
+    drop(queues);
+
+    let data = serde_json::to_vec(&snapshot).map_err(|e| AppError::SnapshotFailed(e.to_string()))?;
+    let path = &state.config.snapshot_path;
+    let tmp = format!("{path}.tmp");
+    tokio::fs::write(&tmp, &data)
+        .await
+        .map_err(|e| AppError::SnapshotFailed(format!("cannot write {tmp}: {e}")))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .map_err(|e| AppError::SnapshotFailed(format!("cannot replace {path}: {e}")))?;
+    Ok(SnapshotSaved {
+        path: path.clone(),
+        written_bytes: data.len(),
+    })
+}
+
+// replaces profiles, queues and matches with the snapshot's. every lock up to
+// lobbies is held while swapping, so nothing that takes them sees a mix of old
+// and new state
+pub async fn restore(state: &AppState) -> Result<SnapshotRestored, AppError> {
+    let path = &state.config.snapshot_path;
+    let data = tokio::fs::read(path)
+        .await
+        .map_err(|e| AppError::SnapshotFailed(format!("cannot read {path}: {e}")))?;
+    let version: Version =
+        serde_json::from_slice(&data).map_err(|e| AppError::SnapshotFailed(e.to_string()))?;
+    if version.schema_version != SCHEMA_VERSION {
+        return Err(AppError::SnapshotFailed(format!(
+            "unsupported schema_version {}, expected {SCHEMA_VERSION}",
+            version.schema_version
+        )));
+    }
+    let mut snapshot: Snapshot =
+        serde_json::from_slice(&data).map_err(|e| AppError::SnapshotFailed(e.to_string()))?;
+    for p in &mut snapshot.profiles {
+        p.fill_mode_mmr();
+    }
+
+    let mut new_queues: HashMap<GameMode, ModeQueue> = HashMap::new();
+    let mut queued = 0;
+    for (mode, entries) in snapshot.queues {
+        let queue = new_queues.entry(mode).or_default();
+        for stored in entries {
+            let entry = stored.into_entry();
+            queued += entry.members.len();
+            queue.insert(entry);
+        }
+    }
+    let name_index: HashMap<String, _> = snapshot
+        .profiles
+        .iter()
+        .map(|p| (normalize_name(&p.name), p.id))
+        .collect();
+    let external_ids = external_ids(&snapshot.profiles);
+    let restored = SnapshotRestored {
+        path: path.clone(),
+        profiles: snapshot.profiles.len(),
+        queued,
+        matches: snapshot.matches.len(),
+    };
+
+    let mut parties = state.parties.lock().await;
+    let mut queues = state.queue.write().await;
+    let mut matches = state.matches.lock().await;
+    let mut names = state.name_index.lock().await;
+    let mut lobbies = state.lobbies.lock().await;
+    let changed: HashSet<GameMode> = queues.keys().chain(new_queues.keys()).copied().collect();
+    // the index is shared with the other shards, whose names stay
+    names.retain(|_, id| !state.profiles.contains_key(id));
+    state.profiles.clear();
+    for p in snapshot.profiles {
+        state.record_home(p.id);
+        state.profiles.insert(p.id, p);
+    }
+    *queues = new_queues;
+    *matches = snapshot.matches.into_iter().map(|m| (m.id, m)).collect();
+    for id in matches.keys() {
+        state.record_home(*id);
+    }
+    names.extend(name_index);
+    state.external_ids.clear();
+    for (external_id, id) in external_ids {
+        state.external_ids.insert(external_id, id);
+    }
+    parties.clear();
+    lobbies.clear();
+    drop(lobbies);
+    drop(names);
+    drop(matches);
+    drop(queues);
+    drop(parties);
+
+    for mode in changed {
+        let _ = state.queue_changes.send(mode);
+    }
+    Ok(restored)
+}
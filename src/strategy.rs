@@ -0,0 +1,158 @@
+// Per-mode matching strategies, set with `[matching_strategies]` in the config
+// file. a strategy only picks among entries that may meet `candidate` at all:
+// same size, not recent opponents, not blocked. modes without one keep the
+// built-in rule of `matchmaking::compatible`, the first entry in queue order
+// within the expanding mmr window and region.
+
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::QueueEntry;
+
+pub trait MatchingStrategy: Send + Sync {
+    // index into `queue`, which is in queue order, of the opponent for
+    // `candidate`. any randomness comes from `rng`, the state's seeded one
+    fn find_opponent(
+        &self,
+        candidate: &QueueEntry,
+        queue: &[&QueueEntry],
+        rng: &mut StdRng,
+    ) -> Option<usize>;
+}
+
+// whoever has waited longest, whatever their rating
+pub struct FifoStrategy;
+
+impl MatchingStrategy for FifoStrategy {
+    fn find_opponent(
+        &self,
+        _: &QueueEntry,
+        queue: &[&QueueEntry],
+        _: &mut StdRng,
+    ) -> Option<usize> {
+        (!queue.is_empty()).then_some(0)
+    }
+}
+
+pub struct RandomStrategy;
+
+impl MatchingStrategy for RandomStrategy {
+    fn find_opponent(
+        &self,
+        _: &QueueEntry,
+        queue: &[&QueueEntry],
+        rng: &mut StdRng,
+    ) -> Option<usize> {
+        (!queue.is_empty()).then(|| rng.gen_range(0..queue.len()))
+    }
+}
+
+// the nearest rating, however far; ties go to the longest waiting
+pub struct ClosestMmrStrategy;
+
+impl MatchingStrategy for ClosestMmrStrategy {
+    fn find_opponent(
+        &self,
+        candidate: &QueueEntry,
+        queue: &[&QueueEntry],
+        _: &mut StdRng,
+    ) -> Option<usize> {
+        (0..queue.len()).min_by_key(|&i| queue[i].mmr.abs_diff(candidate.mmr))
+    }
+}
+
+// the longest waiting within a fixed mmr difference, in any region
+pub struct WindowedMmrStrategy(pub u32);
+
+impl MatchingStrategy for WindowedMmrStrategy {
+    fn find_opponent(
+        &self,
+        candidate: &QueueEntry,
+        queue: &[&QueueEntry],
+        _: &mut StdRng,
+    ) -> Option<usize> {
+        queue
+            .iter()
+            .position(|e| e.mmr.abs_diff(candidate.mmr) <= self.0)
+    }
+}
+
+// "fifo", "random", "closest" or "window:<mmr>"
+pub fn parse(spec: &str) -> Result<Arc<dyn MatchingStrategy>, String> {
+    Ok(match spec.trim() {
+        "fifo" => Arc::new(FifoStrategy),
+        "random" => Arc::new(RandomStrategy),
+        "closest" => Arc::new(ClosestMmrStrategy),
+        other => {
+            let window = other
+                .strip_prefix("window:")
+                .and_then(|w| w.trim().parse().ok())
+                .ok_or_else(|| format!("unknown matching strategy {other:?}"))?;
+            Arc::new(WindowedMmrStrategy(window))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use chrono::Utc;
+    use rand::SeedableRng;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{lanes::Lane, Region};
+
+    fn entry(mmr: u32) -> QueueEntry {
+        let id = Uuid::new_v4();
+        QueueEntry {
+            profile_id: id,
+            party_id: None,
+            members: vec![id],
+            mmr,
+            region: Region::Europe,
+            queued_at: Instant::now(),
+            queued_since: Utc::now(),
+            last_heartbeat: Instant::now(),
+            lane: Lane::Normal,
+            team_size: None,
+        }
+    }
+
+    #[test]
+    fn strategies_pick_as_named() {
+        let waiting = [entry(1400), entry(1100), entry(1250)];
+        let queue: Vec<&QueueEntry> = waiting.iter().collect();
+        let candidate = entry(1200);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut pick = |spec: &str| {
+            parse(spec)
+                .unwrap()
+                .find_opponent(&candidate, &queue, &mut rng)
+        };
+        assert_eq!(pick("fifo"), Some(0));
+        assert_eq!(pick("closest"), Some(2));
+        assert_eq!(pick("window:100"), Some(1));
+        assert_eq!(pick("window:10"), None);
+        assert!(pick("random").is_some_and(|i| i < queue.len()));
+
+        // the same seed makes the same random picks
+        let picks = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..16)
+                .map(|_| RandomStrategy.find_opponent(&candidate, &queue, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_eq!(
+            RandomStrategy.find_opponent(&candidate, &[], &mut StdRng::seed_from_u64(7)),
+            None
+        );
+
+        assert!(parse("window:wide").is_err());
+        assert!(parse("elo").is_err());
+    }
+}
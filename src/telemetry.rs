@@ -0,0 +1,200 @@
+// Logging, and with `otel_exporter_otlp_endpoint` set (OTEL_EXPORTER_OTLP_ENDPOINT)
+// OpenTelemetry traces exported over OTLP/HTTP. the "request" span of every
+// HTTP request (see request_id.rs) continues the trace of an incoming W3C
+// `traceparent` header, so game server traces link up with ours. the queue and
+// match handlers, and the work they hand off (queue scans, rating updates,
+// webhook deliveries), are `#[tracing::instrument]`ed spans below it, naming
+// their profile_id, match_id or game_mode. the record_* helpers fill in what
+// is only known part way through, on the current span.
+
+use axum::http::HeaderMap;
+use opentelemetry::{global, propagation::Extractor, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+// the OpenTelemetry service.name of exported spans
+const SERVICE_NAME: &str = "matchmaker";
+
+// installs the global subscriber: INFO and up to stdout, as before, and to
+// the OTLP exporter when one is configured. panics on an invalid endpoint,
+// like the rest of startup
+pub fn init(config: &Config) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let otel = config.otel_exporter_otlp_endpoint.as_ref().map(|endpoint| {
+        let exporter = opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint);
+        let resource = Resource::new([KeyValue::new("service.name", SERVICE_NAME)]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter)
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(opentelemetry_sdk::runtime::Tokio)
+            .unwrap_or_else(|e| panic!("cannot set up OTLP export to {endpoint}: {e}"));
+        tracing_opentelemetry::layer().with_tracer(tracer)
+    });
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel)
+        .init();
+}
+
+// sends the spans still buffered, at shutdown
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+// makes `span` a child of the trace in the request's `traceparent`, if any
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
+
+// how many players were already waiting in the mode being joined
+pub fn record_queue_depth(queue_depth: usize) {
+    Span::current().record("queue_depth", queue_depth);
+}
+
+// the match a queue join ended in
+pub fn record_match(match_id: Uuid) {
+    Span::current().record("match_id", tracing::field::display(match_id));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        net::SocketAddr,
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Method, Request, StatusCode,
+        },
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        db::{self, Loaded},
+        new_profile, AppState, CreateProfile, Region,
+    };
+
+    const SECRET: &str = "telemetry-test-secret";
+
+    // keeps every finished span
+    #[derive(Debug, Clone, Default)]
+    struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Collected {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn request_span_continues_the_callers_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let collected = Collected::default();
+        let provider = trace::TracerProvider::builder()
+            .with_simple_exporter(collected.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            matching_interval_ms: 0,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(config, db::memory(), Loaded::default()));
+        let payload = CreateProfile {
+            name: "alice".to_string(),
+            mmr: 1000,
+            region: Region::Europe,
+        };
+        let alice = new_profile(&state, payload).await.unwrap().id;
+        let claims = json!({ "sub": alice, "exp": chrono::Utc::now().timestamp() + 3600 });
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        let token = jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap();
+        let body = json!({ "profile_id": alice, "mode": "CasualSolo" });
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri("/v1/queue/enqueue")
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        let res = crate::app(state).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        // the simple processor exports from a thread of its own
+        provider.force_flush();
+        let spans = collected.0.lock().unwrap();
+        let named = |name: &str| spans.iter().find(|s| s.name == name).unwrap();
+        let request = named("request");
+        let trace_id = request.span_context.trace_id().to_string();
+        assert_eq!(trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(request.parent_span_id.to_string(), "00f067aa0ba902b7");
+
+        // request > enqueue > join_queue > find_opponents, all in that trace
+        let chain = ["request", "enqueue", "join_queue", "find_opponents"];
+        for pair in chain.windows(2) {
+            let (parent, child) = (named(pair[0]), named(pair[1]));
+            assert_eq!(child.parent_span_id, parent.span_context.span_id());
+            assert_eq!(
+                child.span_context.trace_id(),
+                request.span_context.trace_id()
+            );
+        }
+        let join = named("join_queue");
+        let attribute = |name: &str| {
+            join.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == name)
+                .map(|kv| kv.value.to_string())
+        };
+        assert_eq!(attribute("profile_id"), Some(alice.to_string()));
+        assert_eq!(attribute("game_mode").as_deref(), Some("CasualSolo"));
+        assert_eq!(attribute("queue_depth").as_deref(), Some("0"));
+    }
+}
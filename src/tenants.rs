@@ -0,0 +1,346 @@
+// Tenants: several game titles on one instance. every tenant gets a complete
+// AppState of its own (profiles, queues, matches, parties, guilds, logs) and
+// its own copy of the v1 routes under /v1/tenants/:tenant_id/, so nothing is
+// shared between tenants or with the untenanted API at /v1. requests need
+// the tenant's key in X-Tenant-Key; its admin routes also take the instance's
+// X-Admin-Key. tenants use the instance's settings, except for its database,
+// snapshot file and Discord webhook, live in memory only and are managed with
+// /v1/admin/tenants.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, State},
+    http::{Request, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{any, delete, post},
+    Extension, Json, Router,
+};
+use hyper::upgrade::OnUpgrade;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    auth::{self, AdminIdentity},
+    config::Config,
+    db,
+    error::AppError,
+    msgpack::MsgpackOrJson,
+    routes, snapshot, spawn_background, AppState,
+};
+
+pub const TENANT_KEY_HEADER: &str = "x-tenant-key";
+
+// longest tenant name, in characters
+const TENANT_NAME_MAX: usize = 64;
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct Tenant {
+    pub id: Uuid,
+    pub name: String,
+    // only returned when the tenant is created
+    pub api_key: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTenant {
+    pub name: String,
+}
+
+pub struct TenantState {
+    pub tenant: Tenant,
+    // behind a lock only because a Router is not Sync; requests use clones
+    router: Mutex<Router>,
+    // queue sweeps and decay, stopped with the tenant
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TenantState {
+    fn new(parent: &AppState, name: String) -> TenantState {
+        let id = Uuid::new_v4();
+        let config = tenant_config(&parent.config, id);
+        let state = Arc::new(AppState::new(config, db::memory(), db::Loaded::default()));
+        let router = routes::v1::v1_router(state.clone())
+            .layer(middleware::from_fn_with_state(state.clone(), audit::record))
+            .with_state(state.clone());
+        let api_key = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        TenantState {
+            tenant: Tenant { id, name, api_key },
+            tasks: spawn_background(&state),
+            router: Mutex::new(router),
+        }
+    }
+}
+
+// the instance's settings, without its database or Discord channel, and with
+// a snapshot file of the tenant's own next to the instance's
+fn tenant_config(parent: &Config, id: Uuid) -> Config {
+    Config {
+        database_url: None,
+        postgres_url: None,
+        discord_webhook_url: None,
+        snapshot_path: snapshot::sibling_path(&parent.snapshot_path, &id.to_string()),
+        ..parent.clone()
+    }
+}
+
+impl Drop for TenantState {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let admin = Router::new()
+        .route("/v1/admin/tenants", post(create_tenant))
+        .route("/v1/admin/tenants/:id", delete(delete_tenant))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_admin,
+        ))
+        .layer(middleware::from_fn_with_state(state, audit::record));
+    Router::new()
+        .route("/v1/tenants/:tenant_id/*rest", any(proxy))
+        .merge(admin)
+}
+
+// hands the request to the tenant's own router, as if sent to /v1/<rest>
+async fn proxy(
+    State(state): State<Arc<AppState>>,
+    Path((tenant_id, _)): Path<(Uuid, String)>,
+    req: Request<Body>,
+) -> Result<Response, AppError> {
+    let tenant = state
+        .tenants
+        .get(&tenant_id)
+        .map(|t| t.clone())
+        .ok_or(AppError::TenantNotFound)?;
+    let given = req
+        .headers()
+        .get(TENANT_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !given.is_some_and(|key| auth::keys_match(&tenant.tenant.api_key, key)) {
+        return Err(AppError::InvalidTenantKey);
+    }
+
+    // the raw path, so percent-encoded segments reach the tenant unchanged
+    let prefix = format!("/v1/tenants/{tenant_id}");
+    let (mut parts, body) = req.into_parts();
+    let rest = parts.uri.path().get(prefix.len()..).unwrap_or_default();
+    let rest = match parts.uri.query() {
+        Some(query) => format!("{rest}?{query}"),
+        None => rest.to_string(),
+    };
+    // a fresh request, so this route's path params don't reach the tenant's
+    // extractors. only the peer address (rate limits) and the websocket
+    // upgrade are carried over
+    let mut inner = Request::builder()
+        .method(parts.method)
+        .uri(rest)
+        .version(parts.version)
+        .body(body)
+        .map_err(|_| AppError::TenantNotFound)?;
+    *inner.headers_mut() = parts.headers;
+    if let Some(peer) = parts.extensions.remove::<ConnectInfo<SocketAddr>>() {
+        inner.extensions_mut().insert(peer);
+    }
+    if let Some(upgrade) = parts.extensions.remove::<OnUpgrade>() {
+        inner.extensions_mut().insert(upgrade);
+    }
+    let router = tenant.router.lock().unwrap().clone();
+    let res = router.oneshot(inner).await;
+    Ok(res.into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/tenants",
+    tag = "admin",
+    request_body = CreateTenant,
+    responses(
+        (status = 201, description = "Tenant created, with its key", body = Tenant),
+        (status = 400, description = "Empty or too long name", body = ApiError),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+pub async fn create_tenant(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    MsgpackOrJson(payload): MsgpackOrJson<CreateTenant>,
+) -> Result<impl IntoResponse, AppError> {
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > TENANT_NAME_MAX {
+        return Err(AppError::InvalidTenantName);
+    }
+    let tenant = TenantState::new(&state, name);
+    let created = tenant.tenant.clone();
+    state.tenants.insert(created.id, Arc::new(tenant));
+    tracing::info!(%admin, tenant_id = %created.id, name = %created.name, "tenant created");
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/tenants/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Tenant id")),
+    responses(
+        (status = 204, description = "Tenant and all of its data removed"),
+        (status = 401, description = "Missing or invalid admin key", body = ApiError),
+        (status = 404, description = "Tenant not found", body = ApiError),
+    ),
+    security(("admin_key" = []))
+)]
+pub async fn delete_tenant(
+    State(state): State<Arc<AppState>>,
+    Extension(AdminIdentity(admin)): Extension<AdminIdentity>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    state.tenants.remove(&id).ok_or(AppError::TenantNotFound)?;
+    tracing::warn!(%admin, tenant_id = %id, "tenant deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        extract::ConnectInfo,
+        http::{header::AUTHORIZATION, header::CONTENT_TYPE, Method},
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const SECRET: &str = "tenant-test-secret";
+    const ADMIN_KEY: &str = "tenant-admin-key";
+
+    fn app() -> Router {
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            admin_key: Some(ADMIN_KEY.to_string()),
+            ..Config::default()
+        };
+        crate::app(Arc::new(AppState::new(
+            config,
+            db::memory(),
+            db::Loaded::default(),
+        )))
+    }
+
+    fn request(method: Method, path: &str, headers: &[(&str, &str)], body: Value) -> Request<Body> {
+        let claims = json!({ "sub": Uuid::nil(), "exp": chrono::Utc::now().timestamp() + 3600 });
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        let token = jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap();
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {token}"));
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = req.body(Body::from(body.to_string())).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        req
+    }
+
+    async fn send(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[test]
+    fn tenants_get_their_own_snapshot_and_no_discord_channel() {
+        let parent = Config {
+            snapshot_path: "/var/lib/matchmaker/snapshot.json".to_string(),
+            discord_webhook_url: Some("https://discord.example/hook".to_string()),
+            ..Config::default()
+        };
+        let id = Uuid::new_v4();
+        let config = tenant_config(&parent, id);
+        assert_eq!(
+            config.snapshot_path,
+            format!("/var/lib/matchmaker/snapshot-{id}.json")
+        );
+        assert_eq!(config.discord_webhook_url, None);
+        assert_ne!(
+            tenant_config(&parent, Uuid::new_v4()).snapshot_path,
+            config.snapshot_path
+        );
+    }
+
+    #[tokio::test]
+    async fn tenants_do_not_share_state() {
+        let app = app();
+        let admin = [("x-admin-key", ADMIN_KEY)];
+        let create = json!({ "name": "second title" });
+        let (status, tenant) = send(
+            &app,
+            request(Method::POST, "/v1/admin/tenants", &admin, create),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = tenant["id"].as_str().unwrap();
+        let key = tenant["api_key"].as_str().unwrap();
+        let tenant_key = [(TENANT_KEY_HEADER, key)];
+
+        let profiles = format!("/v1/tenants/{id}/profiles");
+        let alice = json!({ "name": "alice", "mmr": 1200 });
+        let (status, profile) = send(
+            &app,
+            request(Method::POST, &profiles, &tenant_key, alice.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let profile_id = profile["id"].as_str().unwrap();
+
+        let own = format!("/v1/tenants/{id}/profiles/{profile_id}");
+        let (status, _) = send(&app, request(Method::GET, &own, &tenant_key, Value::Null)).await;
+        assert_eq!(status, StatusCode::OK);
+        let untenanted = format!("/v1/profiles/{profile_id}");
+        let (status, _) = send(&app, request(Method::GET, &untenanted, &[], Value::Null)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let wrong_key = [(TENANT_KEY_HEADER, "not-the-key")];
+        let (status, body) = send(&app, request(Method::GET, &own, &wrong_key, Value::Null)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "INVALID_TENANT_KEY");
+
+        let tenant_path = format!("/v1/admin/tenants/{id}");
+        let (status, _) = send(
+            &app,
+            request(Method::DELETE, &tenant_path, &admin, Value::Null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = send(&app, request(Method::GET, &own, &tenant_key, Value::Null)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "TENANT_NOT_FOUND");
+    }
+}
@@ -0,0 +1,201 @@
+// Tournament brackets. the bracket's matches are ordinary matches in
+// `AppState::matches`; a tournament keeps a copy of each round and works out
+// the next pairings from their results.
+//
+// elimination formats group the remaining players by losses and pair each
+// group best seed against worst seed. a group of odd size gives a bye to the
+// player with the fewest byes so far. in double elimination the last two
+// players meet even from different groups (the grand final). a draw or a
+// cancelled match counts as a win for the better seed.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{GameMode, MatchInfo, MatchResult, MatchStatus};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub enum Format {
+    SingleElimination,
+    DoubleElimination,
+    RoundRobin,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct Tournament {
+    pub id: Uuid,
+    pub name: String,
+    pub format: Format,
+    pub mode: GameMode,
+    // participants by seed, highest mmr first
+    pub seeds: Vec<Uuid>,
+    pub rounds: Vec<Vec<MatchInfo>>,
+    // players left without an opponent in each round
+    pub byes: Vec<Vec<Uuid>>,
+    pub winner: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTournament {
+    pub name: String,
+    pub participants: Vec<Uuid>,
+    pub format: Format,
+    // decides which mmr seeds the bracket and which track the matches rate
+    #[serde(default)]
+    pub mode: GameMode,
+}
+
+// what comes after the current round
+#[derive(Debug, PartialEq)]
+pub enum Next {
+    Round {
+        pairs: Vec<(Uuid, Uuid)>,
+        byes: Vec<Uuid>,
+    },
+    Finished(Uuid),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RoundUnfinished;
+
+// participants ordered for seeding, best first; ties keep the given order
+pub fn seed(mut participants: Vec<(Uuid, u32)>) -> Vec<Uuid> {
+    participants.sort_by_key(|&(_, mmr)| std::cmp::Reverse(mmr));
+    participants.into_iter().map(|(id, _)| id).collect()
+}
+
+impl Tournament {
+    // pairings of the next round, or the winner once the bracket is done
+    pub fn next(&self) -> Result<Next, RoundUnfinished> {
+        let done = |m: &MatchInfo| matches!(m.status, MatchStatus::Completed | MatchStatus::Cancelled);
+        if let Some(last) = self.rounds.last() {
+            if !last.iter().all(done) {
+                return Err(RoundUnfinished);
+            }
+        }
+        Ok(match self.format {
+            Format::SingleElimination => self.next_elimination(1),
+            Format::DoubleElimination => self.next_elimination(2),
+            Format::RoundRobin => self.next_round_robin(),
+        })
+    }
+
+    fn seed_of(&self, id: Uuid) -> usize {
+        self.seeds.iter().position(|&s| s == id).unwrap_or(usize::MAX)
+    }
+
+    // (winner, loser) of a finished match, see the module comment for draws
+    fn decided(&self, m: &MatchInfo) -> (Uuid, Uuid) {
+        let (a, b) = (m.player1, m.player2);
+        let better_first = if self.seed_of(a) <= self.seed_of(b) { (a, b) } else { (b, a) };
+        match (m.status, m.result) {
+            (MatchStatus::Completed, Some(MatchResult::Player1Win)) => (a, b),
+            (MatchStatus::Completed, Some(MatchResult::Player2Win)) => (b, a),
+            _ => better_first,
+        }
+    }
+
+    fn next_elimination(&self, max_losses: u32) -> Next {
+        let mut losses: HashMap<Uuid, u32> = self.seeds.iter().map(|&id| (id, 0)).collect();
+        for m in self.rounds.iter().flatten() {
+            let (_, loser) = self.decided(m);
+            *losses.entry(loser).or_default() += 1;
+        }
+        let mut bye_count: HashMap<Uuid, usize> = HashMap::new();
+        for id in self.byes.iter().flatten() {
+            *bye_count.entry(*id).or_default() += 1;
+        }
+
+        let alive: Vec<Uuid> = self
+            .seeds
+            .iter()
+            .copied()
+            .filter(|id| losses[id] < max_losses)
+            .collect();
+        if alive.len() == 1 {
+            return Next::Finished(alive[0]);
+        }
+        if alive.len() == 2 {
+            return Next::Round {
+                pairs: vec![(alive[0], alive[1])],
+                byes: Vec::new(),
+            };
+        }
+
+        let mut pairs = Vec::new();
+        let mut byes = Vec::new();
+        for group_losses in 0..max_losses {
+            let mut group: Vec<Uuid> = alive
+                .iter()
+                .copied()
+                .filter(|id| losses[id] == group_losses)
+                .collect();
+            if group.len() % 2 == 1 {
+                // min_by_key keeps the first, i.e. best seeded, on ties
+                let idx = (0..group.len())
+                    .min_by_key(|&i| bye_count.get(&group[i]).copied().unwrap_or(0))
+                    .unwrap();
+                byes.push(group.remove(idx));
+            }
+            let half = group.len() / 2;
+            for i in 0..half {
+                pairs.push((group[i], group[group.len() - 1 - i]));
+            }
+        }
+        Next::Round { pairs, byes }
+    }
+
+    // circle method: the first seed stays put while the others rotate
+    fn next_round_robin(&self) -> Next {
+        let mut slots: Vec<Option<Uuid>> = self.seeds.iter().copied().map(Some).collect();
+        if slots.len() % 2 == 1 {
+            slots.push(None);
+        }
+        let total = slots.len() - 1;
+        let round = self.rounds.len();
+        if round == total {
+            return Next::Finished(self.round_robin_leader());
+        }
+
+        slots[1..].rotate_right(round);
+        let mut pairs = Vec::new();
+        let mut byes = Vec::new();
+        let n = slots.len();
+        for i in 0..n / 2 {
+            match (slots[i], slots[n - 1 - i]) {
+                (Some(a), Some(b)) => pairs.push((a, b)),
+                (Some(a), None) | (None, Some(a)) => byes.push(a),
+                (None, None) => {}
+            }
+        }
+        Next::Round { pairs, byes }
+    }
+
+    // most points (win 2, draw 1), better seed on ties. cancelled games score nothing
+    fn round_robin_leader(&self) -> Uuid {
+        let mut points: HashMap<Uuid, u32> = HashMap::new();
+        for m in self.rounds.iter().flatten() {
+            if m.status != MatchStatus::Completed {
+                continue;
+            }
+            match m.result {
+                Some(MatchResult::Player1Win) => *points.entry(m.player1).or_default() += 2,
+                Some(MatchResult::Player2Win) => *points.entry(m.player2).or_default() += 2,
+                Some(MatchResult::Draw) => {
+                    *points.entry(m.player1).or_default() += 1;
+                    *points.entry(m.player2).or_default() += 1;
+                }
+                None => {}
+            }
+        }
+        // max_by_key keeps the last maximum, so walk the seeds worst first
+        self.seeds
+            .iter()
+            .rev()
+            .copied()
+            .max_by_key(|id| points.get(id).copied().unwrap_or(0))
+            .unwrap()
+    }
+}
@@ -0,0 +1,394 @@
+// Outgoing webhooks: registered URLs are POSTed match events, signed with
+// HMAC-SHA256 over the raw body using the secret given at registration, sent
+// hex encoded as `X-Matchmaker-Signature: sha256=<hex>` like GitHub's. the
+// secret itself is not kept: only the HMAC state after hashing its inner and
+// outer pads, which is all signing needs and is never serialized, printed or
+// logged.
+//
+// every URL has a circuit breaker. after CIRCUIT_FAILURE_THRESHOLD failed
+// attempts in a row it opens and deliveries to that URL are dropped instead
+// of queued; once `webhook_half_open_secs` have passed one delivery is let
+// through as a trial, and its success closes the circuit again. webhooks
+// whose circuit has stayed open for DISABLE_AFTER are disabled for good.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::Instrument;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{AppState, MatchInfo};
+
+// retries after the first failed attempt, waiting 1s, 2s, 4s, ...
+const MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+pub const SIGNATURE_HEADER: &str = "x-matchmaker-signature";
+
+// failed attempts in a row, retries included, that open a circuit
+const CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+
+fn disable_after() -> chrono::Duration {
+    chrono::Duration::hours(24)
+}
+
+// named after the event strings, which all start with "match."
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "match.created")]
+    MatchCreated,
+    #[serde(rename = "match.completed")]
+    MatchCompleted,
+    #[serde(rename = "match.cancelled")]
+    MatchCancelled,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    // derived from the secret given at registration, never echoed back
+    #[serde(skip)]
+    pub key: SigningKey,
+    // set once its circuit has been open for a day; nothing is sent to a
+    // disabled webhook. register it again to re-enable it
+    pub disabled: bool,
+}
+
+// HMAC-SHA256 keyed with a webhook's secret, holding only the hash states of
+// the padded key
+#[derive(Clone)]
+pub struct SigningKey(Hmac<Sha256>);
+
+impl SigningKey {
+    pub fn new(secret: &str) -> SigningKey {
+        SigningKey(Hmac::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length"))
+    }
+
+    // hex encoded HMAC-SHA256 of `body`
+    fn sign(&self, body: &[u8]) -> String {
+        let mut mac = self.0.clone();
+        mac.update(body);
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+// by hand, so not even the hash states reach logs and panics
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: WebhookEvent,
+    r#match: &'a MatchInfo,
+}
+
+// sends `event` for `m` to every webhook subscribed to it. deliveries run in
+// the background and never hold up the caller
+#[tracing::instrument(skip_all, fields(?event, match_id = %m.id))]
+pub async fn dispatch(state: &AppState, event: WebhookEvent, m: &MatchInfo) {
+    let body = serde_json::to_vec(&Payload { event, r#match: m }).unwrap();
+    let mut webhooks = state.webhooks.lock().await;
+    for hook in webhooks.iter_mut().filter(|h| h.events.contains(&event)) {
+        if state.webhook_circuits.disable_if_stuck(hook) {
+            continue;
+        }
+        let signature = format!("sha256={}", hook.key.sign(&body));
+        send(
+            state,
+            Delivery {
+                target: hook.id.to_string(),
+                url: hook.url.clone(),
+                signature: Some(signature),
+                body: body.clone(),
+            },
+        );
+    }
+}
+
+// one JSON POST, sent with retries behind its URL's circuit breaker
+pub struct Delivery {
+    // names the receiver in logs: a webhook id, or "discord"
+    pub target: String,
+    pub url: String,
+    // the X-Matchmaker-Signature value, for registered webhooks
+    pub signature: Option<String>,
+    pub body: Vec<u8>,
+}
+
+// starts `delivery` in the background, unless its circuit is open
+pub fn send(state: &AppState, delivery: Delivery) {
+    if !state.webhook_circuits.allow(&delivery.url) {
+        tracing::debug!(target = %delivery.target, "circuit open, webhook delivery dropped");
+        return;
+    }
+    // its span stays under the request or task that sent it
+    tokio::spawn(
+        deliver(state.http.clone(), state.webhook_circuits.clone(), delivery).in_current_span(),
+    );
+}
+
+#[tracing::instrument(skip_all, fields(target = %delivery.target))]
+async fn deliver(client: reqwest::Client, circuits: Arc<Circuits>, delivery: Delivery) {
+    let Delivery {
+        target,
+        url,
+        signature,
+        body,
+    } = delivery;
+    let mut delay = RETRY_BASE_DELAY;
+    for attempt in 0..=MAX_RETRIES {
+        let mut req = client
+            .post(&url)
+            .header("content-type", "application/json")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            req = req.header(SIGNATURE_HEADER, signature);
+        }
+        match req.send().await {
+            Ok(res) if res.status().is_success() => {
+                circuits.success(&url);
+                return;
+            }
+            Ok(res) => {
+                tracing::warn!(%target, status = %res.status(), attempt, "webhook delivery rejected")
+            }
+            Err(e) => tracing::warn!(%target, attempt, "webhook delivery failed: {e}"),
+        }
+        circuits.failure(&url);
+        // no retries into an open circuit
+        if attempt == MAX_RETRIES || !circuits.allow(&url) {
+            break;
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+    tracing::error!(%target, %url, "giving up on webhook delivery");
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    // the half-open timeout has passed: the next delivery is a trial
+    HalfOpen,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    // when the circuit first opened; kept through failed trials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_since: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    failures: u32,
+    open_since: Option<DateTime<Utc>>,
+    // when an open circuit lets its next trial through
+    retry_at: Option<Instant>,
+    // a trial delivery is under way
+    trial: bool,
+}
+
+// circuit breakers by webhook URL; closed circuits are not stored
+pub struct Circuits {
+    circuits: Mutex<HashMap<String, Circuit>>,
+    half_open_after: Duration,
+}
+
+impl Circuits {
+    pub fn new(half_open_after: Duration) -> Circuits {
+        Circuits {
+            circuits: Mutex::new(HashMap::new()),
+            half_open_after,
+        }
+    }
+
+    // whether a delivery to `url` may be attempted now. the first call after
+    // the half-open timeout takes the trial, later ones wait for its outcome
+    fn allow(&self, url: &str) -> bool {
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(c) = circuits.get_mut(url) else {
+            return true;
+        };
+        match c.retry_at {
+            None => true,
+            Some(at) if !c.trial && Instant::now() >= at => {
+                c.trial = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn success(&self, url: &str) {
+        if let Some(c) = self.circuits.lock().unwrap().remove(url) {
+            if c.open_since.is_some() {
+                tracing::info!(url, "webhook circuit closed");
+            }
+        }
+    }
+
+    fn failure(&self, url: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let c = circuits.entry(url.to_string()).or_default();
+        c.failures += 1;
+        if c.trial {
+            c.trial = false;
+            c.retry_at = Some(Instant::now() + self.half_open_after);
+        } else if c.open_since.is_none() && c.failures >= CIRCUIT_FAILURE_THRESHOLD {
+            c.open_since = Some(Utc::now());
+            c.retry_at = Some(Instant::now() + self.half_open_after);
+            tracing::warn!(url, failures = c.failures, "webhook circuit opened");
+        }
+    }
+
+    pub fn status(&self, url: &str) -> CircuitStatus {
+        let circuits = self.circuits.lock().unwrap();
+        let Some(c) = circuits.get(url).filter(|c| c.open_since.is_some()) else {
+            return CircuitStatus {
+                state: CircuitState::Closed,
+                open_since: None,
+            };
+        };
+        let half_open = c.trial || c.retry_at.is_some_and(|at| Instant::now() >= at);
+        CircuitStatus {
+            state: if half_open {
+                CircuitState::HalfOpen
+            } else {
+                CircuitState::Open
+            },
+            open_since: c.open_since,
+        }
+    }
+
+    // disables `hook` once its circuit has been open for DISABLE_AFTER.
+    // true if it is disabled
+    pub fn disable_if_stuck(&self, hook: &mut Webhook) -> bool {
+        if !hook.disabled {
+            let open_since = self.status(&hook.url).open_since;
+            if open_since.is_some_and(|since| Utc::now() - since >= disable_after()) {
+                hook.disabled = true;
+                tracing::error!(webhook_id = %hook.id, url = %hook.url, "webhook disabled, circuit open for a day");
+            }
+        }
+        hook.disabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "http://127.0.0.1:9/hook";
+
+    // the same as a receiver's
+    //   hmac.new(secret.encode(), body, hashlib.sha256).hexdigest()
+    // in Python, with GitHub's documented example secret and body
+    #[test]
+    fn signature_matches_python_hmac() {
+        assert_eq!(
+            SigningKey::new("It's a Secret to Everybody").sign(b"Hello, World!"),
+            "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+    }
+
+    #[test]
+    fn secret_is_not_printed() {
+        let hook = Webhook {
+            id: Uuid::nil(),
+            url: URL.to_string(),
+            events: vec![WebhookEvent::MatchCreated],
+            key: SigningKey::new("hunter2"),
+            disabled: false,
+        };
+        assert!(!format!("{hook:?}").contains("hunter2"));
+        assert!(!serde_json::to_string(&hook).unwrap().contains("hunter2"));
+    }
+
+    #[test]
+    fn circuit_opens_half_opens_and_closes() {
+        let circuits = Circuits::new(Duration::from_millis(50));
+        for _ in 1..CIRCUIT_FAILURE_THRESHOLD {
+            assert!(circuits.allow(URL));
+            circuits.failure(URL);
+        }
+        assert_eq!(circuits.status(URL).state, CircuitState::Closed);
+
+        circuits.failure(URL);
+        let opened = circuits.status(URL);
+        assert_eq!(opened.state, CircuitState::Open);
+        assert!(!circuits.allow(URL));
+
+        // one trial at a time; a failed one keeps the original open_since
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(circuits.status(URL).state, CircuitState::HalfOpen);
+        assert!(circuits.allow(URL));
+        assert!(!circuits.allow(URL));
+        circuits.failure(URL);
+        let reopened = circuits.status(URL);
+        assert_eq!(reopened.state, CircuitState::Open);
+        assert_eq!(reopened.open_since, opened.open_since);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(circuits.allow(URL));
+        circuits.success(URL);
+        let closed = circuits.status(URL);
+        assert_eq!(closed.state, CircuitState::Closed);
+        assert!(closed.open_since.is_none());
+    }
+
+    #[test]
+    fn day_long_open_circuit_disables_the_webhook() {
+        let circuits = Circuits::new(Duration::from_secs(60));
+        let mut hook = Webhook {
+            id: Uuid::new_v4(),
+            url: URL.to_string(),
+            events: vec![WebhookEvent::MatchCreated],
+            key: SigningKey::new("secret"),
+            disabled: false,
+        };
+        for _ in 0..CIRCUIT_FAILURE_THRESHOLD {
+            circuits.failure(URL);
+        }
+        assert!(!circuits.disable_if_stuck(&mut hook));
+
+        let day_ago = Utc::now() - disable_after();
+        circuits
+            .circuits
+            .lock()
+            .unwrap()
+            .get_mut(URL)
+            .unwrap()
+            .open_since = Some(day_ago);
+        assert!(circuits.disable_if_stuck(&mut hook));
+        assert!(hook.disabled);
+    }
+}
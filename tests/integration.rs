@@ -0,0 +1,411 @@
+// End to end tests against the real HTTP app, served on an ephemeral port and
+// driven over the network with reqwest.
+
+use std::{net::SocketAddr, time::Duration};
+
+use matchmaker::config::Config;
+use reqwest::{Client, Method, StatusCode};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const SECRET: &str = "integration-test-secret";
+// so random matching strategies pick the same way on every run
+const SEED: u64 = 79;
+
+// one server per test, aborted when the test ends, passed or not
+struct TestServer {
+    base: String,
+    client: Client,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl TestServer {
+    async fn start(config: Config) -> TestServer {
+        let config = Config {
+            jwt_secret: Some(SECRET.to_string()),
+            ..config
+        };
+        let app = matchmaker::seeded_router(config, SEED);
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        let base = format!("http://{}/v1", server.local_addr());
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        TestServer {
+            base,
+            client: Client::new(),
+            handle,
+        }
+    }
+
+    async fn get(&self, path: &str) -> (StatusCode, Value) {
+        let res = self
+            .client
+            .get(format!("{}{path}", self.base))
+            .send()
+            .await
+            .unwrap();
+        (res.status(), res.json().await.unwrap_or(Value::Null))
+    }
+
+    // a write signed as `player`
+    async fn post(&self, path: &str, player: Uuid, body: Value) -> (StatusCode, Value) {
+        self.send(Method::POST, path, player, body).await
+    }
+
+    async fn send(&self, method: Method, path: &str, player: Uuid, body: Value) -> (StatusCode, Value) {
+        let res = self
+            .client
+            .request(method, format!("{}{path}", self.base))
+            .bearer_auth(token(player))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        (res.status(), res.json().await.unwrap_or(Value::Null))
+    }
+
+    async fn create_profile(&self, name: &str) -> Uuid {
+        let (status, body) = self
+            .post("/profiles", Uuid::nil(), json!({ "name": name }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        body["id"].as_str().unwrap().parse().unwrap()
+    }
+
+    async fn enqueue(&self, player: Uuid) -> (StatusCode, Value) {
+        self.post("/queue/enqueue", player, json!({ "profile_id": player }))
+            .await
+    }
+
+    // the match the background matcher made for `player`, waiting up to a
+    // few dozen of its passes
+    async fn matched(&self, player: Uuid) -> Value {
+        for _ in 0..50 {
+            let (_, history) = self.get(&format!("/profiles/{player}/matches")).await;
+            if let Some(m) = history.as_array().and_then(|ms| ms.first()) {
+                return m.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("{player} was not matched");
+    }
+}
+
+fn token(player: Uuid) -> String {
+    let claims = json!({ "sub": player, "exp": chrono::Utc::now().timestamp() + 3600 });
+    let key = jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes());
+    jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+}
+
+#[tokio::test]
+async fn queued_players_play_and_are_rated() {
+    let server = TestServer::start(Config::default()).await;
+    let alice = server.create_profile("alice").await;
+    let bob = server.create_profile("bob").await;
+
+    for player in [alice, bob] {
+        let (status, _) = server.enqueue(player).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+    // alice has waited longest, so the pass looks for her opponent first
+    // and she joins bob's match as player2
+    let m = server.matched(alice).await;
+    assert_eq!(m["player1"], bob.to_string());
+    assert_eq!(m["player2"], alice.to_string());
+    assert_eq!(m["status"], "Pending");
+    let match_id = m["id"].as_str().unwrap().to_string();
+
+    for player in [alice, bob] {
+        let path = format!("/matches/{match_id}/ready");
+        let (status, _) = server.post(&path, player, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (_, m) = server.get(&format!("/matches/{match_id}")).await;
+    assert_eq!(m["status"], "Active");
+
+    // the result stands once both sides reported it
+    let path = format!("/matches/{match_id}/result");
+    let (status, m) = server
+        .post(&path, alice, json!({ "winner": "player2" }))
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(m["status"], "Active");
+    let (status, m) = server
+        .post(&path, bob, json!({ "winner": "player2" }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(m["status"], "Completed");
+    assert_eq!(m["result"], "Player2Win");
+
+    let (_, winner) = server.get(&format!("/profiles/{alice}")).await;
+    let (_, loser) = server.get(&format!("/profiles/{bob}")).await;
+    assert!(winner["ranked_mmr"].as_u64().unwrap() > 1000);
+    assert!(loser["ranked_mmr"].as_u64().unwrap() < 1000);
+    assert_eq!(winner["wins"], 1);
+    assert_eq!(loser["losses"], 1);
+
+    let (status, history) = server.get(&format!("/profiles/{alice}/matches")).await;
+    assert_eq!(status, StatusCode::OK);
+    let ids: Vec<&str> = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, [match_id.as_str()]);
+}
+
+#[tokio::test]
+async fn unknown_ids_are_not_found() {
+    let server = TestServer::start(Config::default()).await;
+
+    let (status, body) = server.get(&format!("/profiles/{}", Uuid::new_v4())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "PROFILE_NOT_FOUND");
+
+    let (status, body) = server.get(&format!("/matches/{}", Uuid::new_v4())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "MATCH_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn conflicts_are_rejected() {
+    let server = TestServer::start(Config::default()).await;
+    server.create_profile("alice").await;
+
+    let (status, body) = server
+        .post("/profiles", Uuid::nil(), json!({ "name": "ALICE" }))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "NAME_TAKEN");
+
+    // a pending match cannot be completed before it starts
+    let bob = server.create_profile("bob").await;
+    let carol = server.create_profile("carol").await;
+    server.enqueue(bob).await;
+    server.enqueue(carol).await;
+    let m = server.matched(bob).await;
+    let path = format!("/matches/{}/result", m["id"].as_str().unwrap());
+    let (status, body) = server.post(&path, bob, json!({ "winner": "draw" })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "INVALID_TRANSITION");
+}
+
+#[tokio::test]
+async fn enqueue_is_rate_limited_per_address() {
+    let config = Config {
+        enqueue_rate_limit: 2,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let alice = server.create_profile("alice").await;
+
+    // re-queueing an already queued player still counts against the budget
+    for _ in 0..2 {
+        let (status, _) = server.enqueue(alice).await;
+        assert!(status.is_success(), "{status}");
+    }
+    let (status, body) = server.enqueue(alice).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["code"], "RATE_LIMITED");
+}
+
+#[tokio::test]
+async fn queue_replies_say_where_the_entry_waits() {
+    let server = TestServer::start(Config::default()).await;
+    let alice = server.create_profile("alice").await;
+    let (status, _) = server.enqueue(alice).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (status, body) = server.enqueue(alice).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mode"], "RankedSolo");
+    assert_eq!(body["position"], 1);
+    let (status, body) = server
+        .post("/queue/heartbeat", alice, json!({ "profile_id": alice }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["position"], 1);
+    let (status, body) = server
+        .post("/queue/leave", alice, json!({ "profile_id": alice }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["removed_from"], json!(["RankedSolo"]));
+}
+
+#[tokio::test]
+async fn players_cannot_act_for_others() {
+    let server = TestServer::start(Config::default()).await;
+    let alice = server.create_profile("alice").await;
+    let mallory = server.create_profile("mallory").await;
+
+    let profile = format!("/profiles/{alice}");
+    let rename = json!({ "name": "pwned" });
+    let (status, body) = server
+        .send(Method::PATCH, &profile, mallory, rename.clone())
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "PROFILE_MISMATCH");
+    let (status, _) = server.send(Method::PATCH, &profile, alice, rename).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = server
+        .send(Method::DELETE, &profile, mallory, Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, body) = server.get(&profile).await;
+    assert_eq!(body["name"], "pwned");
+
+    let bob = server.create_profile("bob").await;
+    server.enqueue(alice).await;
+    server.enqueue(bob).await;
+    let m = server.matched(alice).await;
+    let match_path = format!("/matches/{}", m["id"].as_str().unwrap());
+    let ready = format!("{match_path}/ready");
+    let (status, body) = server.post(&ready, mallory, Value::Null).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "NOT_MATCH_PARTICIPANT");
+    let (_, m) = server.get(&match_path).await;
+    assert_eq!(m["ready_player1"], false);
+    assert_eq!(m["ready_player2"], false);
+    let (status, body) = server
+        .post(&format!("{match_path}/start"), alice, Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "READY_CHECK_PENDING");
+    let cancel = format!("{match_path}/cancel");
+    let (status, _) = server.post(&cancel, mallory, Value::Null).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, m) = server.get(&match_path).await;
+    assert_eq!(m["status"], "Pending");
+    let carol = server.create_profile("carol").await;
+    server.enqueue(carol).await;
+    let (status, body) = server
+        .post("/queue/heartbeat", mallory, json!({ "profile_id": carol }))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "PROFILE_MISMATCH");
+    let (status, _) = server
+        .post("/queue/leave", mallory, json!({ "profile_id": carol }))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = server.get(&format!("/queue/position/{carol}")).await;
+    assert_eq!(status, StatusCode::OK);
+    let result = format!("{match_path}/result");
+    let (status, body) = server
+        .post(&result, mallory, json!({ "winner": "player2" }))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "NOT_MATCH_PARTICIPANT");
+
+    // spectators are whoever signed the request, whatever the body says
+    for player in [alice, bob] {
+        server.post(&ready, player, Value::Null).await;
+    }
+    let spectate = format!("{match_path}/spectate");
+    let (status, m) = server
+        .post(&spectate, mallory, json!({ "profile_id": carol }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(m["spectators"], json!([mallory]));
+    let (status, _) = server
+        .send(Method::DELETE, &format!("{spectate}/{mallory}"), carol, Value::Null)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn operator_actions_need_the_admin_key() {
+    let server = TestServer::start(Config::default()).await;
+    let alice = server.create_profile("alice").await;
+
+    // the signed-in player is still not an operator
+    let profile = format!("/profiles/{alice}");
+    let (status, body) = server
+        .send(Method::PATCH, &profile, alice, json!({ "name": "alice2", "ranked_mmr": 3000 }))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "MMR_CHANGE_FORBIDDEN");
+    let (_, body) = server.get(&profile).await;
+    assert_eq!((&body["name"], &body["ranked_mmr"]), (&json!("alice"), &json!(1000)));
+    let hook = json!({ "url": "https://example.com/hook", "events": ["match.completed"], "secret": "s" });
+    let (status, _) = server.post("/webhooks", alice, hook).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = server.get("/webhooks").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "INVALID_ADMIN_KEY");
+}
+
+#[tokio::test]
+async fn disputed_results_wait_for_the_admin_key() {
+    let config = Config {
+        admin_key: Some("ops-key".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let alice = server.create_profile("alice").await;
+    let bob = server.create_profile("bob").await;
+    server.enqueue(alice).await;
+    server.enqueue(bob).await;
+    let m = server.matched(alice).await;
+    let match_path = format!("/matches/{}", m["id"].as_str().unwrap());
+    for player in [alice, bob] {
+        server
+            .post(&format!("{match_path}/ready"), player, Value::Null)
+            .await;
+    }
+
+    let result = format!("{match_path}/result");
+    server.post(&result, alice, json!({ "winner": "player1" })).await;
+    let (status, m) = server.post(&result, bob, json!({ "winner": "player2" })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!((&m["status"], &m["disputed"]), (&json!("Active"), &json!(true)));
+    let (_, profile) = server.get(&format!("/profiles/{alice}")).await;
+    assert_eq!(profile["ranked_mmr"], 1000);
+
+    let res = server
+        .client
+        .post(format!("{}{result}", server.base))
+        .header("X-Admin-Key", "ops-key")
+        .json(&json!({ "winner": "draw" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let m: Value = res.json().await.unwrap();
+    assert_eq!((&m["status"], &m["result"]), (&json!("Completed"), &json!("Draw")));
+    assert_eq!(m["disputed"], false);
+}
+
+#[tokio::test]
+async fn parties_are_run_by_their_leader() {
+    let server = TestServer::start(Config::default()).await;
+    let alice = server.create_profile("alice").await;
+    let bob = server.create_profile("bob").await;
+    let mallory = server.create_profile("mallory").await;
+
+    let members = json!({ "members": [bob, alice] });
+    let (status, body) = server.post("/parties", mallory, members.clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "NOT_PARTY_MEMBER");
+    let (status, party) = server.post("/parties", alice, members).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(party["leader"], json!(alice));
+
+    let path = format!("/parties/{}", party["id"].as_str().unwrap());
+    let (status, body) = server.send(Method::DELETE, &path, bob, Value::Null).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "NOT_PARTY_LEADER");
+    let (status, _) = server.send(Method::DELETE, &path, alice, Value::Null).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = server.get(&path).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}